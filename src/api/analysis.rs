@@ -0,0 +1,38 @@
+use axum::{Json, extract::State};
+use serde_json::Value;
+
+use crate::{
+    AppState,
+    api::responses::{AnalyzeStatusResponse, HarmonyResponse},
+};
+
+pub async fn api_start_analysis(State(state): State<AppState>) -> Json<Value> {
+    let status = state
+        .analyzer
+        .start(state.scanner.library(), &state.db)
+        .map_err(|e| e.to_string());
+
+    Json(
+        serde_json::to_value(AnalyzeStatusResponse {
+            harmony: HarmonyResponse {
+                status,
+                with_license: false,
+            },
+            analyze_status: state.analyzer.status(),
+        })
+        .unwrap(),
+    )
+}
+
+pub async fn api_get_analysis_status(State(state): State<AppState>) -> Json<Value> {
+    Json(
+        serde_json::to_value(AnalyzeStatusResponse {
+            harmony: HarmonyResponse {
+                status: Ok(()),
+                with_license: false,
+            },
+            analyze_status: state.analyzer.status(),
+        })
+        .unwrap(),
+    )
+}
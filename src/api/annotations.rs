@@ -0,0 +1,191 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    api::responses::{AnnotationListResponse, AnnotationResponse, HarmonyResponse},
+    db::annotation,
+    library::annotation::{
+        AnnotationExportFormat, annotation_create, annotation_delete, annotation_export,
+        annotation_get_list, annotation_search, annotation_update,
+    },
+};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateAnnotationParameters {
+    u: String,
+    id: Uuid,
+    cfi_range: String,
+    text: String,
+    color: Option<String>,
+    note: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateAnnotationParameters {
+    u: String,
+    id: Uuid,
+    color: Option<String>,
+    note: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AnnotationParameters {
+    u: String,
+    id: Uuid,
+}
+
+#[derive(Deserialize)]
+pub struct ExportAnnotationParameters {
+    u: String,
+    id: Uuid,
+    format: Option<AnnotationExportFormat>,
+}
+
+#[derive(Deserialize)]
+pub struct SearchAnnotationParameters {
+    u: String,
+    query: String,
+    id: Option<Uuid>,
+    size: Option<u32>,
+}
+
+fn annotation_response(result: anyhow::Result<annotation::Model>) -> Json<Value> {
+    let (status, annotation) = match result {
+        Ok(a) => (Ok(()), Some(a)),
+        Err(e) => (Err(e.to_string()), None),
+    };
+    Json(
+        serde_json::to_value(AnnotationResponse {
+            harmony: HarmonyResponse {
+                status,
+                with_license: false,
+            },
+            annotation,
+        })
+        .unwrap(),
+    )
+}
+
+fn annotation_list_response(result: anyhow::Result<Vec<annotation::Model>>) -> Json<Value> {
+    let (status, annotations) = match result {
+        Ok(a) => (Ok(()), a),
+        Err(e) => (Err(e.to_string()), Vec::new()),
+    };
+    Json(
+        serde_json::to_value(AnnotationListResponse {
+            harmony: HarmonyResponse {
+                status,
+                with_license: false,
+            },
+            annotations,
+        })
+        .unwrap(),
+    )
+}
+
+/// Highlights a CFI range of a book, with an optional note on it.
+pub async fn api_create_annotation(
+    State(state): State<AppState>,
+    Query(params): Query<CreateAnnotationParameters>,
+) -> Json<Value> {
+    annotation_response(
+        annotation_create(
+            &params.u,
+            params.id,
+            params.cfi_range,
+            params.text,
+            params.color,
+            params.note,
+            &state.db,
+        )
+        .await,
+    )
+}
+
+/// Changes the color or note of an annotation, where an empty note removes it.
+pub async fn api_update_annotation(
+    State(state): State<AppState>,
+    Query(params): Query<UpdateAnnotationParameters>,
+) -> Json<Value> {
+    annotation_response(
+        annotation_update(&params.u, params.id, params.color, params.note, &state.db).await,
+    )
+}
+
+pub async fn api_delete_annotation(
+    State(state): State<AppState>,
+    Query(params): Query<AnnotationParameters>,
+) -> Json<Value> {
+    Json(
+        serde_json::to_value(HarmonyResponse {
+            status: annotation_delete(&params.u, params.id, &state.db)
+                .await
+                .map_err(|e| e.to_string()),
+            with_license: false,
+        })
+        .unwrap(),
+    )
+}
+
+/// Lists the annotations of the user in a book, in reading order.
+pub async fn api_get_annotations(
+    State(state): State<AppState>,
+    Query(params): Query<AnnotationParameters>,
+) -> Json<Value> {
+    annotation_list_response(annotation_get_list(&params.u, params.id, &state.db).await)
+}
+
+/// Searches the notes and highlighted text of the annotations of the user for the words of the
+/// query.
+pub async fn api_search_annotations(
+    State(state): State<AppState>,
+    Query(params): Query<SearchAnnotationParameters>,
+) -> Json<Value> {
+    // default length is 50
+    let len = params.size.unwrap_or(50);
+    annotation_list_response(
+        annotation_search(&params.u, &params.query, params.id, len, &state.db).await,
+    )
+}
+
+/// Downloads the annotations of the user in a book as Markdown or JSON.
+pub async fn api_export_annotations(
+    State(state): State<AppState>,
+    Query(params): Query<ExportAnnotationParameters>,
+) -> Response {
+    let format = params.format.unwrap_or_default();
+    match annotation_export(&params.u, params.id, format, &state.db).await {
+        Ok((document, content_type)) => {
+            let extension = match format {
+                AnnotationExportFormat::Markdown => "md",
+                AnnotationExportFormat::Json => "json",
+            };
+            let disposition = format!("attachment; filename=\"annotations.{}\"", extension);
+            (
+                [
+                    (header::CONTENT_TYPE, content_type.to_owned()),
+                    (header::CONTENT_DISPOSITION, disposition),
+                ],
+                document,
+            )
+                .into_response()
+        }
+        Err(e) => Json(
+            serde_json::to_value(HarmonyResponse {
+                status: Err(e.to_string()),
+                with_license: false,
+            })
+            .unwrap(),
+        )
+        .into_response(),
+    }
+}
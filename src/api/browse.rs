@@ -2,22 +2,33 @@ use axum::{
     Json,
     extract::{Query, State},
 };
+use sea_orm::Order;
 use serde::Deserialize;
 use serde_json::Value;
 use uuid::Uuid;
 
 use crate::{
     AppState,
-    api::responses::{AlbumListResponse, AlbumResponse, HarmonyResponse, TrackResponse},
+    api::responses::{
+        AlbumListResponse, AlbumResponse, HarmonyResponse, LyricsList, LyricsResponse, PlainLyrics,
+        TrackResponse,
+    },
     library::{
         album::{album_get_by_id, album_get_newest_list, album_get_random_list},
         artist::{artist_get_by_id, artist_get_list},
-        book::{book_get_by_id, book_get_list},
+        book::{
+            BookListFilter, BookListSort, book_get_authors, book_get_by_id, book_get_list,
+            book_get_series, book_get_series_list,
+        },
+        lyrics::{lyrics_get_by_track, lyrics_plain},
         track::track_get_by_id,
     },
 };
 
-use super::responses::{ArtistListResponse, ArtistResponse, BookListResponse, BookResponse};
+use super::responses::{
+    ArtistListResponse, ArtistResponse, AuthorListResponse, BookListResponse, BookResponse,
+    BookSeriesListResponse, BookSeriesResponse,
+};
 
 /* -------------------------------------------------------------------------------------------
     MUSIC BROWSING
@@ -200,14 +211,66 @@ pub async fn api_get_track(
     }
 }
 
+pub async fn api_get_lyrics_by_song_id(
+    State(state): State<AppState>,
+    Query(params): Query<TrackParameters>,
+) -> Json<Value> {
+    let lyrics = match track_get_by_id(params.id, &state.db).await {
+        Ok(t) => lyrics_get_by_track(t.id, state.db.as_ref())
+            .await
+            .map(|l| (t, l)),
+        Err(e) => Err(e),
+    };
+    match lyrics {
+        Ok((t, structured_lyrics)) => {
+            // plain lyrics in the shape of the older getLyrics endpoint
+            let plain = lyrics_plain(&structured_lyrics).map(|value| PlainLyrics {
+                artist: t.artists.iter().next().map(|a| a.name.clone()),
+                title: t.title.clone(),
+                value,
+            });
+            Json(
+                serde_json::to_value(LyricsResponse {
+                    harmony: HarmonyResponse {
+                        status: Ok(()),
+                        with_license: false,
+                    },
+                    lyrics_list: Some(LyricsList { structured_lyrics }),
+                    lyrics: plain,
+                })
+                .unwrap(),
+            )
+        }
+        Err(e) => Json(
+            serde_json::to_value(LyricsResponse {
+                harmony: HarmonyResponse {
+                    status: Err(e.to_string()),
+                    with_license: false,
+                },
+                lyrics_list: None,
+                lyrics: None,
+            })
+            .unwrap(),
+        ),
+    }
+}
+
 /* ------------------------------------------------------------------------------------------
     BOOK BROWSING
 ------------------------------------------------------------------------------------------ */
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct BookListParameters {
     size: Option<u32>,
     _offset: Option<u32>,
+    sort: Option<BookListSort>,
+    descending: Option<bool>,
+    language: Option<String>,
+    publisher: Option<String>,
+    subject: Option<String>,
+    series: Option<String>,
+    identifier: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -215,6 +278,17 @@ pub struct BookParameters {
     id: Uuid,
 }
 
+#[derive(Deserialize)]
+pub struct BookSeriesParameters {
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct AuthorListParameters {
+    size: Option<u32>,
+    _offset: Option<u32>,
+}
+
 pub async fn api_get_books(
     State(state): State<AppState>,
     Query(params): Query<BookListParameters>,
@@ -225,13 +299,39 @@ pub async fn api_get_books(
         len = l;
     }
 
+    // filter and sort the books as requested, by title if no sort is given
+    let filter = BookListFilter {
+        language: params.language,
+        publisher: params.publisher,
+        subject: params.subject,
+        series: params.series,
+        identifier: params.identifier,
+        ..Default::default()
+    };
+    let order = if params.descending.unwrap_or(false) {
+        Order::Desc
+    } else {
+        Order::Asc
+    };
+    let (status, books) = match book_get_list(
+        len,
+        &filter,
+        params.sort.unwrap_or_default(),
+        order,
+        &state.db,
+    )
+    .await
+    {
+        Ok(books) => (Ok(()), books),
+        Err(e) => (Err(e.to_string()), Vec::new()),
+    };
     Json(
         serde_json::to_value(BookListResponse {
             harmony: HarmonyResponse {
-                status: Ok(()),
+                status,
                 with_license: false,
             },
-            books: book_get_list(len, &state.db).await,
+            books,
         })
         .unwrap(),
     )
@@ -264,3 +364,66 @@ pub async fn api_get_book(
         ),
     }
 }
+
+pub async fn api_get_book_series(State(state): State<AppState>) -> Json<Value> {
+    let (status, series) = match book_get_series_list(&state.db).await {
+        Ok(series) => (Ok(()), series),
+        Err(e) => (Err(e.to_string()), Vec::new()),
+    };
+    Json(
+        serde_json::to_value(BookSeriesListResponse {
+            harmony: HarmonyResponse {
+                status,
+                with_license: false,
+            },
+            series,
+        })
+        .unwrap(),
+    )
+}
+
+pub async fn api_get_book_series_detail(
+    State(state): State<AppState>,
+    Query(params): Query<BookSeriesParameters>,
+) -> Json<Value> {
+    let (status, series) = match book_get_series(&params.name, &state.db).await {
+        Ok(series) => (Ok(()), Some(series)),
+        Err(e) => (Err(e.to_string()), None),
+    };
+    Json(
+        serde_json::to_value(BookSeriesResponse {
+            harmony: HarmonyResponse {
+                status,
+                with_license: false,
+            },
+            series,
+        })
+        .unwrap(),
+    )
+}
+
+pub async fn api_get_authors(
+    State(state): State<AppState>,
+    Query(params): Query<AuthorListParameters>,
+) -> Json<Value> {
+    // default length is 10
+    let mut len = 10;
+    if let Some(l) = params.size {
+        len = l;
+    }
+
+    let (status, authors) = match book_get_authors(len, &state.db).await {
+        Ok(authors) => (Ok(()), authors),
+        Err(e) => (Err(e.to_string()), Vec::new()),
+    };
+    Json(
+        serde_json::to_value(AuthorListResponse {
+            harmony: HarmonyResponse {
+                status,
+                with_license: false,
+            },
+            authors,
+        })
+        .unwrap(),
+    )
+}
@@ -0,0 +1,159 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    AppState,
+    api::responses::{
+        KoreaderAuthResponse, KoreaderErrorResponse, KoreaderSaveResponse, KoreaderUserResponse,
+    },
+    auth::users::auth_check_user,
+    library::{
+        koreader::{KoreaderProgress, koreader_get_progress, koreader_save_progress},
+        shelf::get_user_id,
+    },
+};
+
+#[derive(Deserialize)]
+pub struct KoreaderUserParameters {
+    username: String,
+    /// The MD5 digest of the password, which KOReader sends instead of the password.
+    password: String,
+}
+
+/// Builds an error response in the format of the KOReader sync server.
+fn koreader_error(status: StatusCode, code: u32, message: &str) -> Response {
+    let response = KoreaderErrorResponse {
+        code,
+        message: message.to_owned(),
+    };
+    (status, Json(response)).into_response()
+}
+
+/// Checks the credentials KOReader sends in the x-auth-user and x-auth-key headers, where the
+/// key is the MD5 digest of the password. Returns the username of the user.
+async fn koreader_authorize(headers: &HeaderMap, state: &AppState) -> Result<String, Response> {
+    let unauthorized = || koreader_error(StatusCode::UNAUTHORIZED, 2001, "Unauthorized");
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_owned())
+    };
+    let (Some(username), Some(key)) = (header("x-auth-user"), header("x-auth-key")) else {
+        return Err(unauthorized());
+    };
+
+    // the key is the token of a password with an empty salt
+    if let Err(e) = auth_check_user(
+        &username,
+        &key.to_lowercase(),
+        "",
+        &state.settings.key,
+        &state.db,
+        false,
+    )
+    .await
+    {
+        println!("{}", e);
+        return Err(unauthorized());
+    }
+    Ok(username)
+}
+
+/// Registers a KOReader user. Harmony users can't be made from a password digest, so this only
+/// succeeds for the credentials of an existing user, which lets them register a device.
+pub async fn api_koreader_create_user(
+    State(state): State<AppState>,
+    Json(params): Json<KoreaderUserParameters>,
+) -> Response {
+    if auth_check_user(
+        &params.username,
+        &params.password.to_lowercase(),
+        "",
+        &state.settings.key,
+        &state.db,
+        false,
+    )
+    .await
+    .is_ok()
+    {
+        let response = KoreaderUserResponse {
+            username: params.username,
+        };
+        return (StatusCode::CREATED, Json(response)).into_response();
+    }
+    if get_user_id(&params.username, &state.db).await.is_ok() {
+        koreader_error(
+            StatusCode::PAYMENT_REQUIRED,
+            2002,
+            "Username is already registered.",
+        )
+    } else {
+        koreader_error(
+            StatusCode::PAYMENT_REQUIRED,
+            2005,
+            "User registration is disabled, create the user in Harmony.",
+        )
+    }
+}
+
+/// Checks the credentials of a KOReader user.
+pub async fn api_koreader_auth(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    match koreader_authorize(&headers, &state).await {
+        Ok(_) => {
+            let response = KoreaderAuthResponse {
+                authorized: "OK".to_owned(),
+            };
+            Json(response).into_response()
+        }
+        Err(r) => r,
+    }
+}
+
+/// Saves the position KOReader reports in a document, which becomes the reading progress of the
+/// book with the same partial MD5 digest.
+pub async fn api_koreader_update_progress(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(progress): Json<KoreaderProgress>,
+) -> Response {
+    let username = match koreader_authorize(&headers, &state).await {
+        Ok(u) => u,
+        Err(r) => return r,
+    };
+    let document = progress.document.clone();
+    match koreader_save_progress(&username, progress, &state.db).await {
+        Ok(timestamp) => {
+            let response = KoreaderSaveResponse {
+                document,
+                timestamp,
+            };
+            Json(response).into_response()
+        }
+        Err(e) => koreader_error(StatusCode::FORBIDDEN, 2003, &e.to_string()),
+    }
+}
+
+/// Gets the position of the user in a document, or an empty object if there is none.
+pub async fn api_koreader_get_progress(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(document): Path<String>,
+) -> Response {
+    let username = match koreader_authorize(&headers, &state).await {
+        Ok(u) => u,
+        Err(r) => return r,
+    };
+
+    // unknown documents have no progress rather than being an error
+    match koreader_get_progress(&username, &document, &state.db).await {
+        Ok(Some(progress)) => Json(progress).into_response(),
+        _ => Json(Value::Object(Map::new())).into_response(),
+    }
+}
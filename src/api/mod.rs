@@ -1,7 +1,15 @@
+pub mod analysis;
+pub mod annotations;
 pub mod browse;
+pub mod koreader;
+pub mod opds;
+pub mod reading;
 pub mod responses;
 pub mod retrieve;
+pub mod scanning;
 pub mod shelf;
 pub mod system;
+pub mod tags;
 pub mod upload;
 pub mod users;
+pub mod verification;
@@ -0,0 +1,603 @@
+use axum::{
+    Extension, Router,
+    body::Body,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use chrono::{DateTime, Utc};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use sea_orm::Order;
+use serde::Deserialize;
+use tokio::fs::File;
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    auth::auth::auth_decode_basic,
+    db::book,
+    format::{
+        flac::parse_picture_media_type,
+        opds::{
+            OpdsAuthor, OpdsFeed, OpdsFeedKind, OpdsNavigationEntry, OpdsPagination,
+            OpdsPublication, write_opds_atom, write_opds_json, write_opensearch_description,
+        },
+    },
+    library::{
+        artist::artist_get_by_id,
+        book::{
+            BookListFilter, BookListSort, book_get_authors, book_get_by_id, book_get_languages,
+            book_get_page, book_get_series_list,
+        },
+        shelf::get_user_id,
+    },
+};
+
+const OPDS_PAGE_SIZE: u64 = 50;
+
+/// The last page that can be requested, far beyond any library, so that the offset of a page
+/// cannot overflow.
+const OPDS_MAX_PAGE: u64 = u32::MAX as u64;
+
+/// The versions of OPDS the catalog is served in.
+#[derive(Clone, Copy)]
+pub enum OpdsVersion {
+    /// OPDS 1.2, as Atom feeds under /opds.
+    Atom,
+    /// OPDS 2.0, as JSON feeds under /opds/v2.
+    Json,
+}
+
+impl OpdsVersion {
+    fn base(self) -> &'static str {
+        match self {
+            OpdsVersion::Atom => "/opds",
+            OpdsVersion::Json => "/opds/v2",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct OpdsParameters {
+    /// The page of the feed, numbered from one.
+    page: Option<u64>,
+    q: Option<String>,
+}
+
+impl OpdsParameters {
+    fn page(&self) -> u64 {
+        self.page.unwrap_or(1).clamp(1, OPDS_MAX_PAGE) - 1
+    }
+}
+
+/// Returns the routes of the catalog in the given version of OPDS, to be nested under its base
+/// path.
+pub fn opds_router(version: OpdsVersion) -> Router<AppState> {
+    Router::new()
+        .route("/", get(api_opds_root))
+        .route("/books", get(api_opds_books))
+        .route("/recent", get(api_opds_recent))
+        .route("/starred", get(api_opds_starred))
+        .route("/authors", get(api_opds_authors))
+        .route("/authors/{id}", get(api_opds_author))
+        .route("/series", get(api_opds_series_list))
+        .route("/series/{name}", get(api_opds_series))
+        .route("/languages", get(api_opds_languages))
+        .route("/languages/{language}", get(api_opds_language))
+        .route("/search", get(api_opds_search))
+        .route("/opensearch.xml", get(api_opds_opensearch))
+        .route("/books/{id}/file", get(api_opds_book_file))
+        .route("/covers/{id}", get(api_opds_cover))
+        .layer(Extension(version))
+}
+
+/// Writes a feed in the requested version of OPDS.
+fn opds_response(version: OpdsVersion, feed: anyhow::Result<OpdsFeed>) -> Response {
+    let feed = match feed {
+        Ok(f) => f,
+        Err(e) => {
+            println!("{}", e);
+            return StatusCode::NOT_FOUND.into_response();
+        }
+    };
+    let (body, content_type) = match version {
+        OpdsVersion::Atom => {
+            let content_type = match feed.kind {
+                OpdsFeedKind::Navigation => {
+                    "application/atom+xml;profile=opds-catalog;kind=navigation"
+                }
+                OpdsFeedKind::Acquisition => {
+                    "application/atom+xml;profile=opds-catalog;kind=acquisition"
+                }
+            };
+            (write_opds_atom(&feed, version.base()), content_type)
+        }
+        OpdsVersion::Json => match write_opds_json(&feed, version.base()) {
+            Ok(json) => (json, "application/opds+json"),
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        },
+    };
+    ([(header::CONTENT_TYPE, content_type)], body).into_response()
+}
+
+/// Converts a book to a publication of an acquisition feed.
+fn opds_publication(book: book::ModelEx, updated: DateTime<Utc>) -> OpdsPublication {
+    let mut identifiers = Vec::new();
+    if let Some(isbn) = &book.isbn {
+        identifiers.push(format!("urn:isbn:{}", isbn));
+    }
+    if let Some(asin) = &book.asin {
+        identifiers.push(format!("urn:asin:{}", asin));
+    }
+    if let Some(uuid) = &book.uuid {
+        identifiers.push(format!("urn:uuid:{}", uuid));
+    }
+    OpdsPublication {
+        id: format!("urn:uuid:{}", book.id),
+        authors: book
+            .artists
+            .iter()
+            .map(|a| OpdsAuthor {
+                name: a.name.clone(),
+                href: format!("/authors/{}", a.id),
+            })
+            .collect(),
+        subjects: book.subjects.iter().map(|s| s.subject.clone()).collect(),
+        cover: book.picture.as_ref().map(|p| {
+            (
+                format!("/opds/covers/{}", book.id),
+                parse_picture_media_type(p),
+            )
+        }),
+        acquisition_href: format!("/opds/books/{}/file", book.id),
+        updated: book.added_at.unwrap_or(updated),
+        title: book.title,
+        language: book.language,
+        publisher: book.publisher,
+        published: book.publication_date,
+        description: book.description,
+        identifiers,
+        series: book.series,
+        series_index: book.series_index,
+    }
+}
+
+/// Builds a paginated acquisition feed of the books matching the filter. Recently added books
+/// come first, and books sorted by anything else are in ascending order.
+async fn opds_acquisition_feed(
+    id: String,
+    title: String,
+    href: String,
+    filter: BookListFilter,
+    sort: BookListSort,
+    params: &OpdsParameters,
+    state: &AppState,
+) -> anyhow::Result<OpdsFeed> {
+    let order = match sort {
+        BookListSort::Added => Order::Desc,
+        _ => Order::Asc,
+    };
+    let page = params.page();
+    let (books, total) =
+        book_get_page(page, OPDS_PAGE_SIZE, &filter, sort, order, &state.db).await?;
+    let updated = Utc::now();
+    Ok(OpdsFeed {
+        id,
+        title,
+        href,
+        kind: OpdsFeedKind::Acquisition,
+        updated,
+        navigation: Vec::new(),
+        publications: books
+            .into_iter()
+            .map(|b| opds_publication(b, updated))
+            .collect(),
+        pagination: Some(OpdsPagination {
+            page,
+            page_size: OPDS_PAGE_SIZE,
+            total,
+        }),
+        query: None,
+    })
+}
+
+/// Builds a navigation feed from its entries, showing the requested page of them.
+fn opds_navigation_feed(
+    id: &str,
+    title: &str,
+    href: &str,
+    entries: Vec<OpdsNavigationEntry>,
+    params: &OpdsParameters,
+) -> OpdsFeed {
+    let page = params.page();
+    let total = entries.len() as u64;
+    OpdsFeed {
+        id: id.to_owned(),
+        title: title.to_owned(),
+        href: href.to_owned(),
+        kind: OpdsFeedKind::Navigation,
+        updated: Utc::now(),
+        navigation: entries
+            .into_iter()
+            .skip((page * OPDS_PAGE_SIZE) as usize)
+            .take(OPDS_PAGE_SIZE as usize)
+            .collect(),
+        publications: Vec::new(),
+        pagination: Some(OpdsPagination {
+            page,
+            page_size: OPDS_PAGE_SIZE,
+            total,
+        }),
+        query: None,
+    }
+}
+
+/// Lists the ways to browse the catalog.
+pub async fn api_opds_root(Extension(version): Extension<OpdsVersion>) -> Response {
+    let entry = |id: &str, title: &str, href: &str, kind, rel| OpdsNavigationEntry {
+        id: format!("urn:harmony:{}", id),
+        title: title.to_owned(),
+        href: href.to_owned(),
+        kind,
+        rel,
+        count: None,
+    };
+    let feed = OpdsFeed {
+        id: "urn:harmony:catalog".to_owned(),
+        title: "Harmony".to_owned(),
+        href: String::new(),
+        kind: OpdsFeedKind::Navigation,
+        updated: Utc::now(),
+        navigation: vec![
+            entry(
+                "recent",
+                "Recently Added",
+                "/recent",
+                OpdsFeedKind::Acquisition,
+                "http://opds-spec.org/sort/new",
+            ),
+            entry(
+                "books",
+                "All Books",
+                "/books",
+                OpdsFeedKind::Acquisition,
+                "subsection",
+            ),
+            entry(
+                "authors",
+                "Authors",
+                "/authors",
+                OpdsFeedKind::Navigation,
+                "subsection",
+            ),
+            entry(
+                "series",
+                "Series",
+                "/series",
+                OpdsFeedKind::Navigation,
+                "subsection",
+            ),
+            entry(
+                "languages",
+                "Languages",
+                "/languages",
+                OpdsFeedKind::Navigation,
+                "subsection",
+            ),
+            entry(
+                "starred",
+                "Starred",
+                "/starred",
+                OpdsFeedKind::Acquisition,
+                "subsection",
+            ),
+        ],
+        publications: Vec::new(),
+        pagination: None,
+        query: None,
+    };
+    opds_response(version, Ok(feed))
+}
+
+pub async fn api_opds_books(
+    State(state): State<AppState>,
+    Extension(version): Extension<OpdsVersion>,
+    Query(params): Query<OpdsParameters>,
+) -> Response {
+    let feed = opds_acquisition_feed(
+        "urn:harmony:books".to_owned(),
+        "All Books".to_owned(),
+        "/books".to_owned(),
+        BookListFilter::default(),
+        BookListSort::Title,
+        &params,
+        &state,
+    )
+    .await;
+    opds_response(version, feed)
+}
+
+/// Lists the books most recently added to the library first.
+pub async fn api_opds_recent(
+    State(state): State<AppState>,
+    Extension(version): Extension<OpdsVersion>,
+    Query(params): Query<OpdsParameters>,
+) -> Response {
+    let feed = opds_acquisition_feed(
+        "urn:harmony:recent".to_owned(),
+        "Recently Added".to_owned(),
+        "/recent".to_owned(),
+        BookListFilter::default(),
+        BookListSort::Added,
+        &params,
+        &state,
+    )
+    .await;
+    opds_response(version, feed)
+}
+
+/// Lists the books starred by the user the request is authorized as.
+pub async fn api_opds_starred(
+    State(state): State<AppState>,
+    Extension(version): Extension<OpdsVersion>,
+    Query(params): Query<OpdsParameters>,
+    headers: HeaderMap,
+) -> Response {
+    // the credentials were checked before reaching the catalog
+    let username = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| auth_decode_basic(v).ok())
+        .map(|(u, _)| u)
+        .unwrap_or_default();
+    let feed = match get_user_id(&username, &state.db).await {
+        Ok(user_id) => {
+            opds_acquisition_feed(
+                "urn:harmony:starred".to_owned(),
+                "Starred".to_owned(),
+                "/starred".to_owned(),
+                BookListFilter {
+                    starred_by: Some(user_id),
+                    ..Default::default()
+                },
+                BookListSort::Title,
+                &params,
+                &state,
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+    opds_response(version, feed)
+}
+
+pub async fn api_opds_authors(
+    State(state): State<AppState>,
+    Extension(version): Extension<OpdsVersion>,
+    Query(params): Query<OpdsParameters>,
+) -> Response {
+    let feed = book_get_authors(u32::MAX, &state.db).await.map(|authors| {
+        let entries = authors
+            .into_iter()
+            .map(|a| OpdsNavigationEntry {
+                id: format!("urn:uuid:{}", a.artist.id),
+                title: a.artist.name,
+                href: format!("/authors/{}", a.artist.id),
+                kind: OpdsFeedKind::Acquisition,
+                rel: "subsection",
+                count: Some(a.book_count),
+            })
+            .collect();
+        opds_navigation_feed(
+            "urn:harmony:authors",
+            "Authors",
+            "/authors",
+            entries,
+            &params,
+        )
+    });
+    opds_response(version, feed)
+}
+
+pub async fn api_opds_author(
+    State(state): State<AppState>,
+    Extension(version): Extension<OpdsVersion>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<OpdsParameters>,
+) -> Response {
+    let feed = match artist_get_by_id(id, &state.db).await {
+        Ok(artist) => {
+            opds_acquisition_feed(
+                format!("urn:uuid:{}", id),
+                artist.name,
+                format!("/authors/{}", id),
+                BookListFilter {
+                    author_id: Some(id),
+                    ..Default::default()
+                },
+                BookListSort::Series,
+                &params,
+                &state,
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+    opds_response(version, feed)
+}
+
+pub async fn api_opds_series_list(
+    State(state): State<AppState>,
+    Extension(version): Extension<OpdsVersion>,
+    Query(params): Query<OpdsParameters>,
+) -> Response {
+    let feed = book_get_series_list(&state.db).await.map(|series| {
+        let entries = series
+            .into_iter()
+            .map(|s| {
+                let encoded = utf8_percent_encode(&s.name, NON_ALPHANUMERIC).to_string();
+                OpdsNavigationEntry {
+                    id: format!("urn:harmony:series:{}", encoded),
+                    href: format!("/series/{}", encoded),
+                    title: s.name,
+                    kind: OpdsFeedKind::Acquisition,
+                    rel: "subsection",
+                    count: Some(s.book_count),
+                }
+            })
+            .collect();
+        opds_navigation_feed("urn:harmony:series", "Series", "/series", entries, &params)
+    });
+    opds_response(version, feed)
+}
+
+/// Lists the books of a series in the order of the series.
+pub async fn api_opds_series(
+    State(state): State<AppState>,
+    Extension(version): Extension<OpdsVersion>,
+    Path(name): Path<String>,
+    Query(params): Query<OpdsParameters>,
+) -> Response {
+    let encoded = utf8_percent_encode(&name, NON_ALPHANUMERIC).to_string();
+    let feed = opds_acquisition_feed(
+        format!("urn:harmony:series:{}", encoded),
+        name.clone(),
+        format!("/series/{}", encoded),
+        BookListFilter {
+            series: Some(name),
+            ..Default::default()
+        },
+        BookListSort::Series,
+        &params,
+        &state,
+    )
+    .await;
+    opds_response(version, feed)
+}
+
+pub async fn api_opds_languages(
+    State(state): State<AppState>,
+    Extension(version): Extension<OpdsVersion>,
+    Query(params): Query<OpdsParameters>,
+) -> Response {
+    let feed = book_get_languages(&state.db).await.map(|languages| {
+        let entries = languages
+            .into_iter()
+            .map(|l| {
+                let encoded = utf8_percent_encode(&l.language, NON_ALPHANUMERIC).to_string();
+                OpdsNavigationEntry {
+                    id: format!("urn:harmony:languages:{}", encoded),
+                    href: format!("/languages/{}", encoded),
+                    title: l.language,
+                    kind: OpdsFeedKind::Acquisition,
+                    rel: "subsection",
+                    count: Some(l.book_count),
+                }
+            })
+            .collect();
+        opds_navigation_feed(
+            "urn:harmony:languages",
+            "Languages",
+            "/languages",
+            entries,
+            &params,
+        )
+    });
+    opds_response(version, feed)
+}
+
+pub async fn api_opds_language(
+    State(state): State<AppState>,
+    Extension(version): Extension<OpdsVersion>,
+    Path(language): Path<String>,
+    Query(params): Query<OpdsParameters>,
+) -> Response {
+    let encoded = utf8_percent_encode(&language, NON_ALPHANUMERIC).to_string();
+    let feed = opds_acquisition_feed(
+        format!("urn:harmony:languages:{}", encoded),
+        language.clone(),
+        format!("/languages/{}", encoded),
+        BookListFilter {
+            language: Some(language),
+            ..Default::default()
+        },
+        BookListSort::Title,
+        &params,
+        &state,
+    )
+    .await;
+    opds_response(version, feed)
+}
+
+/// Searches the titles, series and authors of the books.
+pub async fn api_opds_search(
+    State(state): State<AppState>,
+    Extension(version): Extension<OpdsVersion>,
+    Query(params): Query<OpdsParameters>,
+) -> Response {
+    let query = params.q.clone().unwrap_or_default().trim().to_owned();
+    let feed = opds_acquisition_feed(
+        "urn:harmony:search".to_owned(),
+        format!("Search: {}", query),
+        "/search".to_owned(),
+        BookListFilter {
+            query: Some(query.clone()),
+            ..Default::default()
+        },
+        BookListSort::Title,
+        &params,
+        &state,
+    )
+    .await
+    .map(|feed| OpdsFeed {
+        query: Some(query),
+        ..feed
+    });
+    opds_response(version, feed)
+}
+
+pub async fn api_opds_opensearch(Extension(version): Extension<OpdsVersion>) -> Response {
+    (
+        [(
+            header::CONTENT_TYPE,
+            "application/opensearchdescription+xml",
+        )],
+        write_opensearch_description(version.base()),
+    )
+        .into_response()
+}
+
+/// Serves the file of a book for download, so that readers can get it with the same
+/// credentials as the catalog.
+pub async fn api_opds_book_file(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let book = book_get_by_id(id, &state.db)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let file_path = &book.file.as_ref().ok_or(StatusCode::NOT_FOUND)?.path;
+    let file = File::open(file_path)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/epub+zip")
+        .body(Body::from_stream(ReaderStream::new(file)))
+        .unwrap())
+}
+
+/// Serves the cover of a book, which is also used as its thumbnail.
+pub async fn api_opds_cover(State(state): State<AppState>, Path(id): Path<Uuid>) -> Response {
+    match book_get_by_id(id, &state.db).await {
+        Ok(book::ModelEx {
+            picture: Some(picture),
+            ..
+        }) => Response::builder()
+            .header(header::CONTENT_TYPE, parse_picture_media_type(&picture))
+            .body(Body::from(picture))
+            .unwrap(),
+        _ => StatusCode::NOT_FOUND.into_response(),
+    }
+}
@@ -0,0 +1,195 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    api::responses::{
+        BookmarkListResponse, BookmarkResponse, ContinueReadingResponse, HarmonyResponse,
+        ReadingProgressResponse,
+    },
+    library::reading::{
+        ReadingPosition, reading_add_bookmark, reading_delete_bookmark, reading_get_bookmarks,
+        reading_get_continue_list, reading_get_progress, reading_save_progress,
+    },
+};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SaveProgressParameters {
+    u: String,
+    id: Uuid,
+    cfi: Option<String>,
+    spine_index: Option<u32>,
+    percentage: f64,
+    device: Option<String>,
+    finished: Option<bool>,
+    timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct ReadingParameters {
+    u: String,
+    id: Uuid,
+}
+
+#[derive(Deserialize)]
+pub struct ContinueReadingParameters {
+    u: String,
+    size: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateBookmarkParameters {
+    u: String,
+    id: Uuid,
+    cfi: Option<String>,
+    spine_index: Option<u32>,
+    label: Option<String>,
+}
+
+/// Saves where the user is in a book. The response holds the position that is kept, which is
+/// not the given one if another device already saved a newer position.
+pub async fn api_save_reading_progress(
+    State(state): State<AppState>,
+    Query(params): Query<SaveProgressParameters>,
+) -> Json<Value> {
+    let position = ReadingPosition {
+        cfi: params.cfi,
+        spine_index: params.spine_index,
+        xpointer: None,
+        percentage: params.percentage,
+        device: params.device,
+        device_id: None,
+        finished: params.finished.unwrap_or(false),
+        timestamp: params.timestamp,
+    };
+    let (status, progress) =
+        match reading_save_progress(&params.u, params.id, position, &state.db).await {
+            Ok(p) => (Ok(()), Some(p)),
+            Err(e) => (Err(e.to_string()), None),
+        };
+    Json(
+        serde_json::to_value(ReadingProgressResponse {
+            harmony: HarmonyResponse {
+                status,
+                with_license: false,
+            },
+            progress,
+        })
+        .unwrap(),
+    )
+}
+
+pub async fn api_get_reading_progress(
+    State(state): State<AppState>,
+    Query(params): Query<ReadingParameters>,
+) -> Json<Value> {
+    let (status, progress) = match reading_get_progress(&params.u, params.id, &state.db).await {
+        Ok(p) => (Ok(()), p),
+        Err(e) => (Err(e.to_string()), None),
+    };
+    Json(
+        serde_json::to_value(ReadingProgressResponse {
+            harmony: HarmonyResponse {
+                status,
+                with_license: false,
+            },
+            progress,
+        })
+        .unwrap(),
+    )
+}
+
+/// Lists the books the user is reading, most recently read first.
+pub async fn api_get_continue_reading(
+    State(state): State<AppState>,
+    Query(params): Query<ContinueReadingParameters>,
+) -> Json<Value> {
+    // default length is 10
+    let len = params.size.unwrap_or(10);
+    let (status, books) = match reading_get_continue_list(&params.u, len, &state.db).await {
+        Ok(b) => (Ok(()), b),
+        Err(e) => (Err(e.to_string()), Vec::new()),
+    };
+    Json(
+        serde_json::to_value(ContinueReadingResponse {
+            harmony: HarmonyResponse {
+                status,
+                with_license: false,
+            },
+            books,
+        })
+        .unwrap(),
+    )
+}
+
+pub async fn api_create_bookmark(
+    State(state): State<AppState>,
+    Query(params): Query<CreateBookmarkParameters>,
+) -> Json<Value> {
+    let (status, bookmark) = match reading_add_bookmark(
+        &params.u,
+        params.id,
+        params.cfi,
+        params.spine_index,
+        params.label,
+        &state.db,
+    )
+    .await
+    {
+        Ok(b) => (Ok(()), Some(b)),
+        Err(e) => (Err(e.to_string()), None),
+    };
+    Json(
+        serde_json::to_value(BookmarkResponse {
+            harmony: HarmonyResponse {
+                status,
+                with_license: false,
+            },
+            bookmark,
+        })
+        .unwrap(),
+    )
+}
+
+pub async fn api_get_bookmarks(
+    State(state): State<AppState>,
+    Query(params): Query<ReadingParameters>,
+) -> Json<Value> {
+    let (status, bookmarks) = match reading_get_bookmarks(&params.u, params.id, &state.db).await {
+        Ok(b) => (Ok(()), b),
+        Err(e) => (Err(e.to_string()), Vec::new()),
+    };
+    Json(
+        serde_json::to_value(BookmarkListResponse {
+            harmony: HarmonyResponse {
+                status,
+                with_license: false,
+            },
+            bookmarks,
+        })
+        .unwrap(),
+    )
+}
+
+pub async fn api_delete_bookmark(
+    State(state): State<AppState>,
+    Query(params): Query<ReadingParameters>,
+) -> Json<Value> {
+    Json(
+        serde_json::to_value(HarmonyResponse {
+            status: reading_delete_bookmark(&params.u, params.id, &state.db)
+                .await
+                .map_err(|e| e.to_string()),
+            with_license: false,
+        })
+        .unwrap(),
+    )
+}
@@ -1,6 +1,19 @@
 use serde::ser::{Serialize, SerializeStruct, Serializer};
+use uuid::Uuid;
 
-use crate::db::{album, artist, book, playlist, track};
+use crate::db::{
+    album, annotation, artist, book, bookmark, file, playlist, reading_progress, scan_error, track,
+};
+use crate::format::{epub::EpubToc, lrc::Lyrics};
+use crate::library::{
+    analyzer::AnalyzeStatus,
+    book::{BookAuthor, BookSeries, BookSeriesDetail},
+    bulk_edit::BulkEditFile,
+    reading::ContinueReading,
+    scanner::ScanStatus,
+    verifier::VerifyStatus,
+    waveform::WaveformPeaks,
+};
 
 const HARMONY_VERSION: &str = "0.1.0";
 const SERVER_TYPE: &str = "harmony";
@@ -88,6 +101,42 @@ pub struct TrackResponse {
     pub track: Option<track::ModelEx>,
 }
 
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LyricsList {
+    pub structured_lyrics: Vec<Lyrics>,
+}
+
+#[derive(serde::Serialize)]
+pub struct PlainLyrics {
+    pub artist: Option<String>,
+    pub title: String,
+    pub value: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LyricsResponse {
+    pub harmony: HarmonyResponse,
+    pub lyrics_list: Option<LyricsList>,
+    pub lyrics: Option<PlainLyrics>,
+}
+
+#[derive(serde::Serialize)]
+pub struct WaveformResponse {
+    pub harmony: HarmonyResponse,
+    pub waveform: Option<WaveformPeaks>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkEditResponse {
+    pub harmony: HarmonyResponse,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch_id: Option<Uuid>,
+    pub files: Vec<BulkEditFile>,
+}
+
 #[derive(serde::Serialize)]
 pub struct PlaylistListResponse {
     pub harmony: HarmonyResponse,
@@ -112,6 +161,54 @@ pub struct BookResponse {
     pub book: Option<book::ModelEx>,
 }
 
+#[derive(serde::Serialize)]
+pub struct BookTocResponse {
+    pub harmony: HarmonyResponse,
+    pub toc: Option<EpubToc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct BookSeriesListResponse {
+    pub harmony: HarmonyResponse,
+    pub series: Vec<BookSeries>,
+}
+
+#[derive(serde::Serialize)]
+pub struct BookSeriesResponse {
+    pub harmony: HarmonyResponse,
+    pub series: Option<BookSeriesDetail>,
+}
+
+#[derive(serde::Serialize)]
+pub struct AuthorListResponse {
+    pub harmony: HarmonyResponse,
+    pub authors: Vec<BookAuthor>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ReadingProgressResponse {
+    pub harmony: HarmonyResponse,
+    pub progress: Option<reading_progress::Model>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ContinueReadingResponse {
+    pub harmony: HarmonyResponse,
+    pub books: Vec<ContinueReading>,
+}
+
+#[derive(serde::Serialize)]
+pub struct BookmarkResponse {
+    pub harmony: HarmonyResponse,
+    pub bookmark: Option<bookmark::Model>,
+}
+
+#[derive(serde::Serialize)]
+pub struct BookmarkListResponse {
+    pub harmony: HarmonyResponse,
+    pub bookmarks: Vec<bookmark::Model>,
+}
+
 #[derive(serde::Serialize)]
 pub struct StarredResponse {
     pub harmony: HarmonyResponse,
@@ -119,3 +216,73 @@ pub struct StarredResponse {
     pub albums: Vec<album::Model>,
     pub books: Vec<book::Model>,
 }
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanErrorListResponse {
+    pub harmony: HarmonyResponse,
+    pub scan_errors: Vec<scan_error::Model>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanStatusResponse {
+    pub harmony: HarmonyResponse,
+    pub scan_status: ScanStatus,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyStatusResponse {
+    pub harmony: HarmonyResponse,
+    pub verify_status: VerifyStatus,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyReportResponse {
+    pub harmony: HarmonyResponse,
+    pub files: Vec<file::Model>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyzeStatusResponse {
+    pub harmony: HarmonyResponse,
+    pub analyze_status: AnalyzeStatus,
+}
+
+/// The body of a KOReader sync server error, such as code 2001 for bad credentials.
+#[derive(serde::Serialize)]
+pub struct KoreaderErrorResponse {
+    pub code: u32,
+    pub message: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct KoreaderUserResponse {
+    pub username: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct KoreaderAuthResponse {
+    pub authorized: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct KoreaderSaveResponse {
+    pub document: String,
+    pub timestamp: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct AnnotationResponse {
+    pub harmony: HarmonyResponse,
+    pub annotation: Option<annotation::Model>,
+}
+
+#[derive(serde::Serialize)]
+pub struct AnnotationListResponse {
+    pub harmony: HarmonyResponse,
+    pub annotations: Vec<annotation::Model>,
+}
@@ -1,17 +1,36 @@
+use std::{
+    io::{ErrorKind, Write},
+    path::PathBuf,
+};
+
+use anyhow::anyhow;
+
 use axum::{
+    Json,
     body::Body,
     extract::{Query, State},
     http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
 use serde::Deserialize;
-use tokio::fs::File;
-use tokio_util::io::ReaderStream;
+use serde_json::Value;
+use tokio::{fs::File, io::DuplexStream};
+use tokio_util::io::{ReaderStream, SyncIoBridge};
 use uuid::Uuid;
 
 use crate::{
     AppState,
-    library::{book::book_get_by_id, track::track_get_by_id},
+    api::responses::{BookTocResponse, HarmonyResponse, WaveformResponse},
+    format::{
+        epub::{parse_epub_toc, read_epub_resource},
+        flac::{FlacDecoder, open_flac_from, parse_flac_file},
+        wav::write_wav_segment,
+    },
+    library::{
+        book::book_get_by_id,
+        track::track_get_by_id,
+        waveform::{waveform_get, waveform_resolution},
+    },
 };
 
 #[derive(Deserialize)]
@@ -19,9 +38,70 @@ pub struct RetrieveParameters {
     id: Uuid,
 }
 
+#[derive(Deserialize)]
+pub struct BookResourceParameters {
+    id: Uuid,
+    path: String,
+}
+
+#[derive(Deserialize)]
+pub struct WaveformParameters {
+    id: Uuid,
+    samples: Option<u32>,
+    format: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct StreamParameters {
+    id: Uuid,
+    #[serde(rename = "timeOffset")]
+    time_offset: Option<f64>,
+}
+
+/// Streams what the given function writes as the response body. The function runs on the
+/// blocking thread pool, as decoding and cutting audio files uses synchronous readers. The
+/// file must have been opened by stream_open() already, as the response has been sent by the
+/// time the function fails.
+fn stream_blocking<F>(content_type: &'static str, write: F) -> Response
+where
+    F: FnOnce(&mut SyncIoBridge<DuplexStream>) -> anyhow::Result<()> + Send + 'static,
+{
+    let (reader, writer) = tokio::io::duplex(64 * 1024);
+    let mut writer = SyncIoBridge::new(writer);
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = write(&mut writer) {
+            println!("[ERROR] Failed to stream track: {}", e);
+        }
+    });
+    Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from_stream(ReaderStream::new(reader)))
+        .unwrap()
+}
+
+/// Opens the file of a track on the blocking thread pool before responding, so that a missing
+/// file is answered with 404, and a file that cannot be read or cut with 500.
+async fn stream_open<T, F>(open: F) -> Result<T, StatusCode>
+where
+    T: Send + 'static,
+    F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(open).await {
+        Ok(Ok(opened)) => Ok(opened),
+        Ok(Err(e)) => {
+            println!("[ERROR] Failed to open track: {}", e);
+            match e.downcast_ref::<std::io::Error>() {
+                Some(e) if e.kind() == ErrorKind::NotFound => Err(StatusCode::NOT_FOUND),
+                _ => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        }
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
 pub async fn api_stream_track(
     State(state): State<AppState>,
-    Query(params): Query<RetrieveParameters>,
+    Query(params): Query<StreamParameters>,
 ) -> Result<impl IntoResponse, StatusCode> {
     // get track with file info
     let track = track_get_by_id(params.id, &state.db)
@@ -35,6 +115,56 @@ pub async fn api_stream_track(
         .ok_or(StatusCode::NOT_FOUND)?
         .path
         .clone();
+    if params
+        .time_offset
+        .is_some_and(|t| !t.is_finite() || t < 0.0)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let time_offset = params.time_offset.filter(|t| *t > 0.0);
+    let ogg = file_path.ends_with(".oga");
+
+    // seeking and tracks of a cue sheet need the stream info of the file
+    if track.start_sample.is_some() || time_offset.is_some() {
+        let path = PathBuf::from(&file_path);
+        let info = stream_open(move || Ok(parse_flac_file(&path, false)?.stream_info)).await?;
+
+        // an offset has to be a time within the track
+        let track_start = track.start_sample.unwrap_or(0) as u64;
+        let end = track.end_sample.map(|e| e as u64);
+        let length = end
+            .or(Some(info.total_samples).filter(|t| *t > 0))
+            .map(|end| end.saturating_sub(track_start) as f64 / info.sample_rate as f64);
+        if let Some(time_offset) = time_offset
+            && length.is_some_and(|length| time_offset >= length)
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let start = track_start
+            .saturating_add((time_offset.unwrap_or(0.0) * info.sample_rate as f64) as u64);
+
+        // tracks of a cue sheet are decoded from the whole file and served as WAV, and so is
+        // Ogg encapsulated FLAC when seeking, as it cannot be cut at a frame
+        let path = PathBuf::from(&file_path);
+        if track.start_sample.is_some() || ogg {
+            let decoder = stream_open(move || FlacDecoder::open_at(&path, start)).await?;
+            return Ok(stream_blocking("audio/wav", move |writer| {
+                write_wav_segment(decoder, start, end, writer)
+            }));
+        }
+
+        // seeking in FLAC serves the stream from the frame at the offset
+        let (header, mut reader) = stream_open(move || open_flac_from(&path, start)).await?;
+        return Ok(stream_blocking("audio/flac", move |writer| {
+            writer.write_all(&header)?;
+            std::io::copy(&mut reader, writer)?;
+            writer.flush()?;
+            Ok(())
+        }));
+    }
+
+    // Ogg encapsulated FLAC is served as Ogg
+    let content_type = if ogg { "audio/ogg" } else { "audio/flac" };
 
     // open the file and create a stream
     let file = File::open(&file_path)
@@ -44,11 +174,52 @@ pub async fn api_stream_track(
     let body = Body::from_stream(stream);
 
     Ok(Response::builder()
-        .header(header::CONTENT_TYPE, "audio/flac")
+        .header(header::CONTENT_TYPE, content_type)
         .body(body)
         .unwrap())
 }
 
+pub async fn api_get_waveform(
+    State(state): State<AppState>,
+    Query(params): Query<WaveformParameters>,
+) -> Response {
+    // default number of peaks is 1000
+    let samples = params.samples.unwrap_or(1000);
+    if waveform_resolution(samples).is_none() {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    let waveform = match track_get_by_id(params.id, &state.db).await {
+        Ok(t) => waveform_get(&t, samples, &state.db).await,
+        Err(e) => Err(e),
+    };
+
+    // the binary format is served as is, without a harmony response
+    if params.format.as_deref() == Some("binary") {
+        return match waveform {
+            Ok(w) => Response::builder()
+                .header(header::CONTENT_TYPE, "application/octet-stream")
+                .body(Body::from(w.to_bytes()))
+                .unwrap(),
+            Err(_) => StatusCode::NOT_FOUND.into_response(),
+        };
+    }
+    let (status, waveform) = match waveform {
+        Ok(w) => (Ok(()), Some(w)),
+        Err(e) => (Err(e.to_string()), None),
+    };
+    Json(
+        serde_json::to_value(WaveformResponse {
+            harmony: HarmonyResponse {
+                status,
+                with_license: false,
+            },
+            waveform,
+        })
+        .unwrap(),
+    )
+    .into_response()
+}
+
 pub async fn api_fetch_book(
     State(state): State<AppState>,
     Query(params): Query<RetrieveParameters>,
@@ -78,3 +249,64 @@ pub async fn api_fetch_book(
         .body(body)
         .unwrap())
 }
+
+/// Gets the table of contents of a book, with the paths of its documents in reading order.
+pub async fn api_get_book_toc(
+    State(state): State<AppState>,
+    Query(params): Query<RetrieveParameters>,
+) -> Json<Value> {
+    let toc = match book_get_by_id(params.id, &state.db).await {
+        Ok(book) => match book.file.as_ref() {
+            Some(file) => {
+                let path = PathBuf::from(&file.path);
+                tokio::task::spawn_blocking(move || parse_epub_toc(&path))
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|r| r)
+            }
+            None => Err(anyhow!("[ERROR] Book does not have a file")),
+        },
+        Err(e) => Err(e),
+    };
+    let (status, toc) = match toc {
+        Ok(t) => (Ok(()), Some(t)),
+        Err(e) => (Err(e.to_string()), None),
+    };
+    Json(
+        serde_json::to_value(BookTocResponse {
+            harmony: HarmonyResponse {
+                status,
+                with_license: false,
+            },
+            toc,
+        })
+        .unwrap(),
+    )
+}
+
+/// Serves a single resource of a book, such as a chapter, a stylesheet or an image, with the
+/// media type given in its manifest. Scripts in the resources are not allowed to run.
+pub async fn api_get_book_resource(
+    State(state): State<AppState>,
+    Query(params): Query<BookResourceParameters>,
+) -> Result<impl IntoResponse, StatusCode> {
+    // get book with file info
+    let book = book_get_by_id(params.id, &state.db)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let path = PathBuf::from(&book.file.as_ref().ok_or(StatusCode::NOT_FOUND)?.path);
+
+    // read the resource out of the archive
+    let (data, media_type) =
+        tokio::task::spawn_blocking(move || read_epub_resource(&path, &params.path))
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .map_err(|_| StatusCode::NOT_FOUND)?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, media_type)
+        .header(header::CONTENT_SECURITY_POLICY, "sandbox")
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .body(Body::from(data))
+        .unwrap())
+}
@@ -0,0 +1,81 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    AppState,
+    api::responses::{HarmonyResponse, ScanErrorListResponse, ScanStatusResponse},
+    library::scan_error::scan_error_get_list,
+};
+
+#[derive(Deserialize)]
+pub struct StartScanParameters {
+    #[serde(rename = "fullScan")]
+    full_scan: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct ScanErrorListParameters {
+    size: Option<u32>,
+    offset: Option<u32>,
+}
+
+pub async fn api_start_scan(
+    State(state): State<AppState>,
+    Query(params): Query<StartScanParameters>,
+) -> Json<Value> {
+    let full = params.full_scan.unwrap_or(false);
+    let status = state
+        .scanner
+        .start(&state.settings.library.path, full, &state.db)
+        .map_err(|e| e.to_string());
+
+    Json(
+        serde_json::to_value(ScanStatusResponse {
+            harmony: HarmonyResponse {
+                status,
+                with_license: false,
+            },
+            scan_status: state.scanner.status(),
+        })
+        .unwrap(),
+    )
+}
+
+pub async fn api_get_scan_status(State(state): State<AppState>) -> Json<Value> {
+    Json(
+        serde_json::to_value(ScanStatusResponse {
+            harmony: HarmonyResponse {
+                status: Ok(()),
+                with_license: false,
+            },
+            scan_status: state.scanner.status(),
+        })
+        .unwrap(),
+    )
+}
+
+pub async fn api_get_scan_errors(
+    State(state): State<AppState>,
+    Query(params): Query<ScanErrorListParameters>,
+) -> Json<Value> {
+    // default length is 10
+    let mut len = 10;
+    if let Some(l) = params.size {
+        len = l;
+    }
+
+    Json(
+        serde_json::to_value(ScanErrorListResponse {
+            harmony: HarmonyResponse {
+                status: Ok(()),
+                with_license: false,
+            },
+            scan_errors: scan_error_get_list(len, params.offset.unwrap_or(0), &state.db).await,
+        })
+        .unwrap(),
+    )
+}
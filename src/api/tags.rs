@@ -0,0 +1,172 @@
+use std::sync::Arc;
+
+use anyhow::{Result, anyhow};
+use axum::{
+    Json,
+    extract::{Multipart, Query, State},
+};
+use serde::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    format::flac::{FlacPicture, FlacTagEdit},
+    library::{
+        bulk_edit::{
+            BulkEditFile, BulkEditOperation, BulkEditSelection, bulk_edit_apply, bulk_edit_preview,
+            bulk_edit_revert,
+        },
+        tags::{tags_update_album, tags_update_track, tags_valid_field},
+    },
+};
+
+use super::responses::{BulkEditResponse, HarmonyResponse};
+
+#[derive(Deserialize)]
+pub struct BulkEditParameters {
+    #[serde(flatten)]
+    selection: BulkEditSelection,
+    operations: Vec<BulkEditOperation>,
+    apply: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct RevertBulkEditParameters {
+    id: Uuid,
+}
+
+/// Reads the id of the track or album to edit from the "id" field, an optional front cover
+/// from the "picture" field, and tags from all other fields, named like Vorbis comments. A tag
+/// field may be repeated for several values, and is removed if all of its values are empty.
+async fn tags_read_edit(mut multipart: Multipart) -> Result<(Uuid, FlacTagEdit)> {
+    let mut id: Option<Uuid> = None;
+    let mut edit = FlacTagEdit {
+        tags: Vec::new(),
+        front_cover: None,
+    };
+    while let Some(field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_owned();
+        if name == "id" {
+            id = Some(Uuid::parse_str(&field.text().await?)?);
+        } else if name == "picture" {
+            edit.front_cover = Some(FlacPicture::front_cover(field.bytes().await?.to_vec()));
+        } else {
+            if !tags_valid_field(&name) {
+                return Err(anyhow!("[ERROR] Invalid tag name: {}", name));
+            }
+            let key = name.to_uppercase();
+            let value = field.text().await?.trim().to_owned();
+            let index = match edit.tags.iter().position(|(k, _)| *k == key) {
+                Some(index) => index,
+                None => {
+                    edit.tags.push((key, Vec::new()));
+                    edit.tags.len() - 1
+                }
+            };
+            if !value.is_empty() {
+                edit.tags[index].1.push(value);
+            }
+        }
+    }
+    let id = id.ok_or(anyhow!("[ERROR] Missing id"))?;
+    if edit.tags.is_empty() && edit.front_cover.is_none() {
+        return Err(anyhow!("[ERROR] No tags to edit"));
+    }
+    Ok((id, edit))
+}
+
+fn tags_response(status: Result<()>) -> Json<Value> {
+    Json(
+        serde_json::to_value(HarmonyResponse {
+            status: status.map_err(|e| e.to_string()),
+            with_license: false,
+        })
+        .unwrap(),
+    )
+}
+
+/// Writes tags to the audio file of a track, and scans it again.
+pub async fn api_update_track_tags(
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> Json<Value> {
+    let artwork = Arc::new(state.settings.library.artwork.clone());
+    let status = match tags_read_edit(multipart).await {
+        Ok((id, edit)) => {
+            tags_update_track(id, edit, &artwork, state.scanner.library(), &state.db).await
+        }
+        Err(e) => Err(e),
+    };
+    tags_response(status)
+}
+
+/// Writes tags to the audio files of every track of an album, and scans them again.
+pub async fn api_update_album_tags(
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> Json<Value> {
+    let artwork = Arc::new(state.settings.library.artwork.clone());
+    let status = match tags_read_edit(multipart).await {
+        Ok((id, edit)) => {
+            tags_update_album(id, edit, &artwork, state.scanner.library(), &state.db).await
+        }
+        Err(e) => Err(e),
+    };
+    tags_response(status)
+}
+
+fn tags_bulk_edit_response(result: Result<(Option<Uuid>, Vec<BulkEditFile>)>) -> Json<Value> {
+    let (status, batch_id, files) = match result {
+        Ok((batch_id, files)) => (Ok(()), batch_id, files),
+        Err(e) => (Err(e.to_string()), None, Vec::new()),
+    };
+    Json(
+        serde_json::to_value(BulkEditResponse {
+            harmony: HarmonyResponse {
+                status,
+                with_license: false,
+            },
+            batch_id,
+            files,
+        })
+        .unwrap(),
+    )
+}
+
+/// Edits the tags of the selected tracks with a list of operations. Unless asked to apply them,
+/// the changes are only previewed; applied changes can be reverted with the returned batch id.
+pub async fn api_bulk_edit_tags(
+    State(state): State<AppState>,
+    Json(params): Json<BulkEditParameters>,
+) -> Json<Value> {
+    let result = if params.apply.unwrap_or(false) {
+        let artwork = Arc::new(state.settings.library.artwork.clone());
+        bulk_edit_apply(
+            &params.selection,
+            params.operations,
+            &artwork,
+            state.scanner.library(),
+            &state.db,
+        )
+        .await
+        .map(|(batch_id, files)| (Some(batch_id), files))
+    } else {
+        bulk_edit_preview(&params.selection, params.operations, state.db.as_ref())
+            .await
+            .map(|files| (None, files))
+    };
+    tags_bulk_edit_response(result)
+}
+
+/// Reverts the changes of a bulk edit.
+pub async fn api_revert_bulk_edit(
+    State(state): State<AppState>,
+    Query(params): Query<RevertBulkEditParameters>,
+) -> Json<Value> {
+    let artwork = Arc::new(state.settings.library.artwork.clone());
+    let result = bulk_edit_revert(params.id, &artwork, state.scanner.library(), &state.db)
+        .await
+        .map(|files| (Some(params.id), files));
+    tags_bulk_edit_response(result)
+}
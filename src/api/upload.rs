@@ -1,3 +1,4 @@
+use anyhow::{Result, anyhow};
 use axum::{
     Json,
     extract::{Multipart, State},
@@ -5,90 +6,60 @@ use axum::{
 use serde_json::Value;
 use uuid::Uuid;
 
-use crate::{AppState, library::artist::artist_set_picture};
+use crate::{
+    AppState,
+    library::{album::album_set_picture, artist::artist_set_picture},
+};
 
 use super::responses::HarmonyResponse;
 
-pub async fn api_upload_artist_picture(
-    State(state): State<AppState>,
-    mut multipart: Multipart,
-) -> Json<Value> {
-    // obtain the id and data for the artist picture
+/// Reads the id and the data of an uploaded picture from the "id" and "picture" fields.
+async fn upload_read_picture(mut multipart: Multipart) -> Result<(Uuid, Vec<u8>)> {
     let mut id: Option<Uuid> = None;
     let mut file_data: Option<Vec<u8>> = None;
-    while let Some(field) = multipart.next_field().await.unwrap() {
+    while let Some(field) = multipart.next_field().await? {
         if field.name() == Some("picture") {
-            let data = match field.bytes().await {
-                Ok(bytes) => bytes.to_vec(),
-                Err(e) => {
-                    return Json(
-                        serde_json::to_value(HarmonyResponse {
-                            status: Err(e.to_string()),
-                            with_license: false,
-                        })
-                        .unwrap(),
-                    );
-                }
-            };
-            file_data = Some(data);
+            file_data = Some(field.bytes().await?.to_vec());
         } else if field.name() == Some("id") {
-            let text = match field.text().await {
-                Ok(t) => t,
-                Err(e) => {
-                    return Json(
-                        serde_json::to_value(HarmonyResponse {
-                            status: Err(e.to_string()),
-                            with_license: false,
-                        })
-                        .unwrap(),
-                    );
-                }
-            };
-            id = match Uuid::parse_str(&text) {
-                Ok(u) => Some(u),
-                Err(e) => {
-                    return Json(
-                        serde_json::to_value(HarmonyResponse {
-                            status: Err(e.to_string()),
-                            with_license: false,
-                        })
-                        .unwrap(),
-                    );
-                }
-            };
+            id = Some(Uuid::parse_str(&field.text().await?)?);
         }
     }
-
-    // try to upload if id and data are available
-    if let Some(artist_id) = id {
-        if let Some(picture) = file_data {
-            match artist_set_picture(artist_id, &state.db, picture).await {
-                Ok(()) => {
-                    return Json(
-                        serde_json::to_value(HarmonyResponse {
-                            status: Ok(()),
-                            with_license: false,
-                        })
-                        .unwrap(),
-                    );
-                }
-                Err(e) => {
-                    return Json(
-                        serde_json::to_value(HarmonyResponse {
-                            status: Err(e.to_string()),
-                            with_license: false,
-                        })
-                        .unwrap(),
-                    );
-                }
-            };
-        }
+    match (id, file_data) {
+        (Some(id), Some(picture)) => Ok((id, picture)),
+        _ => Err(anyhow!("Failed to upload file")),
     }
+}
+
+fn upload_response(status: Result<()>) -> Json<Value> {
     Json(
         serde_json::to_value(HarmonyResponse {
-            status: Err("Failed to upload file".to_string()),
+            status: status.map_err(|e| e.to_string()),
             with_license: false,
         })
         .unwrap(),
     )
 }
+
+pub async fn api_upload_artist_picture(
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> Json<Value> {
+    // obtain the id and data for the artist picture, and try to upload it
+    let status = match upload_read_picture(multipart).await {
+        Ok((artist_id, picture)) => artist_set_picture(artist_id, state.db.as_ref(), picture).await,
+        Err(e) => Err(e),
+    };
+    upload_response(status)
+}
+
+/// Uploads the picture of an album, which replaces the picture chosen during scans for good.
+pub async fn api_upload_album_picture(
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> Json<Value> {
+    let status = match upload_read_picture(multipart).await {
+        Ok((album_id, picture)) => album_set_picture(album_id, state.db.as_ref(), picture).await,
+        Err(e) => Err(e),
+    };
+    upload_response(status)
+}
@@ -0,0 +1,81 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    AppState,
+    api::responses::{HarmonyResponse, VerifyReportResponse, VerifyStatusResponse},
+    library::verifier::verify_get_report,
+};
+
+#[derive(Deserialize)]
+pub struct StartVerifyParameters {
+    #[serde(rename = "fullVerify")]
+    full_verify: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyReportParameters {
+    size: Option<u32>,
+    offset: Option<u32>,
+}
+
+pub async fn api_start_verify(
+    State(state): State<AppState>,
+    Query(params): Query<StartVerifyParameters>,
+) -> Json<Value> {
+    let full = params.full_verify.unwrap_or(false);
+    let status = state
+        .verifier
+        .start(full, &state.db)
+        .map_err(|e| e.to_string());
+
+    Json(
+        serde_json::to_value(VerifyStatusResponse {
+            harmony: HarmonyResponse {
+                status,
+                with_license: false,
+            },
+            verify_status: state.verifier.status(),
+        })
+        .unwrap(),
+    )
+}
+
+pub async fn api_get_verify_status(State(state): State<AppState>) -> Json<Value> {
+    Json(
+        serde_json::to_value(VerifyStatusResponse {
+            harmony: HarmonyResponse {
+                status: Ok(()),
+                with_license: false,
+            },
+            verify_status: state.verifier.status(),
+        })
+        .unwrap(),
+    )
+}
+
+pub async fn api_get_verify_report(
+    State(state): State<AppState>,
+    Query(params): Query<VerifyReportParameters>,
+) -> Json<Value> {
+    // default length is 10
+    let mut len = 10;
+    if let Some(l) = params.size {
+        len = l;
+    }
+
+    Json(
+        serde_json::to_value(VerifyReportResponse {
+            harmony: HarmonyResponse {
+                status: Ok(()),
+                with_license: false,
+            },
+            files: verify_get_report(len, params.offset.unwrap_or(0), &state.db).await,
+        })
+        .unwrap(),
+    )
+}
@@ -19,6 +19,21 @@ pub fn auth_check_and_decode_hex(password: &str) -> Result<String> {
     return Ok(password.to_owned());
 }
 
+/// Decodes the username and password of an HTTP Basic authorization header.
+pub fn auth_decode_basic(header: &str) -> Result<(String, String)> {
+    let (scheme, encoded) = header
+        .split_once(' ')
+        .ok_or(anyhow!("[ERROR] Invalid authorization header"))?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return Err(anyhow!("[ERROR] Authorization scheme must be Basic"));
+    }
+    let credentials = String::from_utf8(general_purpose::STANDARD.decode(encoded.trim())?)?;
+    let (username, password) = credentials
+        .split_once(':')
+        .ok_or(anyhow!("[ERROR] Invalid Basic credentials"))?;
+    Ok((username.to_owned(), password.to_owned()))
+}
+
 fn auth_key_from_string(key_string: &str) -> [u8; 32] {
     let mut hasher = Sha256::new();
     hasher.update(key_string.as_bytes());
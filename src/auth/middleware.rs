@@ -1,54 +1,75 @@
 use axum::{
     extract::{Query, Request, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode, header},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
 };
 use serde::Deserialize;
 
 use crate::{ADMIN_PATHS, AppState};
 
-use super::auth::auth_check_and_decode_hex;
+use super::auth::{auth_check_and_decode_hex, auth_decode_basic};
 use super::users::auth_check_user;
 
 #[derive(Deserialize)]
 pub struct AuthParameters {
-    u: String,
+    u: Option<String>,
     p: Option<String>,
     t: Option<String>,
     s: Option<String>,
 }
 
+/// Gets the credentials of an HTTP Basic authorization header as a username and a token with
+/// an empty salt.
+fn auth_basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (username, password) = auth_decode_basic(value).ok()?;
+    Some((username, format!("{:x}", md5::compute(password.as_bytes()))))
+}
+
 pub async fn auth_middleware(
     State(state): State<AppState>,
     Query(params): Query<AuthParameters>,
     request: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    // either p or both t and s must be specified
+    // without a username in the query, clients such as e-readers may use HTTP Basic instead
+    let username: String;
     let token_str: String;
     let salt_str: String;
-    if let Some(t) = params.t {
-        if let Some(s) = params.s {
-            token_str = t;
-            salt_str = s;
-        } else {
-            println!("[ERROR] Token and salt must both be specified");
-            return Err(StatusCode::UNAUTHORIZED);
-        }
-    } else {
-        if let Some(p) = params.p {
-            if let Ok(dec_password) = auth_check_and_decode_hex(&p) {
-                token_str = format!("{:x}", md5::compute(dec_password.as_bytes()));
-                salt_str = "".to_string();
+    if let Some(u) = params.u {
+        username = u;
+
+        // either p or both t and s must be specified
+        if let Some(t) = params.t {
+            if let Some(s) = params.s {
+                token_str = t;
+                salt_str = s;
             } else {
-                println!("[ERROR] Invalid hex-encoded password");
+                println!("[ERROR] Token and salt must both be specified");
                 return Err(StatusCode::UNAUTHORIZED);
             }
         } else {
-            println!("[ERROR] Either a password or token and salt must be specified");
-            return Err(StatusCode::UNAUTHORIZED);
+            if let Some(p) = params.p {
+                if let Ok(dec_password) = auth_check_and_decode_hex(&p) {
+                    token_str = format!("{:x}", md5::compute(dec_password.as_bytes()));
+                    salt_str = "".to_string();
+                } else {
+                    println!("[ERROR] Invalid hex-encoded password");
+                    return Err(StatusCode::UNAUTHORIZED);
+                }
+            } else {
+                println!("[ERROR] Either a password or token and salt must be specified");
+                return Err(StatusCode::UNAUTHORIZED);
+            }
         }
+    } else if let Some((u, t)) = auth_basic_credentials(request.headers()) {
+        username = u;
+        token_str = t;
+        salt_str = "".to_string();
+    } else {
+        println!("[ERROR] A username must be specified");
+        return Err(StatusCode::UNAUTHORIZED);
     }
 
     // get request path to see if it requires admin privileges
@@ -63,7 +84,7 @@ pub async fn auth_middleware(
 
     // check that the user has the correct credentials
     if let Err(e) = auth_check_user(
-        &params.u,
+        &username,
         &token_str,
         &salt_str,
         &state.settings.key,
@@ -78,3 +99,32 @@ pub async fn auth_middleware(
     let response = next.run(request).await;
     return Ok(response);
 }
+
+/// Checks HTTP Basic credentials only, for clients that can't send them in the query. Failing
+/// responses ask for credentials, so that the client prompts the user for them.
+pub async fn auth_basic_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorized = match auth_basic_credentials(request.headers()) {
+        Some((username, token)) => {
+            auth_check_user(&username, &token, "", &state.settings.key, &state.db, false)
+                .await
+                .inspect_err(|e| println!("{}", e))
+                .is_ok()
+        }
+        None => false,
+    };
+    if !authorized {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(
+                header::WWW_AUTHENTICATE,
+                "Basic realm=\"Harmony\", charset=\"UTF-8\"",
+            )],
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
@@ -4,19 +4,23 @@ use sea_orm::entity::prelude::*;
 use serde::ser::{Serialize, SerializeStruct, Serializer};
 
 #[sea_orm::model]
-#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
 #[sea_orm(table_name = "albums")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub id: Uuid,
     pub name: String,
     pub picture: Option<Vec<u8>>,
+    #[sea_orm(default_value = false)]
+    pub custom_picture: bool,
     #[sea_orm(default_value = 0)]
     pub plays: u32,
     pub last_played: Option<DateTime<Utc>>,
     pub last_modified: DateTime<Utc>,
     #[sea_orm(nullable)]
     pub musicbrainz_id: Option<String>,
+    pub gain: Option<f64>,
+    pub peak: Option<f64>,
     #[sea_orm(has_many, via = "album_artists")]
     pub artists: HasMany<super::artist::Entity>,
     #[sea_orm(has_many)]
@@ -25,12 +29,22 @@ pub struct Model {
 
 impl ActiveModelBehavior for ActiveModel {}
 
+/// Loudness normalization values of an album, in the shape of the OpenSubsonic replayGain field.
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReplayGain {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    album_gain: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    album_peak: Option<f64>,
+}
+
 impl Serialize for Model {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Album", 7)?;
+        let mut state = serializer.serialize_struct("Album", 8)?;
         state.serialize_field("id", &self.id.to_string())?;
         state.serialize_field("name", &self.name)?;
         if let Some(p) = &self.picture {
@@ -42,6 +56,13 @@ impl Serialize for Model {
         state.serialize_field("lastPlayed", &self.last_played)?;
         state.serialize_field("lastModified", &self.last_modified)?;
         state.serialize_field("musicbrainzId", &self.musicbrainz_id)?;
+        state.serialize_field(
+            "replayGain",
+            &ReplayGain {
+                album_gain: self.gain,
+                album_peak: self.peak,
+            },
+        )?;
         state.end()
     }
 }
@@ -51,7 +72,7 @@ impl Serialize for ModelEx {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Album", 9)?;
+        let mut state = serializer.serialize_struct("Album", 10)?;
         state.serialize_field("id", &self.id.to_string())?;
         state.serialize_field("name", &self.name)?;
         if let Some(p) = &self.picture {
@@ -63,6 +84,13 @@ impl Serialize for ModelEx {
         state.serialize_field("lastPlayed", &self.last_played)?;
         state.serialize_field("lastModified", &self.last_modified)?;
         state.serialize_field("musicbrainzId", &self.musicbrainz_id)?;
+        state.serialize_field(
+            "replayGain",
+            &ReplayGain {
+                album_gain: self.gain,
+                album_peak: self.peak,
+            },
+        )?;
         state.serialize_field(
             "artists",
             &self
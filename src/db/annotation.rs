@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+/// A highlight a user made in a book, as an EPUB CFI range and the text it selects, with an
+/// optional note on it.
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "annotations")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    #[sea_orm(indexed)]
+    pub user_id: Uuid,
+    #[sea_orm(indexed)]
+    pub book_id: Uuid,
+    pub cfi_range: String,
+    pub text: String,
+    pub color: String,
+    pub note: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    #[sea_orm(belongs_to, from = "user_id", to = "id", on_delete = "Cascade")]
+    pub user: Option<super::user::Entity>,
+    #[sea_orm(belongs_to, from = "book_id", to = "id", on_delete = "Cascade")]
+    pub book: Option<super::book::Entity>,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl Serialize for Model {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("Annotation", 8)?;
+        state.serialize_field("id", &self.id.to_string())?;
+        state.serialize_field("bookId", &self.book_id.to_string())?;
+        state.serialize_field("cfiRange", &self.cfi_range)?;
+        state.serialize_field("text", &self.text)?;
+        state.serialize_field("color", &self.color)?;
+        state.serialize_field("note", &self.note)?;
+        state.serialize_field("createdAt", &self.created_at)?;
+        state.serialize_field("updatedAt", &self.updated_at)?;
+        state.end()
+    }
+}
@@ -1,15 +1,32 @@
 use base64::{Engine, engine::general_purpose};
+use chrono::{DateTime, Utc};
 use sea_orm::entity::prelude::*;
 use serde::{Serialize, Serializer, ser::SerializeStruct};
 
 #[sea_orm::model]
-#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
 #[sea_orm(table_name = "books")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub id: Uuid,
     pub title: String,
     pub picture: Option<Vec<u8>>,
+    pub description: Option<String>,
+    pub publisher: Option<String>,
+    pub publication_date: Option<String>,
+    pub language: Option<String>,
+    pub isbn: Option<String>,
+    pub asin: Option<String>,
+    pub uuid: Option<String>,
+    pub series: Option<String>,
+    pub series_index: Option<f64>,
+    /// The partial MD5 digest of the book file, which KOReader syncs progress by.
+    #[sea_orm(indexed)]
+    pub partial_md5: Option<String>,
+    /// When the book was first scanned into the library.
+    pub added_at: Option<DateTime<Utc>>,
+    #[sea_orm(has_many)]
+    pub subjects: HasMany<super::book_subjects::Entity>,
     #[sea_orm(has_one)]
     pub file: HasOne<super::file::Entity>,
     #[sea_orm(has_many, via = "book_artists")]
@@ -18,12 +35,23 @@ pub struct Model {
 
 impl ActiveModelBehavior for ActiveModel {}
 
+/// Typed identifiers of a book, leaving out the kinds it doesn't have.
+#[derive(serde::Serialize)]
+struct Identifiers<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    isbn: Option<&'a String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    asin: Option<&'a String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uuid: Option<&'a String>,
+}
+
 impl Serialize for Model {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Book", 3)?;
+        let mut state = serializer.serialize_struct("Book", 14)?;
         state.serialize_field("id", &self.id.to_string())?;
         state.serialize_field("title", &self.title)?;
         if let Some(p) = &self.picture {
@@ -31,6 +59,21 @@ impl Serialize for Model {
         } else {
             state.serialize_field("picture", &None::<String>)?;
         }
+        state.serialize_field("description", &self.description)?;
+        state.serialize_field("publisher", &self.publisher)?;
+        state.serialize_field("publicationDate", &self.publication_date)?;
+        state.serialize_field("language", &self.language)?;
+        state.serialize_field(
+            "identifiers",
+            &Identifiers {
+                isbn: self.isbn.as_ref(),
+                asin: self.asin.as_ref(),
+                uuid: self.uuid.as_ref(),
+            },
+        )?;
+        state.serialize_field("series", &self.series)?;
+        state.serialize_field("seriesIndex", &self.series_index)?;
+        state.serialize_field("addedAt", &self.added_at)?;
         state.end()
     }
 }
@@ -40,7 +83,7 @@ impl Serialize for ModelEx {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Book", 6)?;
+        let mut state = serializer.serialize_struct("Book", 16)?;
         state.serialize_field("id", &self.id.to_string())?;
         state.serialize_field("title", &self.title)?;
         if let Some(p) = &self.picture {
@@ -56,6 +99,29 @@ impl Serialize for ModelEx {
                 .map(|a| a.name.clone())
                 .collect::<Vec<_>>(),
         )?;
+        state.serialize_field("description", &self.description)?;
+        state.serialize_field("publisher", &self.publisher)?;
+        state.serialize_field("publicationDate", &self.publication_date)?;
+        state.serialize_field("language", &self.language)?;
+        state.serialize_field(
+            "identifiers",
+            &Identifiers {
+                isbn: self.isbn.as_ref(),
+                asin: self.asin.as_ref(),
+                uuid: self.uuid.as_ref(),
+            },
+        )?;
+        state.serialize_field("series", &self.series)?;
+        state.serialize_field("seriesIndex", &self.series_index)?;
+        state.serialize_field("addedAt", &self.added_at)?;
+        state.serialize_field(
+            "subjects",
+            &self
+                .subjects
+                .iter()
+                .map(|s| s.subject.clone())
+                .collect::<Vec<_>>(),
+        )?;
         state.end()
     }
 }
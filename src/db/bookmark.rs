@@ -0,0 +1,41 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "bookmarks")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    #[sea_orm(indexed)]
+    pub user_id: Uuid,
+    #[sea_orm(indexed)]
+    pub book_id: Uuid,
+    pub cfi: Option<String>,
+    pub spine_index: Option<u32>,
+    pub label: Option<String>,
+    pub created_at: DateTime<Utc>,
+    #[sea_orm(belongs_to, from = "user_id", to = "id", on_delete = "Cascade")]
+    pub user: Option<super::user::Entity>,
+    #[sea_orm(belongs_to, from = "book_id", to = "id", on_delete = "Cascade")]
+    pub book: Option<super::book::Entity>,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl Serialize for Model {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("Bookmark", 6)?;
+        state.serialize_field("id", &self.id.to_string())?;
+        state.serialize_field("bookId", &self.book_id.to_string())?;
+        state.serialize_field("cfi", &self.cfi)?;
+        state.serialize_field("spineIndex", &self.spine_index)?;
+        state.serialize_field("label", &self.label)?;
+        state.serialize_field("createdAt", &self.created_at)?;
+        state.end()
+    }
+}
@@ -1,6 +1,7 @@
 use chrono::{DateTime, Utc};
 use sea_orm::Set;
 use sea_orm::entity::prelude::*;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
 
 #[sea_orm::model]
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
@@ -10,6 +11,12 @@ pub struct Model {
     pub id: Uuid,
     pub path: String,
     pub last_modified: DateTime<Utc>,
+    pub size: Option<i64>,
+    #[sea_orm(indexed)]
+    pub fingerprint: Option<String>,
+    pub verify_status: Option<String>,
+    pub verify_error: Option<String>,
+    pub verified_at: Option<DateTime<Utc>>,
     #[sea_orm(unique)]
     pub track_id: Option<Uuid>,
     #[sea_orm(belongs_to, from = "track_id", to = "id", on_delete = "Cascade")]
@@ -51,3 +58,22 @@ impl ActiveModelBehavior for ActiveModel {
         })
     }
 }
+
+impl Serialize for Model {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("File", 9)?;
+        state.serialize_field("id", &self.id.to_string())?;
+        state.serialize_field("path", &self.path)?;
+        state.serialize_field("lastModified", &self.last_modified)?;
+        state.serialize_field("size", &self.size)?;
+        state.serialize_field("trackId", &self.track_id.map(|id| id.to_string()))?;
+        state.serialize_field("bookId", &self.book_id.map(|id| id.to_string()))?;
+        state.serialize_field("verifyStatus", &self.verify_status)?;
+        state.serialize_field("verifyError", &self.verify_error)?;
+        state.serialize_field("verifiedAt", &self.verified_at)?;
+        state.end()
+    }
+}
@@ -0,0 +1,17 @@
+use sea_orm::entity::prelude::*;
+
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "lyrics")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    #[sea_orm(indexed)]
+    pub track_id: Uuid,
+    pub lang: String,
+    pub content: String,
+    #[sea_orm(belongs_to, from = "track_id", to = "id", on_delete = "Cascade")]
+    pub track: Option<super::track::Entity>,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,24 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+
+/// A change to one tag of a file made by a bulk edit, with the values of the tag before and
+/// after the change as JSON arrays. The file is referred to by the id of its record, which is
+/// kept when the file is moved.
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "metadata_edits")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    #[sea_orm(indexed)]
+    pub batch_id: Uuid,
+    pub file_id: Uuid,
+    pub path: String,
+    pub field: String,
+    pub before: String,
+    pub after: String,
+    pub edited_at: DateTime<Utc>,
+    pub reverted_at: Option<DateTime<Utc>>,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
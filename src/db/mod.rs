@@ -1,10 +1,17 @@
 pub mod album;
 pub mod album_artists;
+pub mod annotation;
 pub mod artist;
 pub mod book;
 pub mod book_artists;
+pub mod book_subjects;
+pub mod bookmark;
 pub mod file;
+pub mod lyrics;
+pub mod metadata_edit;
 pub mod playlist;
+pub mod reading_progress;
+pub mod scan_error;
 pub mod starred_albums;
 pub mod starred_books;
 pub mod starred_tracks;
@@ -12,3 +19,4 @@ pub mod track;
 pub mod track_artists;
 pub mod track_playlists;
 pub mod user;
+pub mod waveform;
@@ -0,0 +1,50 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+/// Where a user is in a book, as an EPUB CFI, a KOReader XPointer or the index of a document in
+/// the spine, along with the percentage read. The time is when the position was reached on the
+/// device, which decides between positions synced from several devices.
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "reading_progress")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub user_id: Uuid,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub book_id: Uuid,
+    pub cfi: Option<String>,
+    pub spine_index: Option<u32>,
+    pub percentage: f64,
+    pub xpointer: Option<String>,
+    pub device: Option<String>,
+    pub device_id: Option<String>,
+    #[sea_orm(default_value = false)]
+    pub finished: bool,
+    pub updated_at: DateTime<Utc>,
+    #[sea_orm(belongs_to, from = "user_id", to = "id", on_delete = "Cascade")]
+    pub user: Option<super::user::Entity>,
+    #[sea_orm(belongs_to, from = "book_id", to = "id", on_delete = "Cascade")]
+    pub book: Option<super::book::Entity>,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl Serialize for Model {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("ReadingProgress", 9)?;
+        state.serialize_field("bookId", &self.book_id.to_string())?;
+        state.serialize_field("cfi", &self.cfi)?;
+        state.serialize_field("spineIndex", &self.spine_index)?;
+        state.serialize_field("xpointer", &self.xpointer)?;
+        state.serialize_field("percentage", &self.percentage)?;
+        state.serialize_field("device", &self.device)?;
+        state.serialize_field("deviceId", &self.device_id)?;
+        state.serialize_field("finished", &self.finished)?;
+        state.serialize_field("updatedAt", &self.updated_at)?;
+        state.end()
+    }
+}
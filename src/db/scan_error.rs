@@ -0,0 +1,35 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "scan_errors")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    #[sea_orm(unique)]
+    pub path: String,
+    pub format: String,
+    pub error: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl Serialize for Model {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("ScanError", 6)?;
+        state.serialize_field("id", &self.id.to_string())?;
+        state.serialize_field("path", &self.path)?;
+        state.serialize_field("format", &self.format)?;
+        state.serialize_field("error", &self.error)?;
+        state.serialize_field("firstSeen", &self.first_seen)?;
+        state.serialize_field("lastSeen", &self.last_seen)?;
+        state.end()
+    }
+}
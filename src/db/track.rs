@@ -1,19 +1,27 @@
 use chrono::{DateTime, Utc};
-use sea_orm::Set;
 use sea_orm::entity::prelude::*;
 use serde::ser::{Serialize, SerializeStruct, Serializer};
 
 #[sea_orm::model]
-#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
 #[sea_orm(table_name = "tracks")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub id: Uuid,
     pub title: String,
     pub picture: Option<Vec<u8>>,
+    #[sea_orm(default_value = false)]
+    pub front_cover: bool,
     #[sea_orm(default_value = 0)]
     pub plays: u32,
     pub runtime: i64,
+    pub start_sample: Option<i64>,
+    pub end_sample: Option<i64>,
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+    pub analyzed_at: Option<DateTime<Utc>>,
     pub last_played: Option<DateTime<Utc>>,
     pub album_id: Uuid,
     #[sea_orm(has_one)]
@@ -26,32 +34,20 @@ pub struct Model {
     pub playlists: HasMany<super::playlist::Entity>,
 }
 
-impl ActiveModelBehavior for ActiveModel {
-    fn after_save<'life0, 'async_trait, C>(
-        model: Model,
-        db: &'life0 C,
-        _insert: bool,
-    ) -> std::pin::Pin<
-        Box<dyn std::future::Future<Output = Result<Model, DbErr>> + Send + 'async_trait>,
-    >
-    where
-        C: ConnectionTrait + 'async_trait,
-        Self: Send + 'async_trait,
-        'life0: 'async_trait,
-    {
-        Box::pin(async move {
-            // propagate the track picture to the album
-            if let Some(album) = super::album::Entity::find_by_id(model.album_id)
-                .one(db)
-                .await?
-            {
-                let mut album: super::album::ActiveModel = album.into();
-                album.picture = Set(model.picture.clone());
-                album.update(db).await?;
-            }
-            Ok(model)
-        })
-    }
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Loudness normalization values of a track, in the shape of the OpenSubsonic replayGain field.
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReplayGain {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    track_gain: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    track_peak: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    album_gain: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    album_peak: Option<f64>,
 }
 
 impl Serialize for Model {
@@ -59,13 +55,22 @@ impl Serialize for Model {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Track", 6)?;
+        let mut state = serializer.serialize_struct("Track", 7)?;
         state.serialize_field("id", &self.id.to_string())?;
         state.serialize_field("title", &self.title)?;
         state.serialize_field("runtime", &self.runtime)?;
         state.serialize_field("plays", &self.plays)?;
         state.serialize_field("lastPlayed", &self.last_played)?;
         state.serialize_field("albumId", &self.album_id.to_string())?;
+        state.serialize_field(
+            "replayGain",
+            &ReplayGain {
+                track_gain: self.track_gain,
+                track_peak: self.track_peak,
+                album_gain: self.album_gain,
+                album_peak: self.album_peak,
+            },
+        )?;
         state.end()
     }
 }
@@ -75,13 +80,22 @@ impl Serialize for ModelEx {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Track", 7)?;
+        let mut state = serializer.serialize_struct("Track", 8)?;
         state.serialize_field("id", &self.id.to_string())?;
         state.serialize_field("title", &self.title)?;
         state.serialize_field("runtime", &self.runtime)?;
         state.serialize_field("plays", &self.plays)?;
         state.serialize_field("lastPlayed", &self.last_played)?;
         state.serialize_field("albumId", &self.album_id.to_string())?;
+        state.serialize_field(
+            "replayGain",
+            &ReplayGain {
+                track_gain: self.track_gain,
+                track_peak: self.track_peak,
+                album_gain: self.album_gain,
+                album_peak: self.album_peak,
+            },
+        )?;
         state.serialize_field(
             "artists",
             &self
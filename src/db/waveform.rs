@@ -0,0 +1,18 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "waveforms")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub track_id: Uuid,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub samples: u32,
+    pub last_modified: DateTime<Utc>,
+    pub peaks: Vec<u8>,
+    #[sea_orm(belongs_to, from = "track_id", to = "id", on_delete = "Cascade")]
+    pub track: Option<super::track::Entity>,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,255 @@
+use std::{fs, path::Path};
+
+use anyhow::{Result, anyhow};
+
+/// Number of CD frames per second, the unit of the INDEX times in a cue sheet.
+const CUE_FRAMES_PER_SECOND: u64 = 75;
+
+#[derive(Debug, Clone)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub file: Option<String>,
+    pub start_sample: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub tracks: Vec<CueTrack>,
+}
+
+impl CueSheet {
+    /// Returns the sample at which the track with the given index ends, or None if it is the
+    /// last track and runs until the end of the file.
+    pub fn end_sample(&self, index: usize) -> Option<u64> {
+        self.tracks.get(index + 1).map(|t| t.start_sample)
+    }
+
+    /// Keeps only the tracks that belong to the audio file with the given name. Cue sheets
+    /// of single file rips often refer to the file by its name before it was converted to
+    /// FLAC (e.g. "album.wav"), so all tracks are kept if they refer to the same file.
+    pub fn retain_file(&mut self, file_name: &str) {
+        let first = self.tracks.first().and_then(|t| t.file.clone());
+        if self.tracks.iter().all(|t| t.file == first) {
+            return;
+        }
+        self.tracks.retain(|t| {
+            t.file
+                .as_ref()
+                .and_then(|f| {
+                    Path::new(&f.replace('\\', "/"))
+                        .file_name()
+                        .map(|n| n.to_owned())
+                })
+                .is_some_and(|n| n == file_name)
+        });
+    }
+}
+
+/// Splits the first word off the given line, returning it and the rest of the line.
+fn split_word(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    match line.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (line, ""),
+    }
+}
+
+/// Returns the value of a cue sheet field, removing the surrounding quotes if there are any.
+fn parse_value(value: &str) -> String {
+    let value = value.trim();
+    if let Some(quoted) = value.strip_prefix('"') {
+        return match quoted.find('"') {
+            Some(end) => quoted[..end].to_owned(),
+            None => quoted.to_owned(),
+        };
+    }
+    value.to_owned()
+}
+
+/// Parses an INDEX time (mm:ss:ff) into a number of CD frames.
+fn parse_time(time: &str) -> Result<u64> {
+    let parts: Vec<u64> = time
+        .split(':')
+        .map(|p| p.trim().parse::<u64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| anyhow!("[ERROR] Invalid cue sheet time: {}", time))?;
+    match parts[..] {
+        [minutes, seconds, frames] => Ok((minutes * 60 + seconds) * CUE_FRAMES_PER_SECOND + frames),
+        _ => Err(anyhow!("[ERROR] Invalid cue sheet time: {}", time)),
+    }
+}
+
+/// Parses the text of a cue sheet. The INDEX 01 time of every track is converted into a sample
+/// offset using the sample rate of the audio file. Commands that are not needed to split the
+/// audio file into tracks are ignored.
+pub fn parse_cue_sheet(text: &str, sample_rate: u32) -> Result<CueSheet> {
+    let mut sheet = CueSheet::default();
+    let mut file: Option<String> = None;
+    let mut track: Option<CueTrack> = None;
+    let mut has_start = false;
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        let (command, rest) = split_word(line);
+        match command.to_uppercase().as_str() {
+            "FILE" => {
+                // the file type follows the (possibly quoted) file name
+                let name = if rest.starts_with('"') {
+                    parse_value(rest)
+                } else {
+                    rest.rsplit_once(char::is_whitespace)
+                        .map_or(rest, |(name, _)| name)
+                        .to_owned()
+                };
+                file = Some(name);
+            }
+            "TRACK" => {
+                if let Some(t) = track.take()
+                    && has_start
+                {
+                    sheet.tracks.push(t);
+                }
+                let (number, _) = split_word(rest);
+                track = Some(CueTrack {
+                    number: number.parse().unwrap_or(sheet.tracks.len() as u32 + 1),
+                    title: None,
+                    performer: None,
+                    file: file.clone(),
+                    start_sample: 0,
+                });
+                has_start = false;
+            }
+            "TITLE" => match &mut track {
+                Some(t) => t.title = Some(parse_value(rest)),
+                None => sheet.title = Some(parse_value(rest)),
+            },
+            "PERFORMER" => match &mut track {
+                Some(t) => t.performer = Some(parse_value(rest)),
+                None => sheet.performer = Some(parse_value(rest)),
+            },
+            "INDEX" => {
+                let (number, time) = split_word(rest);
+                if let Some(t) = &mut track
+                    && number.parse::<u32>() == Ok(1)
+                {
+                    let frames = parse_time(time)?;
+                    t.start_sample = frames * sample_rate as u64 / CUE_FRAMES_PER_SECOND;
+                    has_start = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(t) = track
+        && has_start
+    {
+        sheet.tracks.push(t);
+    }
+    if sheet.tracks.is_empty() {
+        return Err(anyhow!("[ERROR] Cue sheet does not contain any tracks"));
+    }
+    sheet.tracks.sort_by_key(|t| t.start_sample);
+    Ok(sheet)
+}
+
+/// Reads a cue sheet file. Cue sheets are often not encoded in UTF-8, in which case they are
+/// decoded as ISO-8859-1.
+pub fn parse_cue_file(path: &Path, sample_rate: u32) -> Result<CueSheet> {
+    let bytes = fs::read(path)?;
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => e.as_bytes().iter().map(|&b| b as char).collect(),
+    };
+    parse_cue_sheet(&text, sample_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = "\u{feff}PERFORMER \"The Band\"
+TITLE Unquoted Album
+FILE \"Side A.wav\" WAVE
+  TRACK 01 AUDIO
+    TITLE \"First Song\"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE Second
+    PERFORMER Guest
+    INDEX 00 03:10:00
+    INDEX 01 03:12:37
+FILE side_b.flac WAVE
+  TRACK 03 AUDIO
+    TITLE \"Third Song\"
+    INDEX 01 00:00:00
+";
+
+    /// Returns the track with the given number, as tracks are ordered by their start.
+    fn track(sheet: &CueSheet, number: u32) -> &CueTrack {
+        sheet.tracks.iter().find(|t| t.number == number).unwrap()
+    }
+
+    #[test]
+    fn parse_cue_sheet_reads_quoted_and_unquoted_values() {
+        let sheet = parse_cue_sheet(SHEET, 44100).unwrap();
+        assert_eq!(sheet.performer.as_deref(), Some("The Band"));
+        assert_eq!(sheet.title.as_deref(), Some("Unquoted Album"));
+        assert_eq!(track(&sheet, 1).title.as_deref(), Some("First Song"));
+        assert_eq!(track(&sheet, 2).title.as_deref(), Some("Second"));
+        assert_eq!(track(&sheet, 3).title.as_deref(), Some("Third Song"));
+        assert_eq!(track(&sheet, 2).performer.as_deref(), Some("Guest"));
+        assert_eq!(track(&sheet, 1).file.as_deref(), Some("Side A.wav"));
+        assert_eq!(track(&sheet, 3).file.as_deref(), Some("side_b.flac"));
+    }
+
+    #[test]
+    fn parse_cue_sheet_starts_tracks_at_index_01() {
+        let sheet = parse_cue_sheet(SHEET, 44100).unwrap();
+        // INDEX 00 03:10:00 is the pregap, INDEX 01 03:12:37 is 14437 frames in
+        assert_eq!(track(&sheet, 2).start_sample, 14437 * 44100 / 75);
+    }
+
+    #[test]
+    fn parse_cue_sheet_converts_frames_at_the_sample_rate() {
+        let text = "FILE a.wav WAVE\n TRACK 01 AUDIO\n  INDEX 01 01:02:37\n";
+        // (62 * 75 + 37) frames of 1/75 second
+        assert_eq!(
+            parse_cue_sheet(text, 44100).unwrap().tracks[0].start_sample,
+            2755956
+        );
+        assert_eq!(
+            parse_cue_sheet(text, 48000).unwrap().tracks[0].start_sample,
+            2999680
+        );
+        assert!(
+            parse_cue_sheet("FILE a.wav WAVE\n TRACK 01 AUDIO\n  INDEX 01 1:2\n", 44100).is_err()
+        );
+    }
+
+    #[test]
+    fn parse_cue_sheet_keeps_tracks_of_a_file() {
+        let mut sheet = parse_cue_sheet(SHEET, 44100).unwrap();
+        sheet.retain_file("side_b.flac");
+        let numbers: Vec<_> = sheet.tracks.iter().map(|t| t.number).collect();
+        assert_eq!(numbers, [3]);
+
+        // a single file rip keeps its tracks, whatever the file is named now
+        let text = "FILE album.wav WAVE\n TRACK 01 AUDIO\n  INDEX 01 00:00:00\n TRACK 02 AUDIO\n  INDEX 01 00:01:00\n";
+        let mut sheet = parse_cue_sheet(text, 44100).unwrap();
+        sheet.retain_file("album.flac");
+        assert_eq!(sheet.tracks.len(), 2);
+    }
+
+    #[test]
+    fn parse_cue_sheet_leaves_the_last_track_open() {
+        let text = "FILE a.wav WAVE\n TRACK 01 AUDIO\n  INDEX 01 00:00:00\n TRACK 02 AUDIO\n  INDEX 01 00:01:00\n TRACK 03 AUDIO\n  TITLE Hidden\n";
+        let sheet = parse_cue_sheet(text, 44100).unwrap();
+        // a track without an INDEX 01 is dropped, and the last one runs to the end of the file
+        assert_eq!(sheet.tracks.len(), 2);
+        assert_eq!(sheet.end_sample(0), Some(44100));
+        assert_eq!(sheet.end_sample(1), None);
+        assert!(parse_cue_sheet("TITLE Empty\n", 44100).is_err());
+    }
+}
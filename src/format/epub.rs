@@ -1,81 +1,472 @@
-use std::{fs::File, io::Read, path::Path};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
 
 use anyhow::{Result, anyhow};
-use nom::{
-    Parser,
-    bytes::complete::{tag, take_until},
-    sequence::delimited,
-};
+use percent_encoding::percent_decode_str;
+use roxmltree::{Document, Node, ParsingOptions};
+use serde::Serialize;
+use uuid::Uuid;
 use zip::ZipArchive;
 
+const DC_NAMESPACE: &str = "http://purl.org/dc/elements/1.1/";
+const OPF_NAMESPACE: &str = "http://www.idpf.org/2007/opf";
+const OPS_NAMESPACE: &str = "http://www.idpf.org/2007/ops";
+
 #[derive(Debug, Clone)]
+pub struct EpubCreator {
+    pub name: String,
+    /// The MARC relator code of the creator, such as "aut" for an author.
+    pub role: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct EpubMetadata {
     pub title: Option<String>,
     pub identifier: Option<String>,
     pub language: Option<String>,
-    pub creator: Option<String>,
+    pub creators: Vec<EpubCreator>,
+    pub description: Option<String>,
+    pub publisher: Option<String>,
+    /// The publication date, as an ISO 8601 date that may be reduced to a year or month.
+    pub date: Option<String>,
+    pub subjects: Vec<String>,
+    pub isbn: Option<String>,
+    pub asin: Option<String>,
+    pub uuid: Option<String>,
+    pub series: Option<String>,
+    pub series_index: Option<f64>,
     pub cover: Option<Vec<u8>>,
+    /// The partial MD5 digest KOReader uses to identify the file.
+    pub partial_md5: Option<String>,
+}
+
+/// The kinds of book identifiers that are told apart.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EpubIdentifierType {
+    Isbn,
+    Asin,
+    Uuid,
+}
+
+/// An entry of the table of contents of a book. Entries that only group others have no path.
+#[derive(Debug, Clone, Serialize)]
+pub struct EpubTocEntry {
+    pub label: String,
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fragment: Option<String>,
+    pub children: Vec<EpubTocEntry>,
+}
+
+/// The table of contents of a book, along with the paths of its documents in reading order.
+#[derive(Debug, Clone, Serialize)]
+pub struct EpubToc {
+    pub entries: Vec<EpubTocEntry>,
+    pub spine: Vec<String>,
+}
+
+/// An item of the manifest, with its href resolved to a path in the archive.
+struct EpubManifestItem {
+    id: String,
+    path: String,
+    media_type: String,
+    properties: Vec<String>,
+}
+
+/// A `<meta refines="#id">` refinement of another element of the metadata.
+struct EpubRefinement {
+    property: String,
+    value: String,
+}
+
+/// Parses an XML document, skipping the byte order mark some files start with. NCX files and
+/// XHTML documents usually come with a document type declaration, so those are allowed, but
+/// external entities are never resolved.
+fn parse_xml(input: &str) -> Result<Document<'_>> {
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+    Document::parse_with_options(input.trim_start_matches('\u{feff}'), options)
+        .map_err(|e| anyhow!("[ERROR] Invalid XML: {}", e))
 }
 
-fn parse_tag<'a>(input: &'a str, tag_name: &str) -> Result<String> {
-    let start_tag_prefix = format!("<{}", tag_name);
-    let end_tag = format!("</{}>", tag_name);
+/// Returns the text of an element and its children, including CDATA sections.
+fn parse_text(node: Node) -> String {
+    node.descendants()
+        .filter(|n| n.is_text())
+        .filter_map(|n| n.text())
+        .collect::<String>()
+        .trim()
+        .to_owned()
+}
+
+/// Checks for an element of the OPF namespace with the given name. Some OPF files don't put
+/// their elements in the namespace, so elements without one are accepted as well.
+fn is_opf_element(node: &Node, name: &str) -> bool {
+    node.is_element()
+        && node.tag_name().name() == name
+        && node
+            .tag_name()
+            .namespace()
+            .is_none_or(|ns| ns == OPF_NAMESPACE)
+}
 
-    let (input, _) = take_until::<_, _, nom::error::Error<&str>>(start_tag_prefix.as_str())
-        .parse(input)
-        .map_err(|_| anyhow!("Could not find tag {}", tag_name))?;
-    let (input, _) = tag::<_, _, nom::error::Error<&str>>(start_tag_prefix.as_str())
-        .parse(input)
-        .map_err(|_| anyhow!("Could not find tag {}", tag_name))?;
+fn is_dc_element(node: &Node, name: &str) -> bool {
+    node.is_element()
+        && node.tag_name().name() == name
+        && node.tag_name().namespace() == Some(DC_NAMESPACE)
+}
 
-    let (_, content) = delimited(
-        take_until(">").and(tag(">")),
-        take_until(end_tag.as_str()),
-        tag(end_tag.as_str()),
-    )
-    .parse(input)
-    .map_err(|_: nom::Err<nom::error::Error<&str>>| anyhow!("Could not find tag {}", tag_name))?;
+/// Collects the refinements of the metadata, by the id of the element they refine.
+fn parse_refinements(metadata: Node) -> HashMap<String, Vec<EpubRefinement>> {
+    let mut refinements: HashMap<String, Vec<EpubRefinement>> = HashMap::new();
+    for meta in metadata.children().filter(|n| is_opf_element(n, "meta")) {
+        if let Some(id) = meta.attribute("refines").and_then(|r| r.strip_prefix('#'))
+            && let Some(property) = meta.attribute("property")
+        {
+            refinements
+                .entry(id.to_owned())
+                .or_default()
+                .push(EpubRefinement {
+                    property: property.to_owned(),
+                    value: parse_text(meta),
+                });
+        }
+    }
+    refinements
+}
 
-    Ok(content.to_string())
+/// Returns the value of a refinement of an element, falling back to an EPUB 2 attribute of the
+/// element itself.
+fn parse_refined(
+    node: &Node,
+    refinements: &HashMap<String, Vec<EpubRefinement>>,
+    property: &str,
+) -> Option<String> {
+    node.attribute("id")
+        .and_then(|id| refinements.get(id))
+        .and_then(|r| r.iter().find(|r| r.property == property))
+        .map(|r| r.value.clone())
+        .or_else(|| {
+            node.attribute((OPF_NAMESPACE, property))
+                .map(|v| v.trim().to_owned())
+        })
+        .filter(|v| !v.is_empty())
 }
 
-/// Parse the cover item ID from `<meta name="cover" content="cover_id"/>`
-fn parse_cover_id(input: &str) -> Option<String> {
-    input
-        .split("<meta")
-        .find(|s| s.contains("name=\"cover\""))
-        .and_then(|s| s.split("content=\"").nth(1))
-        .and_then(|s| s.split('"').next())
-        .map(|s| s.to_string())
+/// Returns the text of the first Dublin Core element with the given name.
+fn parse_dc_text(metadata: Node, name: &str) -> Option<String> {
+    metadata
+        .children()
+        .filter(|n| is_dc_element(n, name))
+        .map(parse_text)
+        .find(|t| !t.is_empty())
 }
 
-/// Parse the cover href from `<item id="cover_id" href="path/to/cover.jpg" .../>`
-fn parse_cover_href(input: &str, cover_id: &str) -> Option<String> {
-    let id_pattern = format!("id=\"{}\"", cover_id);
-    input
-        .split("<item")
-        .find(|s| s.contains(&id_pattern))
-        .and_then(|s| s.split("href=\"").nth(1))
-        .and_then(|s| s.split('"').next())
-        .map(|s| s.to_string())
+/// Returns the publication date. EPUB 2 files may list several dates for different events,
+/// of which the publication is preferred.
+fn parse_date(metadata: Node) -> Option<String> {
+    let dates: Vec<Node> = metadata
+        .children()
+        .filter(|n| is_dc_element(n, "date"))
+        .collect();
+    let date = dates
+        .iter()
+        .find(|d| d.attribute((OPF_NAMESPACE, "event")) == Some("publication"))
+        .or(dates
+            .iter()
+            .find(|d| d.attribute((OPF_NAMESPACE, "event")).is_none()))
+        .map(|d| parse_text(*d))?;
+
+    // only the date is kept of a date and time
+    let date = date.split('T').next().unwrap_or_default().trim();
+    let valid = date.len() >= 4
+        && date.starts_with(|c: char| c.is_ascii_digit())
+        && date.chars().all(|c| c.is_ascii_digit() || c == '-');
+    valid.then(|| date.to_owned())
 }
 
-fn parse_epub_metadata(input: &str) -> (EpubMetadata, Option<String>) {
-    let cover_href = parse_cover_id(input).and_then(|id| parse_cover_href(input, &id));
+/// Returns the subjects of the book, without duplicates.
+fn parse_subjects(metadata: Node) -> Vec<String> {
+    let mut subjects: Vec<String> = Vec::new();
+    for subject in metadata
+        .children()
+        .filter(|n| is_dc_element(n, "subject"))
+        .map(parse_text)
+    {
+        if !subject.is_empty() && !subjects.contains(&subject) {
+            subjects.push(subject);
+        }
+    }
+    subjects
+}
+
+/// Works out the kind of an identifier from its scheme, a URN prefix of its value, or the shape
+/// of its value, and returns it with the value stripped of any prefix.
+fn parse_identifier(
+    node: &Node,
+    refinements: &HashMap<String, Vec<EpubRefinement>>,
+) -> Option<(EpubIdentifierType, String)> {
+    let value = parse_text(*node);
+    let scheme = node
+        .attribute((OPF_NAMESPACE, "scheme"))
+        .map(|s| s.to_owned())
+        .or_else(|| parse_refined(node, refinements, "identifier-type"))
+        .map(|s| s.to_lowercase());
+
+    // values like "urn:isbn:978..." or calibre's "amazon:B00..." carry their kind
+    let lower = value.to_lowercase();
+    let prefixed = [
+        ("urn:isbn:", EpubIdentifierType::Isbn),
+        ("isbn:", EpubIdentifierType::Isbn),
+        ("urn:uuid:", EpubIdentifierType::Uuid),
+        ("uuid:", EpubIdentifierType::Uuid),
+        ("urn:asin:", EpubIdentifierType::Asin),
+        ("asin:", EpubIdentifierType::Asin),
+        ("amazon:", EpubIdentifierType::Asin),
+    ]
+    .into_iter()
+    .find(|(prefix, _)| lower.starts_with(prefix))
+    .map(|(prefix, kind)| (kind, value[prefix.len()..].trim().to_owned()));
+    let (kind, value) = match prefixed {
+        Some(p) => p,
+        None => {
+            // ONIX codes 02 and 15 are ISBN-10 and ISBN-13
+            let kind = match scheme.as_deref() {
+                Some("isbn" | "02" | "15") => EpubIdentifierType::Isbn,
+                Some("uuid" | "calibre") => EpubIdentifierType::Uuid,
+                Some("asin" | "amazon" | "mobi-asin") => EpubIdentifierType::Asin,
+                _ if parse_is_isbn(&value) => EpubIdentifierType::Isbn,
+                _ if Uuid::parse_str(&value).is_ok() => EpubIdentifierType::Uuid,
+                _ => return None,
+            };
+            (kind, value)
+        }
+    };
+    match kind {
+        EpubIdentifierType::Isbn => {
+            let isbn: String = value.chars().filter(|c| !matches!(c, '-' | ' ')).collect();
+            parse_is_isbn(&isbn).then_some((kind, isbn.to_uppercase()))
+        }
+        EpubIdentifierType::Uuid => Uuid::parse_str(&value).ok().map(|u| (kind, u.to_string())),
+        EpubIdentifierType::Asin => (!value.is_empty()).then_some((kind, value.to_uppercase())),
+    }
+}
+
+/// Checks for a 10 or 13 digit ISBN, possibly with hyphens or spaces in between.
+fn parse_is_isbn(value: &str) -> bool {
+    let isbn: String = value.chars().filter(|c| !matches!(c, '-' | ' ')).collect();
+    if !isbn.is_ascii() {
+        return false;
+    }
+    match isbn.len() {
+        10 => {
+            isbn[..9].chars().all(|c| c.is_ascii_digit())
+                && isbn[9..]
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || c == 'x' || c == 'X')
+        }
+        13 => {
+            isbn.chars().all(|c| c.is_ascii_digit())
+                && (isbn.starts_with("978") || isbn.starts_with("979"))
+        }
+        _ => false,
+    }
+}
+
+/// Returns the series of the book and its position in it, from an EPUB 3 collection of the
+/// series type, or from calibre's own metadata.
+fn parse_series(
+    metadata: Node,
+    refinements: &HashMap<String, Vec<EpubRefinement>>,
+) -> (Option<String>, Option<f64>) {
+    let metas: Vec<Node> = metadata
+        .children()
+        .filter(|n| is_opf_element(n, "meta"))
+        .collect();
+    let collection = metas.iter().find(|m| {
+        m.attribute("property") == Some("belongs-to-collection")
+            && m.attribute("refines").is_none()
+            && parse_refined(m, refinements, "collection-type").is_none_or(|t| t == "series")
+    });
+    if let Some(collection) = collection {
+        let name = parse_text(*collection);
+        if !name.is_empty() {
+            let index = parse_refined(collection, refinements, "group-position")
+                .and_then(|p| p.parse().ok());
+            return (Some(name), index);
+        }
+    }
+    let calibre = |name: &str| {
+        metas
+            .iter()
+            .find(|m| m.attribute("name") == Some(name))
+            .and_then(|m| m.attribute("content"))
+            .map(|c| c.trim().to_owned())
+            .filter(|c| !c.is_empty())
+    };
+    let series = calibre("calibre:series");
+    let index = series
+        .as_ref()
+        .and(calibre("calibre:series_index"))
+        .and_then(|i| i.parse().ok());
+    (series, index)
+}
+
+/// Finds the href of the cover image in the manifest, marked with `properties="cover-image"` in
+/// EPUB 3 and by a `<meta name="cover" content="item_id"/>` in EPUB 2.
+fn parse_cover_href(metadata: Option<Node>, manifest: Option<Node>) -> Option<String> {
+    let items: Vec<Node> = manifest?
+        .children()
+        .filter(|n| is_opf_element(n, "item"))
+        .collect();
+    let item = items
+        .iter()
+        .find(|i| {
+            i.attribute("properties")
+                .is_some_and(|p| p.split_whitespace().any(|p| p == "cover-image"))
+        })
+        .or_else(|| {
+            let cover_id = metadata?
+                .children()
+                .filter(|n| is_opf_element(n, "meta"))
+                .find(|m| m.attribute("name") == Some("cover"))?
+                .attribute("content")?;
+            items.iter().find(|i| i.attribute("id") == Some(cover_id))
+        })?;
+    item.attribute("href").map(|h| h.to_owned())
+}
+
+fn parse_epub_metadata(input: &str) -> Result<(EpubMetadata, Option<String>)> {
+    let document = parse_xml(input)?;
+    let package = document.root_element();
+    let metadata = package.children().find(|n| is_opf_element(n, "metadata"));
+    let manifest = package.children().find(|n| is_opf_element(n, "manifest"));
+    let cover_href = parse_cover_href(metadata, manifest);
+    let Some(metadata) = metadata else {
+        return Ok((EpubMetadata::default(), cover_href));
+    };
+    let refinements = parse_refinements(metadata);
+
+    // the main title is marked as such in EPUB 3, otherwise it is the first one
+    let titles: Vec<Node> = metadata
+        .children()
+        .filter(|n| is_dc_element(n, "title"))
+        .collect();
+    let title = titles
+        .iter()
+        .find(|t| parse_refined(t, &refinements, "title-type").as_deref() == Some("main"))
+        .or(titles.first())
+        .map(|t| parse_text(*t))
+        .filter(|t| !t.is_empty());
+
+    // the package names the identifier that identifies the book
+    let identifiers: Vec<Node> = metadata
+        .children()
+        .filter(|n| is_dc_element(n, "identifier"))
+        .collect();
+    let identifier = identifiers
+        .iter()
+        .find(|i| {
+            package
+                .attribute("unique-identifier")
+                .is_some_and(|id| i.attribute("id") == Some(id))
+        })
+        .or(identifiers.first())
+        .map(|i| parse_text(*i))
+        .filter(|i| !i.is_empty());
+
+    let language = metadata
+        .children()
+        .find(|n| is_dc_element(n, "language"))
+        .map(parse_text)
+        .filter(|l| !l.is_empty());
+
+    // creators are listed in their display sequence if one is given
+    let mut creators: Vec<(Option<u32>, EpubCreator)> = metadata
+        .children()
+        .filter(|n| is_dc_element(n, "creator"))
+        .filter_map(|n| {
+            let name = parse_text(n);
+            (!name.is_empty()).then(|| {
+                (
+                    parse_refined(&n, &refinements, "display-seq").and_then(|s| s.parse().ok()),
+                    EpubCreator {
+                        name,
+                        role: parse_refined(&n, &refinements, "role"),
+                    },
+                )
+            })
+        })
+        .collect();
+    creators.sort_by_key(|(seq, _)| seq.unwrap_or(u32::MAX));
 
-    (
+    // the first identifier of each kind is kept
+    let mut typed: Vec<(EpubIdentifierType, String)> = Vec::new();
+    for node in &identifiers {
+        if let Some((kind, value)) = parse_identifier(node, &refinements)
+            && !typed.iter().any(|(k, _)| *k == kind)
+        {
+            typed.push((kind, value));
+        }
+    }
+    let typed_identifier = |kind: EpubIdentifierType| {
+        typed
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, v)| v.clone())
+    };
+
+    let (series, series_index) = parse_series(metadata, &refinements);
+    Ok((
         EpubMetadata {
-            title: parse_tag(input, "dc:title").ok(),
-            identifier: parse_tag(input, "dc:identifier").ok(),
-            language: parse_tag(input, "dc:language").ok(),
-            creator: parse_tag(input, "dc:creator").ok(),
-            cover: None, // populated later from archive
+            title,
+            identifier,
+            language,
+            creators: creators.into_iter().map(|(_, c)| c).collect(),
+            description: parse_dc_text(metadata, "description"),
+            publisher: parse_dc_text(metadata, "publisher"),
+            date: parse_date(metadata),
+            subjects: parse_subjects(metadata),
+            isbn: typed_identifier(EpubIdentifierType::Isbn),
+            asin: typed_identifier(EpubIdentifierType::Asin),
+            uuid: typed_identifier(EpubIdentifierType::Uuid),
+            series,
+            series_index,
+            cover: None,       // populated later from archive
+            partial_md5: None, // populated later from file
         },
         cover_href,
-    )
+    ))
 }
 
-pub fn parse_epub_file(path: &Path) -> Result<EpubMetadata> {
+/// Resolves an href of a file in the archive to the path of the file it points to. Hrefs are
+/// URLs relative to the file they are in, so they may be percent-encoded and contain dot
+/// segments.
+fn parse_archive_path(base_path: &str, href: &str) -> String {
+    let href = href.split(['#', '?']).next().unwrap_or_default();
+    let href = percent_decode_str(href).decode_utf8_lossy();
+    let mut segments: Vec<&str> = base_path.split('/').collect();
+    segments.pop();
+    for segment in href.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            s => segments.push(s),
+        }
+    }
+    segments.join("/")
+}
+
+/// Opens the archive of an EPUB file, and reads the path and the contents of its OPF file.
+fn open_epub(path: &Path) -> Result<(ZipArchive<File>, String, String)> {
     // open the file as a reader
     let file = File::open(path)?;
     let mut archive = ZipArchive::new(file)?;
@@ -85,33 +476,281 @@ pub fn parse_epub_file(path: &Path) -> Result<EpubMetadata> {
         let mut container = archive.by_name("META-INF/container.xml")?;
         let mut contents = String::new();
         container.read_to_string(&mut contents)?;
-        contents
-            .split("full-path=\"")
-            .nth(1)
-            .and_then(|s| s.split('\"').next())
+        let document = parse_xml(&contents)?;
+        document
+            .descendants()
+            .find(|n| n.is_element() && n.tag_name().name() == "rootfile")
+            .and_then(|n| n.attribute("full-path"))
             .ok_or_else(|| anyhow!("[ERROR] Could not find rootfile path in container.xml"))?
             .to_string()
     };
+    let mut opf_contents = String::new();
+    archive
+        .by_name(&opf_path)?
+        .read_to_string(&mut opf_contents)?;
+    Ok((archive, opf_path, opf_contents))
+}
+
+/// Reads a file of the archive as text.
+fn read_archive_text(archive: &mut ZipArchive<File>, path: &str) -> Result<String> {
+    let mut contents = String::new();
+    archive.by_name(path)?.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+/// Returns the items of the manifest of the OPF file.
+fn parse_manifest(package: Node, opf_path: &str) -> Vec<EpubManifestItem> {
+    package
+        .children()
+        .filter(|n| is_opf_element(n, "manifest"))
+        .flat_map(|m| m.children().filter(|n| is_opf_element(n, "item")))
+        .filter_map(|i| {
+            Some(EpubManifestItem {
+                id: i.attribute("id").unwrap_or_default().to_owned(),
+                path: parse_archive_path(opf_path, i.attribute("href")?),
+                media_type: i
+                    .attribute("media-type")
+                    .unwrap_or("application/octet-stream")
+                    .to_owned(),
+                properties: i
+                    .attribute("properties")
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .map(|p| p.to_owned())
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// Returns the text of a label of the table of contents, on a single line.
+fn parse_label(node: Node) -> String {
+    parse_text(node)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns the entry of the table of contents pointing to the given href.
+fn parse_toc_entry(
+    label: String,
+    href: Option<&str>,
+    base_path: &str,
+    children: Vec<EpubTocEntry>,
+) -> EpubTocEntry {
+    EpubTocEntry {
+        label,
+        path: href.map(|h| parse_archive_path(base_path, h)),
+        fragment: href
+            .and_then(|h| h.split_once('#'))
+            .map(|(_, f)| f.to_owned())
+            .filter(|f| !f.is_empty()),
+        children,
+    }
+}
+
+/// Parses the entries of an `<ol>` of an EPUB 3 navigation document.
+fn parse_nav_list(list: Node, base_path: &str) -> Vec<EpubTocEntry> {
+    list.children()
+        .filter(|n| n.is_element() && n.tag_name().name() == "li")
+        .filter_map(|li| {
+            let link = li
+                .children()
+                .find(|n| n.is_element() && matches!(n.tag_name().name(), "a" | "span"))?;
+            let children = li
+                .children()
+                .find(|n| n.is_element() && n.tag_name().name() == "ol")
+                .map(|ol| parse_nav_list(ol, base_path))
+                .unwrap_or_default();
+            Some(parse_toc_entry(
+                parse_label(link),
+                link.attribute("href"),
+                base_path,
+                children,
+            ))
+        })
+        .collect()
+}
+
+/// Parses the table of contents of an EPUB 3 navigation document, which is the `<nav>` marked
+/// as the toc, or the first one if none is.
+fn parse_nav(input: &str, base_path: &str) -> Result<Vec<EpubTocEntry>> {
+    let document = parse_xml(input)?;
+    let navs: Vec<Node> = document
+        .descendants()
+        .filter(|n| n.is_element() && n.tag_name().name() == "nav")
+        .collect();
+    let nav = navs
+        .iter()
+        .find(|n| {
+            n.attribute((OPS_NAMESPACE, "type"))
+                .is_some_and(|t| t.split_whitespace().any(|t| t == "toc"))
+        })
+        .or(navs.first())
+        .ok_or(anyhow!(
+            "[ERROR] Navigation document has no table of contents"
+        ))?;
+    Ok(nav
+        .children()
+        .find(|n| n.is_element() && n.tag_name().name() == "ol")
+        .map(|ol| parse_nav_list(ol, base_path))
+        .unwrap_or_default())
+}
+
+/// Parses the `<navPoint>`s of an EPUB 2 NCX file.
+fn parse_ncx_points(parent: Node, base_path: &str) -> Vec<EpubTocEntry> {
+    parent
+        .children()
+        .filter(|n| n.is_element() && n.tag_name().name() == "navPoint")
+        .map(|point| {
+            let label = point
+                .children()
+                .find(|n| n.is_element() && n.tag_name().name() == "navLabel")
+                .map(parse_label)
+                .unwrap_or_default();
+            let href = point
+                .children()
+                .find(|n| n.is_element() && n.tag_name().name() == "content")
+                .and_then(|c| c.attribute("src"));
+            parse_toc_entry(label, href, base_path, parse_ncx_points(point, base_path))
+        })
+        .collect()
+}
+
+fn parse_ncx(input: &str, base_path: &str) -> Result<Vec<EpubTocEntry>> {
+    let document = parse_xml(input)?;
+    Ok(document
+        .descendants()
+        .find(|n| n.is_element() && n.tag_name().name() == "navMap")
+        .map(|map| parse_ncx_points(map, base_path))
+        .unwrap_or_default())
+}
+
+pub fn parse_epub_file(path: &Path) -> Result<EpubMetadata> {
+    let (mut archive, opf_path, opf_contents) = open_epub(path)?;
 
     // get the contents of the opf file and parse the metadata from it
-    let (mut metadata, cover_href) = {
-        let mut opf_file = archive.by_name(&opf_path)?;
-        let mut opf_contents = String::new();
-        opf_file.read_to_string(&mut opf_contents)?;
-        parse_epub_metadata(&opf_contents)
-    };
+    let (mut metadata, cover_href) = parse_epub_metadata(&opf_contents)?;
 
     // read cover image if present
-    if let Some(href) = cover_href {
-        let opf_dir = Path::new(&opf_path).parent().unwrap_or(Path::new(""));
-        let cover_path = opf_dir.join(&href).to_string_lossy().to_string();
-        if let Ok(mut cover_file) = archive.by_name(&cover_path) {
-            let mut cover_data = Vec::new();
-            if cover_file.read_to_end(&mut cover_data).is_ok() {
-                metadata.cover = Some(cover_data);
-            }
+    if let Some(href) = cover_href
+        && let Ok(mut cover_file) = archive.by_name(&parse_archive_path(&opf_path, &href))
+    {
+        let mut cover_data = Vec::new();
+        if cover_file.read_to_end(&mut cover_data).is_ok() {
+            metadata.cover = Some(cover_data);
         }
     }
 
+    metadata.partial_md5 = Some(parse_partial_md5(path)?);
+
     Ok(metadata)
 }
+
+/// Computes the partial MD5 digest KOReader identifies documents by, which hashes 1 KiB samples
+/// taken at exponentially growing offsets instead of the whole file.
+pub fn parse_partial_md5(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut context = md5::Context::new();
+    let mut sample = [0u8; 1024];
+
+    // offsets are 0, then 1 KiB multiplied by four each step up to 1 GiB, since KOReader
+    // shifts 1024 by -2 first which LuaJIT turns into 0
+    for i in -1i32..=10 {
+        let offset = if i < 0 { 0 } else { 1024u64 << (2 * i) };
+        file.seek(SeekFrom::Start(offset))?;
+
+        // fill the sample until it is full or the file ends
+        let mut length = 0;
+        while length < sample.len() {
+            let read = file.read(&mut sample[length..])?;
+            if read == 0 {
+                break;
+            }
+            length += read;
+        }
+        if length == 0 {
+            break;
+        }
+        context.consume(&sample[..length]);
+    }
+
+    Ok(format!("{:x}", context.finalize()))
+}
+
+/// Parses the table of contents of an EPUB file, from its EPUB 3 navigation document or else
+/// from its EPUB 2 NCX file.
+pub fn parse_epub_toc(path: &Path) -> Result<EpubToc> {
+    let (mut archive, opf_path, opf_contents) = open_epub(path)?;
+    let document = parse_xml(&opf_contents)?;
+    let package = document.root_element();
+    let manifest = parse_manifest(package, &opf_path);
+
+    // the spine lists the documents of the book in reading order
+    let spine_node = package.children().find(|n| is_opf_element(n, "spine"));
+    let spine: Vec<String> = spine_node
+        .iter()
+        .flat_map(|s| s.children().filter(|n| is_opf_element(n, "itemref")))
+        .filter_map(|r| {
+            let idref = r.attribute("idref")?;
+            manifest
+                .iter()
+                .find(|i| i.id == idref)
+                .map(|i| i.path.clone())
+        })
+        .collect();
+
+    // a navigation document that is not well-formed falls back to the NCX file
+    let mut entries = None;
+    if let Some(nav) = manifest
+        .iter()
+        .find(|i| i.properties.iter().any(|p| p == "nav"))
+    {
+        entries = read_archive_text(&mut archive, &nav.path)
+            .and_then(|contents| parse_nav(&contents, &nav.path))
+            .ok();
+    }
+    if entries.is_none() {
+        let ncx_id = spine_node.and_then(|s| s.attribute("toc"));
+        let ncx = manifest
+            .iter()
+            .find(|i| Some(i.id.as_str()) == ncx_id)
+            .or(manifest
+                .iter()
+                .find(|i| i.media_type == "application/x-dtbncx+xml"))
+            .ok_or(anyhow!("[ERROR] Book has no table of contents"))?;
+        let contents = read_archive_text(&mut archive, &ncx.path)?;
+        entries = Some(parse_ncx(&contents, &ncx.path)?);
+    }
+
+    Ok(EpubToc {
+        entries: entries.unwrap_or_default(),
+        spine,
+    })
+}
+
+/// Reads a resource of an EPUB file, returning its contents and its media type. Only the items
+/// of the manifest can be read, which keeps the container and package files, and anything
+/// outside of the archive, out of reach.
+pub fn read_epub_resource(path: &Path, resource: &str) -> Result<(Vec<u8>, String)> {
+    // the requested path is resolved within the archive, so it can't climb out of it
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in resource.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return Err(anyhow!("[ERROR] Invalid resource path: {}", resource)),
+            s => segments.push(s),
+        }
+    }
+    let resource = segments.join("/");
+
+    let (mut archive, opf_path, opf_contents) = open_epub(path)?;
+    let document = parse_xml(&opf_contents)?;
+    let item = parse_manifest(document.root_element(), &opf_path)
+        .into_iter()
+        .find(|i| i.path == resource)
+        .ok_or(anyhow!("[ERROR] Resource not found in book: {}", resource))?;
+    let mut data = Vec::new();
+    archive.by_name(&item.path)?.read_to_end(&mut data)?;
+    Ok((data, item.media_type))
+}
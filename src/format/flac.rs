@@ -1,13 +1,29 @@
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
 
 use anyhow::{Result, anyhow};
+use claxon::{
+    frame::{Block, FrameReader},
+    input::BufferedReader,
+};
 use nom::{
     IResult,
     bytes::complete::{tag, take},
     number::complete::{be_u8, be_u16, be_u24, be_u32, be_u64, le_u32},
 };
+use sha2::{Digest, Sha256};
 
-use crate::library::track::TrackMetadata;
+use crate::library::track::{ReplayGain, TrackMetadata};
+
+use super::{
+    cue::{CueSheet, CueTrack, parse_cue_sheet},
+    id3::{merge_id3_tags, parse_id3_reader},
+    ogg::OggPacketReader,
+};
 
 #[derive(Debug, Clone)]
 pub enum FlacBlockType {
@@ -33,6 +49,18 @@ impl FlacBlockType {
             _ => None,
         }
     }
+
+    fn to_u8(&self) -> u8 {
+        match self {
+            FlacBlockType::StreamInfo => 0,
+            FlacBlockType::Padding => 1,
+            FlacBlockType::Application => 2,
+            FlacBlockType::SeekTable => 3,
+            FlacBlockType::VorbisComment => 4,
+            FlacBlockType::Cuesheet => 5,
+            FlacBlockType::Picture => 6,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -87,6 +115,32 @@ impl FlacPictureType {
             _ => FlacPictureType::Other,
         }
     }
+
+    fn to_u32(&self) -> u32 {
+        match self {
+            FlacPictureType::Other => 0,
+            FlacPictureType::PngIcon => 1,
+            FlacPictureType::GeneralIcon => 2,
+            FlacPictureType::FrontCover => 3,
+            FlacPictureType::BackCover => 4,
+            FlacPictureType::LinerNotes => 5,
+            FlacPictureType::MediaLabel => 6,
+            FlacPictureType::LeadArtist => 7,
+            FlacPictureType::Artist => 8,
+            FlacPictureType::Conductor => 9,
+            FlacPictureType::Orchestra => 10,
+            FlacPictureType::Composer => 11,
+            FlacPictureType::Lyricist => 12,
+            FlacPictureType::RecordingLocation => 13,
+            FlacPictureType::Recording => 14,
+            FlacPictureType::Performance => 15,
+            FlacPictureType::ScreenCapture => 16,
+            FlacPictureType::BrightlyColoredFish => 17,
+            FlacPictureType::Illustration => 18,
+            FlacPictureType::ArtistLogo => 19,
+            FlacPictureType::PublisherLogo => 20,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -121,11 +175,65 @@ pub struct FlacPicture {
     pub data: Vec<u8>,
 }
 
+/// Guesses the media type of an image from the start of its data, taking JPEG for anything
+/// that isn't PNG, GIF or WebP.
+pub fn parse_picture_media_type(data: &[u8]) -> &'static str {
+    if data.starts_with(b"\x89PNG") {
+        "image/png"
+    } else if data.starts_with(b"GIF8") {
+        "image/gif"
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        "image/webp"
+    } else {
+        "image/jpeg"
+    }
+}
+
+impl FlacPicture {
+    /// Returns a front cover with the given image data, whose media type is guessed from the
+    /// start of the data. The dimensions of the image are left unknown.
+    pub fn front_cover(data: Vec<u8>) -> FlacPicture {
+        let media_type = parse_picture_media_type(&data);
+        FlacPicture {
+            picture_type: FlacPictureType::FrontCover,
+            media_type: media_type.to_owned(),
+            description: String::new(),
+            width: 0,
+            height: 0,
+            color_depth: 0,
+            colors: 0,
+            data,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FlacSeekPoint {
+    pub sample: u64,
+    pub offset: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct FlacMetadata {
     pub stream_info: FlacStreamInfo,
     pub tags: HashMap<String, Vec<String>>,
     pub pictures: Vec<FlacPicture>,
+    pub cue_sheet: Option<CueSheet>,
+    pub seek_table: Vec<FlacSeekPoint>,
+    pub audio_offset: Option<u64>,
+}
+
+impl FlacMetadata {
+    /// Returns the cue sheet of the file, if the file is a rip of a whole album that should be
+    /// split into tracks. A cue sheet stored in the CUESHEET tag is preferred over the
+    /// CUESHEET block, as only the former contains track titles and performers.
+    pub fn get_cue_sheet(&self) -> Option<CueSheet> {
+        self.tags
+            .get("CUESHEET")
+            .and_then(|v| parse_cue_sheet(&v[0], self.stream_info.sample_rate).ok())
+            .or_else(|| self.cue_sheet.clone())
+            .filter(|sheet| sheet.tracks.len() > 1)
+    }
 }
 
 impl TrackMetadata for FlacMetadata {
@@ -189,6 +297,64 @@ impl TrackMetadata for FlacMetadata {
             return None;
         }
     }
+
+    fn get_replay_gain(&self) -> ReplayGain {
+        // ReplayGain values look like "-6.54 dB" and "0.988553"
+        let value = |key: &str| {
+            self.tags.get(key).and_then(|v| {
+                v[0].trim()
+                    .trim_end_matches("dB")
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+            })
+        };
+
+        // R128 gains are Q7.8 integers relative to -23 LUFS instead of -18 LUFS
+        let r128 = |key: &str| {
+            self.tags
+                .get(key)
+                .and_then(|v| v[0].trim().parse::<i16>().ok())
+                .map(|g| g as f64 / 256.0 + 5.0)
+        };
+        ReplayGain {
+            track_gain: value("REPLAYGAIN_TRACK_GAIN").or_else(|| r128("R128_TRACK_GAIN")),
+            track_peak: value("REPLAYGAIN_TRACK_PEAK"),
+            album_gain: value("REPLAYGAIN_ALBUM_GAIN").or_else(|| r128("R128_ALBUM_GAIN")),
+            album_peak: value("REPLAYGAIN_ALBUM_PEAK"),
+        }
+    }
+
+    fn get_lyrics(&self) -> Vec<String> {
+        // synced lyrics are usually stored in LRC format in LYRICS
+        ["LYRICS", "UNSYNCEDLYRICS"]
+            .iter()
+            .filter_map(|key| self.tags.get(*key))
+            .flatten()
+            .filter(|text| !text.trim().is_empty())
+            .cloned()
+            .collect()
+    }
+
+    fn get_fingerprint(&self) -> Option<String> {
+        // the audio checksum identifies the recording, and the tags tell apart releases of it
+        let mut keys: Vec<&String> = self.tags.keys().collect();
+        keys.sort();
+        let mut hasher = Sha256::new();
+        for key in keys {
+            hasher.update(key.as_bytes());
+            for value in &self.tags[key] {
+                hasher.update([0u8]);
+                hasher.update(value.as_bytes());
+            }
+            hasher.update([1u8]);
+        }
+        Some(format!(
+            "flac:{}:{}",
+            hex::encode(self.stream_info.checksum),
+            hex::encode(hasher.finalize())
+        ))
+    }
 }
 
 fn parse_flac_marker(input: &[u8]) -> IResult<&[u8], &[u8]> {
@@ -220,8 +386,8 @@ fn parse_streaminfo(input: &[u8]) -> IResult<&[u8], FlacStreamInfo> {
     // next 8 bytes contain sample rate (20), channels (3), bps (5), total samples (36)
     let (input, data) = be_u64(input)?;
     let sample_rate = (data >> 44) as u32;
-    let channels = ((data >> 41) & 0x7) as u8;
-    let bps = ((data >> 36) & 0x1F) as u8;
+    let channels = ((data >> 41) & 0x7) as u8 + 1;
+    let bps = ((data >> 36) & 0x1F) as u8 + 1;
     let total_samples = data & 0xFFFFFFFFF;
 
     // final 16 bytes contain an md5 checksum of the audio data
@@ -259,22 +425,39 @@ fn parse_vorbis_field(input: &[u8]) -> IResult<&[u8], (String, String)> {
     }
 }
 
-fn parse_vorbis_comments(input: &[u8]) -> IResult<&[u8], HashMap<String, Vec<String>>> {
+/// The fields of a VORBIS_COMMENT block in the order in which they are stored, along with the
+/// vendor string of the encoder.
+struct VorbisComments {
+    vendor: String,
+    fields: Vec<(String, String)>,
+}
+
+fn parse_vorbis_block(input: &[u8]) -> IResult<&[u8], VorbisComments> {
     // we have the size of the vendor string, the vendor, and the number of fields
     let (input, vendor_size) = le_u32(input)?;
-    let (input, _vendor_string) = take(vendor_size)(input)?;
-    let (input, fields) = le_u32(input)?;
+    let (input, vendor_bytes) = take(vendor_size)(input)?;
+    let vendor = String::from_utf8_lossy(vendor_bytes).to_string();
+    let (input, count) = le_u32(input)?;
 
     // iterate over the number of fields and parse them
     let mut rest = input;
+    let mut fields = Vec::new();
+    for _ in 0..count {
+        let (new_rest, field) = parse_vorbis_field(rest)?;
+        fields.push(field);
+        rest = new_rest;
+    }
+    Ok((rest, VorbisComments { vendor, fields }))
+}
+
+fn parse_vorbis_comments(input: &[u8]) -> IResult<&[u8], HashMap<String, Vec<String>>> {
+    let (rest, block) = parse_vorbis_block(input)?;
     let mut comments: HashMap<String, Vec<String>> = HashMap::new();
-    for _ in 0..fields {
-        let (new_rest, (key, value)) = parse_vorbis_field(rest)?;
+    for (key, value) in block.fields {
         comments
             .entry(key)
             .or_insert_with(Vec::new)
             .push(value.trim().to_string());
-        rest = new_rest;
     }
     Ok((rest, comments))
 }
@@ -316,60 +499,968 @@ fn parse_picture(input: &[u8]) -> IResult<&[u8], FlacPicture> {
     ))
 }
 
-fn parse_flac_metadata(input: &[u8]) -> IResult<&[u8], FlacMetadata> {
-    // parse "fLaC" marker and setup parsing output
-    let (mut input, _) = parse_flac_marker(input)?;
-    let mut stream_info: Option<FlacStreamInfo> = None;
-    let mut tags: HashMap<String, Vec<String>> = HashMap::new();
-    let mut pictures: Vec<FlacPicture> = Vec::new();
+fn parse_seektable(input: &[u8]) -> IResult<&[u8], Vec<FlacSeekPoint>> {
+    // each seek point is the first sample of a frame, its offset from the first frame, and the
+    // number of samples in the frame
+    let mut input = input;
+    let mut points = Vec::new();
+    while input.len() >= 18 {
+        let (rest, sample) = be_u64(input)?;
+        let (rest, offset) = be_u64(rest)?;
+        let (rest, _samples) = be_u16(rest)?;
 
-    // loop through metadata blocks until we hit the last block
-    loop {
-        let (rest, header) = parse_metadata_header(input)?;
-        match header.block_type {
+        // placeholder points are reserved for later use and do not point to a frame
+        if sample != u64::MAX {
+            points.push(FlacSeekPoint { sample, offset });
+        }
+        input = rest;
+    }
+    Ok((input, points))
+}
+
+fn parse_cuesheet_track(input: &[u8]) -> IResult<&[u8], Option<CueTrack>> {
+    // track offset, number, isrc, flags and reserved bytes, and the number of index points
+    let (input, offset) = be_u64(input)?;
+    let (input, number) = be_u8(input)?;
+    let (input, _isrc) = take(12usize)(input)?;
+    let (input, _flags) = take(14usize)(input)?;
+    let (input, index_count) = be_u8(input)?;
+
+    // the track starts at index point 1, or at its first index point if there is none
+    let mut input = input;
+    let mut start = None;
+    for _ in 0..index_count {
+        let (rest, index_offset) = be_u64(input)?;
+        let (rest, index_number) = be_u8(rest)?;
+        let (rest, _reserved) = take(3usize)(rest)?;
+        if index_number == 1 || start.is_none() {
+            start = Some(offset + index_offset);
+        }
+        input = rest;
+    }
+
+    // the lead-out track marks the end of the last track and has no index points
+    let track = match start {
+        Some(start_sample) if number != 170 && number != 255 => Some(CueTrack {
+            number: number as u32,
+            title: None,
+            performer: None,
+            file: None,
+            start_sample,
+        }),
+        _ => None,
+    };
+    Ok((input, track))
+}
+
+fn parse_cuesheet(input: &[u8]) -> IResult<&[u8], CueSheet> {
+    // media catalog number, lead-in samples, flags and reserved bytes, and number of tracks
+    let (input, _catalog) = take(128usize)(input)?;
+    let (input, _lead_in) = be_u64(input)?;
+    let (input, _flags) = take(259usize)(input)?;
+    let (input, track_count) = be_u8(input)?;
+
+    let mut input = input;
+    let mut tracks = Vec::new();
+    for _ in 0..track_count {
+        let (rest, track) = parse_cuesheet_track(input)?;
+        tracks.extend(track);
+        input = rest;
+    }
+    Ok((
+        input,
+        CueSheet {
+            title: None,
+            performer: None,
+            tracks,
+        },
+    ))
+}
+
+fn write_metadata_header(is_last: bool, block_type: u8, block_size: usize) -> Result<[u8; 4]> {
+    if block_size >= 1 << 24 {
+        return Err(anyhow!("[ERROR] FLAC metadata block is too large"));
+    }
+    let size = (block_size as u32).to_be_bytes();
+    let first = if is_last {
+        block_type | 0x80
+    } else {
+        block_type
+    };
+    Ok([first, size[1], size[2], size[3]])
+}
+
+fn write_streaminfo(info: &FlacStreamInfo) -> Vec<u8> {
+    let packed = ((info.sample_rate as u64) << 44)
+        | (((info.channels - 1) as u64) << 41)
+        | (((info.bps - 1) as u64) << 36)
+        | info.total_samples;
+    let mut block = Vec::with_capacity(34);
+    block.extend_from_slice(&info.min_block_size.to_be_bytes());
+    block.extend_from_slice(&info.max_block_size.to_be_bytes());
+    block.extend_from_slice(&info.min_frame_size.to_be_bytes()[1..]);
+    block.extend_from_slice(&info.max_frame_size.to_be_bytes()[1..]);
+    block.extend_from_slice(&packed.to_be_bytes());
+    block.extend_from_slice(&info.checksum);
+    block
+}
+
+fn write_vorbis_comments(comments: &VorbisComments) -> Vec<u8> {
+    let mut block = Vec::new();
+    block.extend_from_slice(&(comments.vendor.len() as u32).to_le_bytes());
+    block.extend_from_slice(comments.vendor.as_bytes());
+    block.extend_from_slice(&(comments.fields.len() as u32).to_le_bytes());
+    for (key, value) in &comments.fields {
+        let field = format!("{}={}", key, value);
+        block.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        block.extend_from_slice(field.as_bytes());
+    }
+    block
+}
+
+fn write_picture(picture: &FlacPicture) -> Vec<u8> {
+    let mut block = Vec::with_capacity(32 + picture.data.len());
+    block.extend_from_slice(&picture.picture_type.to_u32().to_be_bytes());
+    block.extend_from_slice(&(picture.media_type.len() as u32).to_be_bytes());
+    block.extend_from_slice(picture.media_type.as_bytes());
+    block.extend_from_slice(&(picture.description.len() as u32).to_be_bytes());
+    block.extend_from_slice(picture.description.as_bytes());
+    block.extend_from_slice(&picture.width.to_be_bytes());
+    block.extend_from_slice(&picture.height.to_be_bytes());
+    block.extend_from_slice(&picture.color_depth.to_be_bytes());
+    block.extend_from_slice(&picture.colors.to_be_bytes());
+    block.extend_from_slice(&(picture.data.len() as u32).to_be_bytes());
+    block.extend_from_slice(&picture.data);
+    block
+}
+
+/// Serializes metadata blocks, followed by a padding block of the given size if there is one.
+fn write_metadata_blocks(blocks: &[(u8, Vec<u8>)], padding: Option<usize>) -> Result<Vec<u8>> {
+    let mut metadata = Vec::new();
+    for (i, (block_type, block)) in blocks.iter().enumerate() {
+        let is_last = padding.is_none() && i == blocks.len() - 1;
+        metadata.extend_from_slice(&write_metadata_header(is_last, *block_type, block.len())?);
+        metadata.extend_from_slice(block);
+    }
+    if let Some(size) = padding {
+        let header = write_metadata_header(true, FlacBlockType::Padding.to_u8(), size)?;
+        metadata.extend_from_slice(&header);
+        metadata.resize(metadata.len() + size, 0);
+    }
+    Ok(metadata)
+}
+
+/// Collects the metadata blocks of a FLAC stream as they are read.
+#[derive(Default)]
+struct FlacMetadataBuilder {
+    stream_info: Option<FlacStreamInfo>,
+    tags: HashMap<String, Vec<String>>,
+    pictures: Vec<FlacPicture>,
+    cue_sheet: Option<CueSheet>,
+    seek_table: Vec<FlacSeekPoint>,
+}
+
+impl FlacMetadataBuilder {
+    /// Returns whether the contents of blocks of the given type are needed.
+    fn wants(block_type: &Option<FlacBlockType>, pictures: bool) -> bool {
+        match block_type {
+            Some(FlacBlockType::StreamInfo)
+            | Some(FlacBlockType::VorbisComment)
+            | Some(FlacBlockType::SeekTable)
+            | Some(FlacBlockType::Cuesheet) => true,
+            Some(FlacBlockType::Picture) => pictures,
+            _ => false,
+        }
+    }
+
+    /// Parses the contents of a metadata block of the given type.
+    fn add_block(&mut self, block_type: &Option<FlacBlockType>, block: &[u8]) -> Result<()> {
+        match block_type {
             Some(FlacBlockType::StreamInfo) => {
-                let (rest, info) = parse_streaminfo(rest)?;
-                stream_info = Some(info);
-                input = rest;
+                let (_, info) = parse_streaminfo(block)
+                    .map_err(|e| anyhow!("[ERROR] Failed to parse FLAC STREAMINFO: {:?}", e))?;
+                self.stream_info = Some(info);
             }
             Some(FlacBlockType::VorbisComment) => {
-                let (rest, comments) = parse_vorbis_comments(rest)?;
-                tags = comments;
-                input = rest;
+                let (_, comments) = parse_vorbis_comments(block)
+                    .map_err(|e| anyhow!("[ERROR] Failed to parse FLAC VORBIS_COMMENT: {:?}", e))?;
+                self.tags = comments;
             }
             Some(FlacBlockType::Picture) => {
-                let (rest, picture) = parse_picture(rest)?;
-                pictures.push(picture);
-                input = rest;
+                let (_, picture) = parse_picture(block)
+                    .map_err(|e| anyhow!("[ERROR] Failed to parse FLAC PICTURE: {:?}", e))?;
+                self.pictures.push(picture);
             }
-            _ => {
-                let (rest, _) = take(header.block_size)(rest)?;
-                input = rest;
+            Some(FlacBlockType::SeekTable) => {
+                let (_, seek_table) = parse_seektable(block)
+                    .map_err(|e| anyhow!("[ERROR] Failed to parse FLAC SEEKTABLE: {:?}", e))?;
+                self.seek_table = seek_table;
             }
+            Some(FlacBlockType::Cuesheet) => {
+                let (_, cue_sheet) = parse_cuesheet(block)
+                    .map_err(|e| anyhow!("[ERROR] Failed to parse FLAC CUESHEET: {:?}", e))?;
+                self.cue_sheet = Some(cue_sheet);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn build(self) -> Result<FlacMetadata> {
+        // error if there is no stream info
+        let stream_info = self
+            .stream_info
+            .ok_or_else(|| anyhow!("[ERROR] FLAC file must contain STREAMINFO"))?;
+
+        // runtimes are computed by dividing by the sample rate
+        if stream_info.sample_rate == 0 {
+            return Err(anyhow!("[ERROR] FLAC STREAMINFO has a sample rate of 0"));
+        }
+        Ok(FlacMetadata {
+            stream_info,
+            tags: self.tags,
+            pictures: self.pictures,
+            cue_sheet: self.cue_sheet,
+            seek_table: self.seek_table,
+            audio_offset: None,
+        })
+    }
+}
+
+/// Reads the metadata blocks of a native FLAC stream, starting right after the "fLaC" marker.
+fn parse_native_blocks<R: Read + Seek>(
+    reader: &mut R,
+    pictures: bool,
+    builder: &mut FlacMetadataBuilder,
+) -> Result<()> {
+    // loop through metadata blocks until we hit the last block
+    loop {
+        let mut header_bytes = [0u8; 4];
+        reader.read_exact(&mut header_bytes)?;
+        let (_, header) = parse_metadata_header(&header_bytes)
+            .map_err(|e| anyhow!("[ERROR] Failed to parse FLAC block header: {:?}", e))?;
+        if FlacMetadataBuilder::wants(&header.block_type, pictures) {
+            let block = read_block(reader, header.block_size)?;
+            builder.add_block(&header.block_type, &block)?;
+        } else {
+            reader.seek(SeekFrom::Current(header.block_size as i64))?;
+        }
+        if header.is_last {
+            return Ok(());
+        }
+    }
+}
+
+fn parse_ogg_mapping_header(input: &[u8]) -> IResult<&[u8], u16> {
+    // packet type, signature, mapping version and the number of header packets
+    let (input, _) = tag(&b"\x7fFLAC"[..])(input)?;
+    let (input, _major_version) = be_u8(input)?;
+    let (input, _minor_version) = be_u8(input)?;
+    let (input, header_packets) = be_u16(input)?;
+    let (input, _) = parse_flac_marker(input)?;
+    Ok((input, header_packets))
+}
+
+/// Reads the metadata blocks of an Ogg encapsulated FLAC stream. The first packet holds the
+/// mapping header followed by STREAMINFO, and each following header packet holds exactly one
+/// metadata block.
+fn parse_ogg_blocks<R: Read>(
+    reader: R,
+    pictures: bool,
+    builder: &mut FlacMetadataBuilder,
+) -> Result<()> {
+    let mut packets = OggPacketReader::new(reader);
+    let first = packets
+        .next_packet()?
+        .ok_or_else(|| anyhow!("[ERROR] Ogg container is empty"))?;
+    let (block, _) = parse_ogg_mapping_header(&first)
+        .map_err(|_| anyhow!("[ERROR] Ogg container does not hold a FLAC stream"))?;
+    let mut packet = block.to_vec();
+    loop {
+        let (block, header) = parse_metadata_header(&packet)
+            .map_err(|e| anyhow!("[ERROR] Failed to parse FLAC block header: {:?}", e))?;
+        if FlacMetadataBuilder::wants(&header.block_type, pictures) {
+            let size = (header.block_size as usize).min(block.len());
+            builder.add_block(&header.block_type, &block[..size])?;
+        }
+        if header.is_last {
+            return Ok(());
+        }
+        match packets.next_packet()? {
+            Some(next) => packet = next,
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Reads the metadata blocks of a FLAC stream from the start of the given reader, without
+/// reading the audio frames that follow them. PADDING and APPLICATION blocks are skipped by
+/// seeking, as are PICTURE blocks unless `pictures` is set. Native FLAC
+/// streams may be preceded by an ID3v2 tag, whose fields are merged into the tags, and Ogg
+/// encapsulated FLAC streams are supported as well.
+///
+/// The reader is used synchronously, so from async code this should be called inside
+/// spawn_blocking(), wrapping async readers with tokio_util's SyncIoBridge if needed.
+pub fn parse_flac_reader<R: Read + Seek>(reader: &mut R, pictures: bool) -> Result<FlacMetadata> {
+    let (metadata, _) = parse_flac_stream(reader, pictures)?;
+    Ok(metadata)
+}
+
+/// Reads the metadata blocks of a FLAC stream like parse_flac_reader(), also returning whether
+/// the stream is Ogg encapsulated. The reader is left at the first audio frame, or at the first
+/// audio page for Ogg encapsulated streams.
+fn parse_flac_stream<R: Read + Seek>(
+    reader: &mut R,
+    pictures: bool,
+) -> Result<(FlacMetadata, bool)> {
+    let id3_tags = parse_id3_reader(reader)?;
+    let mut builder = FlacMetadataBuilder::default();
+    let mut marker = [0u8; 4];
+    reader.read_exact(&mut marker)?;
+    let ogg = &marker == b"OggS";
+    if ogg {
+        reader.seek(SeekFrom::Current(-4))?;
+        parse_ogg_blocks(&mut *reader, pictures, &mut builder)?;
+    } else {
+        parse_flac_marker(&marker)
+            .map_err(|_| anyhow!("[ERROR] Failed to parse FLAC file: missing fLaC marker"))?;
+        parse_native_blocks(reader, pictures, &mut builder)?;
+    }
+
+    let mut metadata = builder.build()?;
+    if !ogg {
+        metadata.audio_offset = Some(reader.stream_position()?);
+    }
+    if let Some(id3_tags) = id3_tags {
+        merge_id3_tags(&mut metadata.tags, id3_tags);
+    }
+    Ok((metadata, ogg))
+}
+
+/// Reads the contents of a metadata block of the given size.
+fn read_block<R: Read>(reader: &mut R, size: u32) -> Result<Vec<u8>> {
+    let mut block = vec![0u8; size as usize];
+    reader.read_exact(&mut block)?;
+    Ok(block)
+}
+
+pub fn parse_flac_file(path: &Path, pictures: bool) -> Result<FlacMetadata> {
+    let mut reader = BufReader::new(File::open(path)?);
+    parse_flac_reader(&mut reader, pictures)
+}
+
+/// Size of the padding left after the metadata blocks when a file is rewritten, so that later
+/// edits of its tags fit in place.
+const FLAC_PADDING: usize = 8192;
+
+/// Vendor string of VORBIS_COMMENT blocks written to files that did not have one.
+const FLAC_VENDOR: &str = "harmony";
+
+/// Changes to the tags and pictures of a FLAC file. Each tag replaces all the fields with its
+/// name, regardless of case, and tags without any values are removed. A front cover replaces
+/// the front covers of the file, and its other pictures are kept.
+#[derive(Clone)]
+pub struct FlacTagEdit {
+    pub tags: Vec<(String, Vec<String>)>,
+    pub front_cover: Option<FlacPicture>,
+}
+
+/// Writes the given changes to the tags and pictures of a native FLAC file. The metadata blocks
+/// are rewritten in place if they fit into the space of the old blocks and their padding, and
+/// the file is otherwise copied into a temporary file that then replaces it. An ID3v2 tag in
+/// front of the stream is kept as it is.
+pub fn write_flac_tags(path: &Path, edit: &FlacTagEdit) -> Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    parse_id3_reader(&mut reader)?;
+    let start = reader.stream_position()?;
+    let mut marker = [0u8; 4];
+    reader.read_exact(&mut marker)?;
+    if &marker == b"OggS" {
+        return Err(anyhow!(
+            "[ERROR] Tags of Ogg encapsulated FLAC cannot be written"
+        ));
+    }
+    parse_flac_marker(&marker)
+        .map_err(|_| anyhow!("[ERROR] Failed to parse FLAC file: missing fLaC marker"))?;
+
+    // read every metadata block except for padding, keeping blocks of unknown types as well
+    let mut blocks: Vec<(u8, Vec<u8>)> = Vec::new();
+    loop {
+        let mut header_bytes = [0u8; 4];
+        reader.read_exact(&mut header_bytes)?;
+        let (_, header) = parse_metadata_header(&header_bytes)
+            .map_err(|e| anyhow!("[ERROR] Failed to parse FLAC block header: {:?}", e))?;
+        if let Some(FlacBlockType::Padding) = header.block_type {
+            reader.seek(SeekFrom::Current(header.block_size as i64))?;
+        } else {
+            blocks.push((
+                header_bytes[0] & 0x7f,
+                read_block(&mut reader, header.block_size)?,
+            ));
         }
         if header.is_last {
             break;
         }
     }
+    let audio_offset = reader.stream_position()?;
+
+    // apply the changes to the vorbis comments and the front covers
+    let comment_type = FlacBlockType::VorbisComment.to_u8();
+    let picture_type = FlacBlockType::Picture.to_u8();
+    let mut comments = match blocks.iter().find(|(t, _)| *t == comment_type) {
+        Some((_, block)) => {
+            parse_vorbis_block(block)
+                .map_err(|e| anyhow!("[ERROR] Failed to parse FLAC VORBIS_COMMENT: {:?}", e))?
+                .1
+        }
+        None => VorbisComments {
+            vendor: FLAC_VENDOR.to_owned(),
+            fields: Vec::new(),
+        },
+    };
+    for (key, values) in &edit.tags {
+        comments
+            .fields
+            .retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+        comments
+            .fields
+            .extend(values.iter().map(|v| (key.clone(), v.clone())));
+    }
+    blocks.retain(|(t, block)| {
+        *t != comment_type
+            && !(*t == picture_type
+                && edit.front_cover.is_some()
+                && parse_picture(block)
+                    .is_ok_and(|(_, p)| p.picture_type == FlacPictureType::FrontCover))
+    });
+
+    // STREAMINFO stays the first block, followed by the comments and the new front cover
+    let mut position = 1.min(blocks.len());
+    blocks.insert(position, (comment_type, write_vorbis_comments(&comments)));
+    if let Some(picture) = &edit.front_cover {
+        position += 1;
+        blocks.insert(position, (picture_type, write_picture(picture)));
+    }
+
+    // reuse the old space if the blocks fit exactly, or with room for a padding block
+    let size: usize = blocks.iter().map(|(_, block)| 4 + block.len()).sum();
+    let available = (audio_offset - start - 4) as usize;
+    let in_place = match available.checked_sub(size) {
+        Some(0) => Some(None),
+        Some(free) if free >= 4 && free - 4 < 1 << 24 => Some(Some(free - 4)),
+        _ => None,
+    };
+    if let Some(padding) = in_place {
+        let metadata = write_metadata_blocks(&blocks, padding)?;
+        drop(reader);
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.seek(SeekFrom::Start(start + 4))?;
+        file.write_all(&metadata)?;
+        file.sync_all()?;
+        return Ok(());
+    }
+    let metadata = write_metadata_blocks(&blocks, Some(FLAC_PADDING))?;
+
+    // otherwise copy the file with the new metadata, and replace the old file with the copy
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow!("[ERROR] Invalid FLAC file path"))?;
+    let temp_path = path.with_file_name(format!(".{}.tmp", name));
+    let copy = |reader: &mut BufReader<File>| -> Result<()> {
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        reader.seek(SeekFrom::Start(0))?;
+        std::io::copy(&mut reader.by_ref().take(start + 4), &mut writer)?;
+        writer.write_all(&metadata)?;
+        reader.seek(SeekFrom::Start(audio_offset))?;
+        std::io::copy(reader, &mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.set_permissions(fs::metadata(path)?.permissions())?;
+        file.sync_all()?;
+        Ok(())
+    };
+    if let Err(e) = copy(&mut reader) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &b in data {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Parses the header of the frame at the start of the given bytes, returning the number of its
+/// first sample. Returns None if the bytes do not start with a valid frame header, which is how
+/// frames are told apart from sync codes that happen to appear in the audio data.
+fn parse_frame_header(bytes: &[u8], info: &FlacStreamInfo) -> Option<u64> {
+    // sync code, reserved bit and blocking strategy
+    if bytes.len() < 6 || bytes[0] != 0xff || bytes[1] & 0xfe != 0xf8 {
+        return None;
+    }
+    let variable = bytes[1] & 0x01 != 0;
+    let block_size_code = bytes[2] >> 4;
+    let sample_rate_code = bytes[2] & 0x0f;
+    let channels = bytes[3] >> 4;
+    if block_size_code == 0 || sample_rate_code == 15 || channels > 10 || bytes[3] & 0x01 != 0 {
+        return None;
+    }
+
+    // frame or sample number, coded like a UTF-8 character of up to 7 bytes
+    let length = bytes[4].leading_ones() as usize;
+    if length == 1 || length > 7 || bytes.len() < 4 + length.max(1) {
+        return None;
+    }
+    let mut number = if length == 0 {
+        bytes[4] as u64
+    } else {
+        (bytes[4] & (0x7f >> length)) as u64
+    };
+    for &b in &bytes[5..4 + length.max(1)] {
+        if b & 0xc0 != 0x80 {
+            return None;
+        }
+        number = (number << 6) | (b & 0x3f) as u64;
+    }
+
+    // block size and sample rate stored at the end of the header, then the header checksum
+    let mut end = 4 + length.max(1);
+    end += match block_size_code {
+        6 => 1,
+        7 => 2,
+        _ => 0,
+    };
+    end += match sample_rate_code {
+        12 => 1,
+        13 | 14 => 2,
+        _ => 0,
+    };
+    if bytes.len() <= end || crc8(&bytes[..end]) != bytes[end] {
+        return None;
+    }
+    if variable {
+        Some(number)
+    } else {
+        Some(number * info.max_block_size as u64)
+    }
+}
+
+/// Returns whether a whole frame can be decoded at the given offset, so that its CRC-16 footer
+/// matches. This rules out the sync codes in the audio data whose bytes happen to form a valid
+/// frame header. The position of the reader is left as it was.
+fn frame_decodes<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    info: &FlacStreamInfo,
+) -> Result<bool> {
+    let position = reader.stream_position()?;
+    reader.seek(SeekFrom::Start(offset))?;
 
-    // error if there is no stream info
-    let stream_info = stream_info.ok_or_else(|| {
-        nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify))
+    // a frame is at most max_frame_size long, which is 0 if unknown
+    let limit = match info.max_frame_size {
+        0 => 1024 * 1024,
+        size => size as u64,
+    };
+    let mut frames = FrameReader::new(BufferedReader::new(reader.by_ref().take(limit)));
+    let decodes = matches!(frames.read_next_or_eof(Vec::new()), Ok(Some(_)));
+    reader.seek(SeekFrom::Start(position))?;
+    Ok(decodes)
+}
+
+/// Searches the audio frames forward from the given offset, calling `visit` with the offset and
+/// first sample of every frame found until it returns false. Only frames that decode are
+/// visited.
+fn search_frames<R: Read + Seek>(
+    reader: &mut R,
+    from: u64,
+    info: &FlacStreamInfo,
+    mut visit: impl FnMut(u64, u64) -> bool,
+) -> Result<()> {
+    const CHUNK: usize = 64 * 1024;
+    const HEADER: usize = 16;
+    reader.seek(SeekFrom::Start(from))?;
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut buffer_offset = from;
+    loop {
+        let start = buffer.len();
+        buffer.resize(start + CHUNK, 0);
+        let read = reader.read(&mut buffer[start..])?;
+        buffer.truncate(start + read);
+
+        // headers close to the end of the buffer are checked once more data has been read
+        let eof = read == 0;
+        let limit = if eof {
+            buffer.len()
+        } else {
+            buffer.len().saturating_sub(HEADER)
+        };
+        for i in 0..limit {
+            if buffer[i] == 0xff
+                && let Some(sample) = parse_frame_header(&buffer[i..], info)
+                && frame_decodes(reader, buffer_offset + i as u64, info)?
+                && !visit(buffer_offset + i as u64, sample)
+            {
+                return Ok(());
+            }
+        }
+        if eof {
+            return Ok(());
+        }
+        buffer.drain(..limit);
+        buffer_offset += limit as u64;
+    }
+}
+
+/// Finds the frame that contains the given sample, using the seek table if there is one, or a
+/// binary search for frame headers otherwise. Returns the offset and first sample of the frame.
+fn find_frame<R: Read + Seek>(
+    reader: &mut R,
+    metadata: &FlacMetadata,
+    sample: u64,
+) -> Result<Option<(u64, u64)>> {
+    let info = &metadata.stream_info;
+    let Some(audio_offset) = metadata.audio_offset else {
+        return Ok(None);
+    };
+
+    // start from the closest seek point before the sample
+    let mut from = audio_offset;
+    if let Some(point) = metadata.seek_table.iter().rfind(|p| p.sample <= sample) {
+        from = audio_offset + point.offset;
+    } else if metadata.seek_table.is_empty() {
+        // narrow down the range by looking at the first frame after the middle
+        let mut low = audio_offset;
+        let mut high = reader.seek(SeekFrom::End(0))?;
+        while high - low > 64 * 1024 {
+            let middle = low + (high - low) / 2;
+            let mut found = None;
+            search_frames(reader, middle, info, |offset, first| {
+                found = Some((offset, first));
+                false
+            })?;
+            match found {
+                Some((offset, first)) if first <= sample => low = offset,
+                _ => high = middle,
+            }
+        }
+        from = low;
+    }
+
+    // then walk the frames up to the one containing the sample, skipping any frame whose
+    // number does not follow the previous one
+    let mut frame: Option<(u64, u64)> = None;
+    search_frames(reader, from, info, |offset, first| {
+        if let Some((_, previous)) = frame {
+            if first <= previous {
+                return true;
+            }
+            if first > sample {
+                return false;
+            }
+        }
+        frame = Some((offset, first));
+        true
     })?;
+    Ok(frame)
+}
 
-    Ok((
-        input,
-        FlacMetadata {
-            stream_info,
-            tags,
-            pictures,
-        },
-    ))
+/// Opens a FLAC file to be served from the frame containing the given sample. Returns the start
+/// of a valid FLAC stream, a STREAMINFO block describing the remaining audio, along with a
+/// reader positioned at the frame, from which the rest of the stream is copied. The MD5
+/// checksum of the audio is cleared, as it only applies to the whole stream.
+pub fn open_flac_from(path: &Path, sample: u64) -> Result<(Vec<u8>, BufReader<File>)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let (metadata, ogg) = parse_flac_stream(&mut reader, false)?;
+    if ogg {
+        return Err(anyhow!("[ERROR] Ogg encapsulated FLAC cannot be cut"));
+    }
+    let (offset, first) = find_frame(&mut reader, &metadata, sample)?
+        .ok_or_else(|| anyhow!("[ERROR] FLAC file does not contain any frames"))?;
+
+    // rewrite STREAMINFO as the only metadata block
+    let info = FlacStreamInfo {
+        total_samples: metadata.stream_info.total_samples.saturating_sub(first),
+        checksum: [0u8; 16],
+        ..metadata.stream_info
+    };
+    let block = write_streaminfo(&info);
+    let mut header = b"fLaC".to_vec();
+    header.extend_from_slice(&write_metadata_header(
+        true,
+        FlacBlockType::StreamInfo.to_u8(),
+        block.len(),
+    )?);
+    header.extend_from_slice(&block);
+    reader.seek(SeekFrom::Start(offset))?;
+    Ok((header, reader))
 }
 
-pub fn parse_flac_file(path: &Path) -> Result<FlacMetadata> {
-    let data = std::fs::read(path)?;
-    let (_, metadata) = parse_flac_metadata(&data)
-        .map_err(|e| anyhow!("[ERROR] Failed to parse FLAC file: {:?}", e))?;
-    Ok(metadata)
+/// Decodes the audio frames of a FLAC file, in either the native or the Ogg container.
+pub struct FlacDecoder {
+    frames: FrameReader<BufferedReader<Box<dyn Read + Send>>>,
+    block: Block,
+    position: u64,
+    pub metadata: FlacMetadata,
+}
+
+impl FlacDecoder {
+    /// Opens the file at the given path and reads its metadata, without pictures. Decoding
+    /// starts at the frame containing the given sample if the frame can be found, or at the
+    /// start of the stream otherwise.
+    pub fn open_at(path: &Path, sample: u64) -> Result<FlacDecoder> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let (metadata, ogg) = parse_flac_stream(&mut reader, false)?;
+        let mut position = 0;
+        if sample > 0
+            && let Some((offset, first)) = find_frame(&mut reader, &metadata, sample)?
+        {
+            reader.seek(SeekFrom::Start(offset))?;
+            position = first;
+        } else if let Some(audio_offset) = metadata.audio_offset {
+            reader.seek(SeekFrom::Start(audio_offset))?;
+        }
+        let reader: Box<dyn Read + Send> = if ogg {
+            Box::new(OggPacketReader::new(reader))
+        } else {
+            Box::new(reader)
+        };
+        Ok(FlacDecoder {
+            frames: FrameReader::new(BufferedReader::new(reader)),
+            block: Block::empty(),
+            position,
+            metadata,
+        })
+    }
+
+    /// Decodes the next frame, returning the number of its first sample along with it, or None
+    /// at the end of the stream.
+    pub fn next_block(&mut self) -> Result<Option<(u64, &Block)>> {
+        let buffer = std::mem::replace(&mut self.block, Block::empty()).into_buffer();
+        match self.frames.read_next_or_eof(buffer) {
+            Ok(Some(block)) => {
+                // the position is counted, as the time of a block is wrong for a short last
+                // frame of a stream with a fixed block size
+                let first = self.position;
+                self.position += block.duration() as u64;
+                self.block = block;
+                Ok(Some((first, &self.block)))
+            }
+            Ok(None) => Ok(None),
+            Err(e) => Err(anyhow::Error::new(e).context("[ERROR] Failed to decode FLAC frame")),
+        }
+    }
+}
+
+/// Result of checking the decoded audio of a FLAC file against the checksum in STREAMINFO.
+pub enum FlacVerification {
+    Ok,
+    Corrupt(String),
+    Unverifiable(String),
+}
+
+/// Decodes the whole file at the given path and compares the MD5 checksum of the decoded audio
+/// with the one in STREAMINFO. The checksum is computed over the interleaved samples as signed
+/// little endian integers of a whole number of bytes each.
+pub fn verify_flac_file(path: &Path) -> FlacVerification {
+    let mut decoder = match FlacDecoder::open_at(path, 0) {
+        Ok(decoder) => decoder,
+        Err(e) => return FlacVerification::Corrupt(format!("{:#}", e)),
+    };
+    let info = decoder.metadata.stream_info.clone();
+    if info.checksum == [0u8; 16] {
+        return FlacVerification::Unverifiable(
+            "[ERROR] FLAC file has no audio checksum".to_owned(),
+        );
+    }
+
+    let bytes = (info.bps as usize).div_ceil(8);
+    let mut context = md5::Context::new();
+    let mut buffer = Vec::new();
+    let mut samples = 0;
+    loop {
+        let block = match decoder.next_block() {
+            Ok(Some((_, block))) => block,
+            Ok(None) => break,
+            Err(e) => {
+                // frames the decoder does not support are not a sign of corruption
+                let message = format!("{:#}", e);
+                return match e.downcast_ref::<claxon::Error>() {
+                    Some(claxon::Error::Unsupported(_)) => FlacVerification::Unverifiable(message),
+                    _ => FlacVerification::Corrupt(message),
+                };
+            }
+        };
+        buffer.clear();
+        for i in 0..block.duration() {
+            for ch in 0..block.channels() {
+                buffer.extend_from_slice(&block.sample(ch, i).to_le_bytes()[..bytes]);
+            }
+        }
+        context.consume(&buffer);
+        samples += block.duration() as u64;
+    }
+
+    if info.total_samples > 0 && samples != info.total_samples {
+        return FlacVerification::Corrupt(format!(
+            "[ERROR] FLAC file has {} samples, but STREAMINFO says {}",
+            samples, info.total_samples
+        ));
+    }
+    if context.finalize().0 != info.checksum {
+        return FlacVerification::Corrupt(
+            "[ERROR] Decoded audio does not match the checksum in STREAMINFO".to_owned(),
+        );
+    }
+    FlacVerification::Ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes standing in for the audio frames, which writing tags never parses.
+    const AUDIO: [u8; 64] = [0xa5; 64];
+
+    fn test_stream_info() -> FlacStreamInfo {
+        FlacStreamInfo {
+            min_block_size: 4096,
+            max_block_size: 4096,
+            min_frame_size: 14,
+            max_frame_size: 8192,
+            sample_rate: 44100,
+            channels: 2,
+            bps: 16,
+            total_samples: 441000,
+            checksum: [7; 16],
+        }
+    }
+
+    /// Builds a FLAC file with the given tags and padding, followed by the stand-in audio.
+    fn test_flac(tags: &[(&str, &str)], padding: Option<usize>) -> Vec<u8> {
+        let comments = VorbisComments {
+            vendor: FLAC_VENDOR.to_owned(),
+            fields: tags
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        };
+        let blocks = [
+            (
+                FlacBlockType::StreamInfo.to_u8(),
+                write_streaminfo(&test_stream_info()),
+            ),
+            (
+                FlacBlockType::VorbisComment.to_u8(),
+                write_vorbis_comments(&comments),
+            ),
+        ];
+        let mut bytes = b"fLaC".to_vec();
+        bytes.extend(write_metadata_blocks(&blocks, padding).unwrap());
+        bytes.extend_from_slice(&AUDIO);
+        bytes
+    }
+
+    /// Builds an ID3v2.4 tag with a single title frame.
+    fn test_id3(title: &str) -> Vec<u8> {
+        let mut frame = b"TIT2".to_vec();
+        frame.extend_from_slice(&(title.len() as u32 + 1).to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 3]);
+        frame.extend_from_slice(title.as_bytes());
+        let mut tag = b"ID3\x04\x00\x00".to_vec();
+        tag.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        tag.extend(frame);
+        tag
+    }
+
+    fn test_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("harmony-{}-{}.flac", std::process::id(), name))
+    }
+
+    fn test_edit(key: &str, value: &str) -> FlacTagEdit {
+        FlacTagEdit {
+            tags: vec![(key.to_owned(), vec![value.to_owned()])],
+            front_cover: None,
+        }
+    }
+
+    #[test]
+    fn write_flac_tags_reuses_padding() {
+        let path = test_path("padding");
+        let original = test_flac(&[("TITLE", "Old")], Some(1024));
+        fs::write(&path, &original).unwrap();
+        write_flac_tags(&path, &test_edit("TITLE", "New")).unwrap();
+
+        // the metadata shrank into the padding, and the audio stayed where it was
+        let written = fs::read(&path).unwrap();
+        let metadata = parse_flac_file(&path, false).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(written.len(), original.len());
+        assert_eq!(written[original.len() - AUDIO.len()..], AUDIO);
+        assert_eq!(
+            metadata.audio_offset,
+            Some((original.len() - AUDIO.len()) as u64)
+        );
+        assert_eq!(metadata.tags["TITLE"], ["New"]);
+    }
+
+    #[test]
+    fn write_flac_tags_grows_past_padding() {
+        let path = test_path("grow");
+        let original = test_flac(&[("TITLE", "Old")], None);
+        fs::write(&path, &original).unwrap();
+        let comment = "x".repeat(2000);
+        write_flac_tags(&path, &test_edit("COMMENT", &comment)).unwrap();
+
+        // the file was rewritten with fresh padding, keeping the stream info and the audio
+        let written = fs::read(&path).unwrap();
+        let metadata = parse_flac_file(&path, false).unwrap();
+        let temp_name = format!(".{}.tmp", path.file_name().unwrap().to_str().unwrap());
+        let temp_exists = path.with_file_name(temp_name).exists();
+        fs::remove_file(&path).unwrap();
+        assert!(!temp_exists);
+        assert!(written.len() > original.len() + FLAC_PADDING);
+        assert!(written.ends_with(&AUDIO));
+        assert_eq!(
+            metadata.audio_offset,
+            Some((written.len() - AUDIO.len()) as u64)
+        );
+        assert_eq!(
+            write_streaminfo(&metadata.stream_info),
+            write_streaminfo(&test_stream_info())
+        );
+        assert_eq!(metadata.tags["TITLE"], ["Old"]);
+        assert_eq!(metadata.tags["COMMENT"], [comment]);
+    }
+
+    #[test]
+    fn write_flac_tags_keeps_id3_prefix() {
+        let path = test_path("id3");
+        let id3 = test_id3("Id3 Title");
+        let mut original = id3.clone();
+        original.extend(test_flac(&[("ARTIST", "Old")], None));
+        fs::write(&path, &original).unwrap();
+        write_flac_tags(&path, &test_edit("ARTIST", "New")).unwrap();
+
+        // the ID3 tag is copied as it is in front of the rewritten stream
+        let written = fs::read(&path).unwrap();
+        let metadata = parse_flac_file(&path, false).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(written.starts_with(&id3));
+        assert_eq!(&written[id3.len()..id3.len() + 4], b"fLaC");
+        assert!(written.ends_with(&AUDIO));
+        assert_eq!(metadata.tags["ARTIST"], ["New"]);
+        assert_eq!(metadata.tags["TITLE"], ["Id3 Title"]);
+    }
 }
@@ -0,0 +1,492 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, SeekFrom},
+};
+
+use anyhow::{Result, anyhow};
+use nom::{
+    IResult,
+    bytes::complete::{tag, take},
+    number::complete::{be_u8, be_u16, be_u24, be_u32},
+};
+
+use super::lrc::format_lrc_time;
+
+#[derive(Debug, Clone)]
+struct Id3Header {
+    major_version: u8,
+    flags: u8,
+    size: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Id3Frame {
+    id: String,
+    data: Vec<u8>,
+}
+
+/// Decodes a 28-bit "syncsafe" integer, in which the top bit of every byte is zero.
+fn syncsafe(value: u32) -> u32 {
+    ((value & 0x7f000000) >> 3)
+        | ((value & 0x007f0000) >> 2)
+        | ((value & 0x00007f00) >> 1)
+        | (value & 0x0000007f)
+}
+
+/// Reverses the unsynchronisation scheme, which inserts a zero byte after every 0xFF.
+fn resynchronise(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut last = 0u8;
+    for &b in data {
+        if !(last == 0xff && b == 0x00) {
+            out.push(b);
+        }
+        last = b;
+    }
+    out
+}
+
+fn parse_id3_header(input: &[u8]) -> IResult<&[u8], Id3Header> {
+    let (input, _) = tag("ID3")(input)?;
+    let (input, major_version) = be_u8(input)?;
+    let (input, _revision) = be_u8(input)?;
+    let (input, flags) = be_u8(input)?;
+    let (input, size) = be_u32(input)?;
+    Ok((
+        input,
+        Id3Header {
+            major_version,
+            flags,
+            size: syncsafe(size),
+        },
+    ))
+}
+
+fn parse_id3_frame(input: &[u8], major_version: u8) -> IResult<&[u8], Id3Frame> {
+    // ID3v2.2 uses three character ids and sizes, later versions use four
+    let (input, id, size) = if major_version == 2 {
+        let (input, id) = take(3usize)(input)?;
+        let (input, size) = be_u24(input)?;
+        (input, id, size)
+    } else {
+        let (input, id) = take(4usize)(input)?;
+        let (input, size) = be_u32(input)?;
+        let (input, _flags) = be_u16(input)?;
+        let size = if major_version >= 4 {
+            syncsafe(size)
+        } else {
+            size
+        };
+        (input, id, size)
+    };
+    let (input, data) = take(size)(input)?;
+    Ok((
+        input,
+        Id3Frame {
+            id: String::from_utf8_lossy(id).to_string(),
+            data: data.to_vec(),
+        },
+    ))
+}
+
+/// Decodes a string in the given ID3 text encoding.
+fn decode_text(encoding: u8, data: &[u8]) -> String {
+    match encoding {
+        // UTF-16 with a byte order mark, or big endian UTF-16 without one
+        1 | 2 => {
+            let mut big_endian = encoding == 2;
+            let mut data = data;
+            if data.len() >= 2 && encoding == 1 {
+                big_endian = data[0] == 0xfe && data[1] == 0xff;
+                data = &data[2..];
+            }
+            let units: Vec<u16> = data
+                .chunks_exact(2)
+                .map(|c| {
+                    if big_endian {
+                        u16::from_be_bytes([c[0], c[1]])
+                    } else {
+                        u16::from_le_bytes([c[0], c[1]])
+                    }
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        // UTF-8
+        3 => String::from_utf8_lossy(data).to_string(),
+        // ISO-8859-1
+        _ => data.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Splits the data of a text frame into its null separated values.
+fn split_text(encoding: u8, data: &[u8]) -> Vec<String> {
+    let text = decode_text(encoding, data);
+    text.split('\0')
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Splits the data of a frame that starts with a null terminated string in the given encoding,
+/// returning the string and the remaining data.
+fn split_terminated(encoding: u8, data: &[u8]) -> (String, &[u8]) {
+    let end = if encoding == 1 || encoding == 2 {
+        data.chunks_exact(2)
+            .position(|c| c == [0, 0])
+            .map(|i| (i * 2, i * 2 + 2))
+    } else {
+        data.iter().position(|&b| b == 0).map(|i| (i, i + 1))
+    };
+    match end {
+        Some((end, next)) => (decode_text(encoding, &data[..end]), &data[next..]),
+        None => (decode_text(encoding, data), &[]),
+    }
+}
+
+/// Returns an LRC language tag line for the language code of a lyrics frame, or nothing if
+/// the language is not given.
+fn lyrics_language(code: &[u8]) -> String {
+    let code: String = code.iter().map(|&b| b as char).collect();
+    let code = code.trim_matches(char::from(0)).trim().to_lowercase();
+    if code.len() != 3 || !code.chars().all(|c| c.is_ascii_alphabetic()) || code == "xxx" {
+        return String::new();
+    }
+    format!("[la:{}]\n", code)
+}
+
+/// Converts the data of a SYLT frame into lyrics in LRC format. Only lyrics that are timed in
+/// milliseconds are supported, as MPEG frame timing depends on the audio.
+fn sylt_to_lrc(encoding: u8, data: &[u8]) -> Option<String> {
+    let (language, timestamp_format) = (&data[..3], data[3]);
+    if timestamp_format != 2 {
+        return None;
+    }
+    let (_description, mut rest) = split_terminated(encoding, &data[5..]);
+    let mut lrc = lyrics_language(language);
+    let mut lines = 0;
+    while !rest.is_empty() {
+        let (text, after) = split_terminated(encoding, rest);
+        if after.len() < 4 {
+            break;
+        }
+        let time = u32::from_be_bytes([after[0], after[1], after[2], after[3]]);
+        rest = &after[4..];
+        lrc.push_str(&format!(
+            "[{}]{}\n",
+            format_lrc_time(time as u64),
+            text.trim()
+        ));
+        lines += 1;
+    }
+    Some(lrc).filter(|_| lines > 0)
+}
+
+/// Returns the Vorbis comment field name for an ID3 text frame id.
+fn frame_field(id: &str) -> Option<&'static str> {
+    match id {
+        "TIT2" | "TT2" => Some("TITLE"),
+        "TALB" | "TAL" => Some("ALBUM"),
+        "TPE1" | "TP1" => Some("ARTIST"),
+        "TPE2" | "TP2" => Some("ALBUMARTIST"),
+        "TCOM" | "TCM" => Some("COMPOSER"),
+        "TCON" | "TCO" => Some("GENRE"),
+        "TRCK" | "TRK" => Some("TRACKNUMBER"),
+        "TPOS" | "TPA" => Some("DISCNUMBER"),
+        "TDRC" | "TYER" | "TYE" => Some("DATE"),
+        "TPUB" | "TPB" => Some("ORGANIZATION"),
+        "TSRC" | "TRC" => Some("ISRC"),
+        _ => None,
+    }
+}
+
+/// Converts ID3 frames into Vorbis comment style fields, so that they can be merged into the
+/// tags of the file. Frames without a Vorbis comment counterpart are ignored.
+fn frames_to_tags(frames: &[Id3Frame]) -> HashMap<String, Vec<String>> {
+    let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+    for frame in frames {
+        let Some((&encoding, data)) = frame.data.split_first() else {
+            continue;
+        };
+        match frame.id.as_str() {
+            // user defined text, e.g. "MusicBrainz Album Id" or "REPLAYGAIN_TRACK_GAIN"
+            "TXXX" | "TXX" => {
+                let (description, value) = split_terminated(encoding, data);
+                let key = description.trim().to_uppercase().replace(' ', "_");
+                let key = match key.as_str() {
+                    "MUSICBRAINZ_ALBUM_ID" => "MUSICBRAINZ_ALBUMID".to_owned(),
+                    "MUSICBRAINZ_ARTIST_ID" => "MUSICBRAINZ_ARTISTID".to_owned(),
+                    _ => key,
+                };
+                tags.entry(key)
+                    .or_default()
+                    .extend(split_text(encoding, value));
+            }
+            // unsynchronised lyrics, prefixed with a language and a content description
+            "USLT" | "ULT" if data.len() >= 3 => {
+                let (_description, text) = split_terminated(encoding, &data[3..]);
+                let text = decode_text(encoding, text);
+                if !text.trim().is_empty() {
+                    tags.entry("UNSYNCEDLYRICS".to_owned())
+                        .or_default()
+                        .push(lyrics_language(&data[..3]) + &text);
+                }
+            }
+            // synchronised lyrics, converted to LRC
+            "SYLT" | "SLT" if data.len() >= 5 => {
+                if let Some(lrc) = sylt_to_lrc(encoding, data) {
+                    tags.entry("LYRICS".to_owned()).or_default().push(lrc);
+                }
+            }
+            id => {
+                if let Some(field) = frame_field(id) {
+                    tags.entry(field.to_owned())
+                        .or_default()
+                        .extend(split_text(encoding, data));
+                }
+            }
+        }
+    }
+    tags.retain(|_, v| !v.is_empty());
+    tags
+}
+
+/// Reads an ID3v2 tag at the current position of the reader, if there is one, and returns its
+/// frames as Vorbis comment style fields. The reader is left positioned after the tag, or where
+/// it was if there is no tag.
+pub fn parse_id3_reader<R: Read + Seek>(
+    reader: &mut R,
+) -> Result<Option<HashMap<String, Vec<String>>>> {
+    let start = reader.stream_position()?;
+    let mut header_bytes = [0u8; 10];
+    if reader.read_exact(&mut header_bytes).is_err() {
+        reader.seek(SeekFrom::Start(start))?;
+        return Ok(None);
+    }
+    let Ok((_, header)) = parse_id3_header(&header_bytes) else {
+        reader.seek(SeekFrom::Start(start))?;
+        return Ok(None);
+    };
+
+    // read the whole tag, and skip the footer if there is one
+    let mut body = vec![0u8; header.size as usize];
+    reader.read_exact(&mut body)?;
+    if header.flags & 0x10 != 0 {
+        reader.seek(SeekFrom::Current(10))?;
+    }
+    if header.major_version < 2 || header.major_version > 4 {
+        return Err(anyhow!(
+            "[ERROR] Unsupported ID3v2.{} tag",
+            header.major_version
+        ));
+    }
+    if header.flags & 0x80 != 0 {
+        body = resynchronise(&body);
+    }
+
+    // skip the extended header, whose size is stored differently in each version
+    let mut input: &[u8] = &body;
+    if header.flags & 0x40 != 0 && header.major_version >= 3 && input.len() >= 4 {
+        let size = u32::from_be_bytes([input[0], input[1], input[2], input[3]]);
+        let skip = if header.major_version == 4 {
+            syncsafe(size) as usize
+        } else {
+            size as usize + 4
+        };
+        input = &input[skip.min(input.len())..];
+    }
+
+    // parse frames until the end of the tag or the start of the padding
+    let mut frames = Vec::new();
+    while !input.is_empty() && input[0] != 0 {
+        match parse_id3_frame(input, header.major_version) {
+            Ok((rest, frame)) => {
+                frames.push(frame);
+                input = rest;
+            }
+            Err(_) => break,
+        }
+    }
+    Ok(Some(frames_to_tags(&frames)))
+}
+
+/// Merges the fields of an ID3 tag into the tags of a file. Fields that are already present
+/// in the tags take precedence over the ones from the ID3 tag.
+pub fn merge_id3_tags(
+    tags: &mut HashMap<String, Vec<String>>,
+    id3_tags: HashMap<String, Vec<String>>,
+) {
+    for (key, values) in id3_tags {
+        tags.entry(key).or_insert(values);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn to_syncsafe(value: u32) -> [u8; 4] {
+        [
+            (value >> 21) as u8 & 0x7f,
+            (value >> 14) as u8 & 0x7f,
+            (value >> 7) as u8 & 0x7f,
+            value as u8 & 0x7f,
+        ]
+    }
+
+    fn frame(major_version: u8, id: &str, data: &[u8]) -> Vec<u8> {
+        let mut bytes = id.as_bytes().to_vec();
+        match major_version {
+            2 => bytes.extend_from_slice(&(data.len() as u32).to_be_bytes()[1..]),
+            3 => bytes.extend_from_slice(&(data.len() as u32).to_be_bytes()),
+            _ => bytes.extend_from_slice(&to_syncsafe(data.len() as u32)),
+        }
+        if major_version > 2 {
+            bytes.extend_from_slice(&[0, 0]);
+        }
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    /// Builds an ID3v2 tag from its frames and padding, followed by the bytes of a stream.
+    fn id3(major_version: u8, flags: u8, frames: &[Vec<u8>], padding: usize) -> Vec<u8> {
+        let mut body = frames.concat();
+        body.resize(body.len() + padding, 0);
+        let mut bytes = vec![b'I', b'D', b'3', major_version, 0, flags];
+        bytes.extend_from_slice(&to_syncsafe(body.len() as u32));
+        bytes.extend(body);
+        bytes.extend_from_slice(b"fLaC");
+        bytes
+    }
+
+    fn parse(bytes: &[u8]) -> (Option<HashMap<String, Vec<String>>>, u64) {
+        let mut reader = Cursor::new(bytes);
+        let tags = parse_id3_reader(&mut reader).unwrap();
+        (tags, reader.position())
+    }
+
+    #[test]
+    fn parse_id3_reader_reads_v23_text_frames() {
+        let mut artists = vec![1, 0xff, 0xfe];
+        for unit in "One\0Two".encode_utf16() {
+            artists.extend_from_slice(&unit.to_le_bytes());
+        }
+        let bytes = id3(
+            3,
+            0,
+            &[
+                frame(3, "TIT2", b"\x00Caf\xe9"),
+                frame(3, "TPE1", &artists),
+                frame(3, "TXXX", b"\x00MusicBrainz Album Id\x00abc-123"),
+                frame(3, "PRIV", b"ignored"),
+            ],
+            32,
+        );
+        let (tags, position) = parse(&bytes);
+        let tags = tags.unwrap();
+        assert_eq!(tags["TITLE"], ["Café"]);
+        assert_eq!(tags["ARTIST"], ["One", "Two"]);
+        assert_eq!(tags["MUSICBRAINZ_ALBUMID"], ["abc-123"]);
+        assert_eq!(tags.len(), 3);
+        // the reader is left at the stream after the tag and its padding
+        assert_eq!(&bytes[position as usize..], b"fLaC");
+    }
+
+    #[test]
+    fn parse_id3_reader_reads_v24_and_v22_frames() {
+        let album = "a".repeat(200);
+        let mut data = vec![3];
+        data.extend_from_slice(album.as_bytes());
+        let bytes = id3(
+            4,
+            0,
+            &[frame(4, "TALB", &data), frame(4, "TRCK", b"\x033/12")],
+            0,
+        );
+        let tags = parse(&bytes).0.unwrap();
+        assert_eq!(tags["ALBUM"], [album]);
+        assert_eq!(tags["TRACKNUMBER"], ["3/12"]);
+
+        let bytes = id3(
+            2,
+            0,
+            &[frame(2, "TT2", b"\x00Old"), frame(2, "TYE", b"\x001999")],
+            0,
+        );
+        let tags = parse(&bytes).0.unwrap();
+        assert_eq!(tags["TITLE"], ["Old"]);
+        assert_eq!(tags["DATE"], ["1999"]);
+    }
+
+    #[test]
+    fn parse_id3_reader_converts_lyrics() {
+        let mut sylt = b"\x03eng\x02\x01\x00".to_vec();
+        sylt.extend_from_slice(b"Hello\x00");
+        sylt.extend_from_slice(&1500u32.to_be_bytes());
+        sylt.extend_from_slice(b"World\x00");
+        sylt.extend_from_slice(&62340u32.to_be_bytes());
+        let bytes = id3(
+            3,
+            0,
+            &[
+                frame(3, "USLT", b"\x03deu\x00Zeile eins\nZeile zwei"),
+                frame(3, "SYLT", &sylt),
+            ],
+            0,
+        );
+        let tags = parse(&bytes).0.unwrap();
+        assert_eq!(tags["UNSYNCEDLYRICS"], ["[la:deu]\nZeile eins\nZeile zwei"]);
+        assert_eq!(
+            tags["LYRICS"],
+            ["[la:eng]\n[00:01.50]Hello\n[01:02.34]World\n"]
+        );
+
+        // lyrics timed in MPEG frames cannot be converted
+        let bytes = id3(
+            3,
+            0,
+            &[frame(
+                3,
+                "SYLT",
+                b"\x03eng\x01\x01\x00a\x00\x00\x00\x00\x01",
+            )],
+            0,
+        );
+        assert!(!parse(&bytes).0.unwrap().contains_key("LYRICS"));
+    }
+
+    #[test]
+    fn parse_id3_reader_resynchronises() {
+        // the zero byte after 0xFF is removed from the whole tag, and the size of the frame
+        // counts the bytes that are left
+        let mut unsynchronised = frame(3, "TIT2", b"\x00\xffy");
+        unsynchronised.insert(unsynchronised.len() - 1, 0);
+        let bytes = id3(3, 0x80, &[unsynchronised], 0);
+        let tags = parse(&bytes).0.unwrap();
+        assert_eq!(tags["TITLE"], ["\u{ff}y"]);
+    }
+
+    #[test]
+    fn parse_id3_reader_leaves_other_streams() {
+        let (tags, position) = parse(b"fLaC\x00\x00\x00\x22");
+        assert!(tags.is_none());
+        assert_eq!(position, 0);
+        assert!(parse_id3_reader(&mut Cursor::new(id3(5, 0, &[], 0))).is_err());
+    }
+
+    #[test]
+    fn merge_id3_tags_prefers_existing_tags() {
+        let mut tags = HashMap::from([("TITLE".to_owned(), vec!["Vorbis".to_owned()])]);
+        merge_id3_tags(
+            &mut tags,
+            HashMap::from([
+                ("TITLE".to_owned(), vec!["Id3".to_owned()]),
+                ("ARTIST".to_owned(), vec!["Id3 Artist".to_owned()]),
+            ]),
+        );
+        assert_eq!(tags["TITLE"], ["Vorbis"]);
+        assert_eq!(tags["ARTIST"], ["Id3 Artist"]);
+    }
+}
@@ -0,0 +1,219 @@
+use std::{fs, path::Path};
+
+use anyhow::Result;
+
+#[derive(Debug, Clone)]
+pub struct LyricLine {
+    pub start: Option<u64>,
+    pub value: String,
+}
+
+/// Lyrics parsed from LRC or plain text. Synced lyrics only keep their timed lines, sorted by
+/// their start time in milliseconds.
+#[derive(Debug, Clone, Default)]
+pub struct Lyrics {
+    pub lang: Option<String>,
+    pub synced: bool,
+    pub offset: i64,
+    pub display_artist: Option<String>,
+    pub display_title: Option<String>,
+    pub lines: Vec<LyricLine>,
+}
+
+/// Parses a timestamp (mm:ss, mm:ss.xx or mm:ss:xx) into milliseconds.
+fn parse_lrc_time(time: &str) -> Option<u64> {
+    let (minutes, rest) = time.trim().split_once(':')?;
+    let (seconds, fraction) = rest.split_once(['.', ':']).unwrap_or((rest, ""));
+    if !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let minutes: u64 = minutes.parse().ok()?;
+    let seconds: u64 = seconds.parse().ok()?;
+
+    // the fraction is in hundredths of a second, or in thousandths with three digits
+    let millis = match fraction.len() {
+        0 => 0,
+        1 => fraction.parse::<u64>().ok()? * 100,
+        2 => fraction.parse::<u64>().ok()? * 10,
+        _ => fraction[..3].parse::<u64>().ok()?,
+    };
+    Some((minutes * 60 + seconds) * 1000 + millis)
+}
+
+/// Formats milliseconds as an LRC timestamp.
+pub fn format_lrc_time(millis: u64) -> String {
+    format!(
+        "{:02}:{:02}.{:02}",
+        millis / 60000,
+        millis / 1000 % 60,
+        millis % 1000 / 10
+    )
+}
+
+/// Removes the word timestamps of enhanced LRC, e.g. "<00:12.34>word".
+fn strip_word_times(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        match rest[start..].find('>') {
+            Some(end) if parse_lrc_time(&rest[start + 1..start + end]).is_some() => {
+                out.push_str(&rest[..start]);
+                rest = &rest[start + end + 1..];
+            }
+            _ => {
+                out.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out.trim().to_owned()
+}
+
+/// Parses lyrics in LRC format. Lines may have several timestamps, in which case they are
+/// repeated at each of them. Text without any timestamps is parsed as plain lyrics.
+pub fn parse_lrc(text: &str) -> Lyrics {
+    let mut lyrics = Lyrics::default();
+    let mut timed = Vec::new();
+    let mut untimed = Vec::new();
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        let mut rest = line.trim();
+        let mut times = Vec::new();
+        let mut id_tag = false;
+        while let Some(inner) = rest.strip_prefix('[')
+            && let Some((tag, after)) = inner.split_once(']')
+        {
+            if let Some(time) = parse_lrc_time(tag) {
+                times.push(time);
+                rest = after.trim_start();
+                continue;
+            }
+
+            // ID tags such as [ar:Artist] only appear on lines of their own
+            let Some((key, value)) = tag.split_once(':') else {
+                break;
+            };
+            if !times.is_empty() || !key.trim().chars().all(|c| c.is_ascii_alphabetic()) {
+                break;
+            }
+            let value = value.trim();
+            match key.trim().to_lowercase().as_str() {
+                "ar" => lyrics.display_artist = Some(value.to_owned()),
+                "ti" => lyrics.display_title = Some(value.to_owned()),
+                "la" | "lang" | "language" => lyrics.lang = Some(value.to_lowercase()),
+                "offset" => {
+                    lyrics.offset = value.trim_start_matches('+').parse().unwrap_or(0);
+                }
+                _ => {}
+            }
+            id_tag = true;
+            rest = after.trim_start();
+            break;
+        }
+        if id_tag && rest.is_empty() {
+            continue;
+        }
+
+        let value = strip_word_times(rest);
+        if times.is_empty() {
+            untimed.push(LyricLine { start: None, value });
+        } else {
+            for start in times {
+                timed.push(LyricLine {
+                    start: Some(start),
+                    value: value.clone(),
+                });
+            }
+        }
+    }
+
+    if timed.is_empty() {
+        // drop the blank lines around plain lyrics
+        while untimed.last().is_some_and(|l| l.value.is_empty()) {
+            untimed.pop();
+        }
+        let first = untimed.iter().position(|l| !l.value.is_empty());
+        lyrics.lines = untimed.split_off(first.unwrap_or(untimed.len()));
+    } else {
+        timed.sort_by_key(|l| l.start);
+        lyrics.synced = true;
+        lyrics.lines = timed;
+    }
+    lyrics
+}
+
+/// Reads a lyrics file. Like cue sheets, lyrics files are often not encoded in UTF-8, in which
+/// case they are decoded as ISO-8859-1.
+pub fn read_lrc_file(path: &Path) -> Result<String> {
+    let bytes = fs::read(path)?;
+    Ok(match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => e.as_bytes().iter().map(|&b| b as char).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lyrics: &Lyrics) -> Vec<(Option<u64>, &str)> {
+        lyrics
+            .lines
+            .iter()
+            .map(|l| (l.start, l.value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_lrc_time_reads_fractions() {
+        assert_eq!(parse_lrc_time("01:02"), Some(62000));
+        assert_eq!(parse_lrc_time("01:02.5"), Some(62500));
+        assert_eq!(parse_lrc_time("01:02.34"), Some(62340));
+        assert_eq!(parse_lrc_time("01:02.345"), Some(62345));
+        assert_eq!(parse_lrc_time("01:02:34"), Some(62340));
+        assert_eq!(parse_lrc_time("ar:Artist"), None);
+        assert_eq!(parse_lrc_time("01:02.x"), None);
+        assert_eq!(format_lrc_time(62345), "01:02.34");
+    }
+
+    #[test]
+    fn parse_lrc_reads_id_tags_and_repeated_lines() {
+        let lyrics = parse_lrc(
+            "\u{feff}[ar:The Band]\n[ti: Song ]\n[la:EN]\n[offset:+250]\n\
+             [00:12.00][00:42.00]Chorus\n[00:20.50]Verse\n\n[00:30.00]",
+        );
+        assert_eq!(lyrics.display_artist.as_deref(), Some("The Band"));
+        assert_eq!(lyrics.display_title.as_deref(), Some("Song"));
+        assert_eq!(lyrics.lang.as_deref(), Some("en"));
+        assert_eq!(lyrics.offset, 250);
+        assert!(lyrics.synced);
+        // untimed lines are dropped from synced lyrics, and the timed ones sorted
+        assert_eq!(
+            lines(&lyrics),
+            [
+                (Some(12000), "Chorus"),
+                (Some(20500), "Verse"),
+                (Some(30000), ""),
+                (Some(42000), "Chorus"),
+            ]
+        );
+        assert_eq!(parse_lrc("[offset:-100]\n[00:01]a").offset, -100);
+    }
+
+    #[test]
+    fn parse_lrc_strips_word_times() {
+        let lyrics = parse_lrc("[00:01.00]<00:01.00>Hello <00:01.50>world <not a time>");
+        assert_eq!(lines(&lyrics), [(Some(1000), "Hello world <not a time>")]);
+    }
+
+    #[test]
+    fn parse_lrc_reads_plain_lyrics() {
+        let lyrics = parse_lrc("\n\nFirst line\n\nSecond line\n\n");
+        assert!(!lyrics.synced);
+        assert_eq!(
+            lines(&lyrics),
+            [(None, "First line"), (None, ""), (None, "Second line")]
+        );
+        assert!(parse_lrc("[ar:Nobody]\n").lines.is_empty());
+    }
+}
@@ -1,2 +1,8 @@
+pub mod cue;
 pub mod epub;
 pub mod flac;
+pub mod id3;
+pub mod lrc;
+pub mod ogg;
+pub mod opds;
+pub mod wav;
@@ -0,0 +1,126 @@
+use std::io::{self, Read};
+
+use anyhow::{Result, anyhow};
+use nom::{
+    IResult,
+    bytes::complete::{tag, take},
+    number::complete::{le_u8, le_u32, le_u64},
+};
+
+#[derive(Debug, Clone)]
+struct OggPageHeader {
+    serial: u32,
+    segments: Vec<u8>,
+}
+
+fn parse_page_header(input: &[u8]) -> IResult<&[u8], OggPageHeader> {
+    // capture pattern, version, header type and granule position
+    let (input, _) = tag("OggS")(input)?;
+    let (input, _version) = le_u8(input)?;
+    let (input, _header_type) = le_u8(input)?;
+    let (input, _granule_position) = le_u64(input)?;
+
+    // bitstream serial number, page sequence number, checksum and the segment table
+    let (input, serial) = le_u32(input)?;
+    let (input, _sequence) = le_u32(input)?;
+    let (input, _checksum) = le_u32(input)?;
+    let (input, segment_count) = le_u8(input)?;
+    let (input, segments) = take(segment_count)(input)?;
+    Ok((
+        input,
+        OggPageHeader {
+            serial,
+            segments: segments.to_vec(),
+        },
+    ))
+}
+
+/// Reads the packets of the first logical bitstream of an Ogg container.
+pub struct OggPacketReader<R: Read> {
+    reader: R,
+    serial: Option<u32>,
+    segments: Vec<u8>,
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> OggPacketReader<R> {
+    pub fn new(reader: R) -> Self {
+        OggPacketReader {
+            reader,
+            serial: None,
+            segments: Vec::new(),
+            data: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Reads the next page of the bitstream, skipping pages of other bitstreams. Returns false
+    /// at the end of the container.
+    fn next_page(&mut self) -> Result<bool> {
+        loop {
+            let mut fixed = [0u8; 27];
+            match self.reader.read_exact(&mut fixed) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+                Err(e) => return Err(e.into()),
+            }
+            let mut header_bytes = fixed.to_vec();
+            let mut segment_table = vec![0u8; fixed[26] as usize];
+            self.reader.read_exact(&mut segment_table)?;
+            header_bytes.extend_from_slice(&segment_table);
+            let (_, header) = parse_page_header(&header_bytes)
+                .map_err(|e| anyhow!("[ERROR] Failed to parse Ogg page: {:?}", e))?;
+
+            let size: usize = header.segments.iter().map(|&s| s as usize).sum();
+            let mut data = vec![0u8; size];
+            self.reader.read_exact(&mut data)?;
+            if *self.serial.get_or_insert(header.serial) != header.serial {
+                continue;
+            }
+            self.segments = header.segments;
+            self.data = data;
+            self.position = 0;
+            return Ok(true);
+        }
+    }
+
+    /// Returns the next packet of the bitstream, or None at the end of the container. Packets
+    /// may span several segments and pages; a segment shorter than 255 bytes ends a packet.
+    pub fn next_packet(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut packet = Vec::new();
+        loop {
+            if self.segments.is_empty() && !self.next_page()? {
+                if packet.is_empty() {
+                    return Ok(None);
+                }
+                return Err(anyhow!("[ERROR] Ogg container ends inside a packet"));
+            }
+            let segment = self.segments.remove(0) as usize;
+            packet.extend_from_slice(&self.data[self.position..self.position + segment]);
+            self.position += segment;
+            if segment < 255 {
+                return Ok(Some(packet));
+            }
+        }
+    }
+}
+
+/// Reads the data of the bitstream without regard to packet boundaries, which is all that is
+/// needed for codecs whose packets can be found in the data itself, such as FLAC frames. This
+/// should not be mixed with next_packet().
+impl<R: Read> Read for OggPacketReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position >= self.data.len() {
+            match self.next_page() {
+                Ok(true) => self.segments.clear(),
+                Ok(false) => return Ok(0),
+                Err(e) => return Err(io::Error::other(e.to_string())),
+            }
+        }
+        let size = buf.len().min(self.data.len() - self.position);
+        buf[..size].copy_from_slice(&self.data[self.position..self.position + size]);
+        self.position += size;
+        Ok(size)
+    }
+}
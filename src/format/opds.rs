@@ -0,0 +1,569 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use serde::Serialize;
+
+const ATOM_NAVIGATION_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=navigation";
+const ATOM_ACQUISITION_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=acquisition";
+const OPDS_JSON_TYPE: &str = "application/opds+json";
+const OPENSEARCH_TYPE: &str = "application/opensearchdescription+xml";
+const EPUB_TYPE: &str = "application/epub+zip";
+
+/// Whether a feed links to other feeds or lists publications.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpdsFeedKind {
+    Navigation,
+    Acquisition,
+}
+
+/// A link from a navigation feed to another feed. The href is relative to the root of the
+/// catalog.
+#[derive(Debug, Clone)]
+pub struct OpdsNavigationEntry {
+    pub id: String,
+    pub title: String,
+    pub href: String,
+    pub kind: OpdsFeedKind,
+    /// The relation of the linked feed, such as "http://opds-spec.org/sort/new" for a feed of
+    /// new publications.
+    pub rel: &'static str,
+    pub count: Option<usize>,
+}
+
+/// An author of a publication, with the href of the feed of their books relative to the root of
+/// the catalog.
+#[derive(Debug, Clone)]
+pub struct OpdsAuthor {
+    pub name: String,
+    pub href: String,
+}
+
+/// A book in an acquisition feed. The cover and acquisition hrefs are absolute paths, as they
+/// are the same for every version of the catalog.
+#[derive(Debug, Clone)]
+pub struct OpdsPublication {
+    pub id: String,
+    pub title: String,
+    pub authors: Vec<OpdsAuthor>,
+    pub language: Option<String>,
+    pub publisher: Option<String>,
+    pub published: Option<String>,
+    pub description: Option<String>,
+    pub subjects: Vec<String>,
+    /// Identifiers as URNs, such as "urn:isbn:9780000000000".
+    pub identifiers: Vec<String>,
+    pub series: Option<String>,
+    pub series_index: Option<f64>,
+    pub updated: DateTime<Utc>,
+    pub cover: Option<(String, &'static str)>,
+    pub acquisition_href: String,
+}
+
+/// The position of a feed in a paginated list, with pages numbered from zero.
+#[derive(Debug, Clone, Copy)]
+pub struct OpdsPagination {
+    pub page: u64,
+    pub page_size: u64,
+    pub total: u64,
+}
+
+/// A feed of the catalog, which is written as an OPDS 1.2 Atom feed or as an OPDS 2.0 JSON
+/// feed. The href is relative to the root of the catalog, and leaves out the query.
+#[derive(Debug, Clone)]
+pub struct OpdsFeed {
+    pub id: String,
+    pub title: String,
+    pub href: String,
+    pub kind: OpdsFeedKind,
+    pub updated: DateTime<Utc>,
+    pub navigation: Vec<OpdsNavigationEntry>,
+    pub publications: Vec<OpdsPublication>,
+    pub pagination: Option<OpdsPagination>,
+    /// The search terms of a feed of search results.
+    pub query: Option<String>,
+}
+
+impl OpdsFeed {
+    /// Returns the href of a page of the feed, relative to the root of the catalog.
+    fn page_href(&self, page: u64) -> String {
+        let mut params = Vec::new();
+        if let Some(query) = &self.query {
+            params.push(format!(
+                "q={}",
+                utf8_percent_encode(query, NON_ALPHANUMERIC)
+            ));
+        }
+        if page > 0 {
+            params.push(format!("page={}", page.saturating_add(1)));
+        }
+        if params.is_empty() {
+            self.href.clone()
+        } else {
+            format!("{}?{}", self.href, params.join("&"))
+        }
+    }
+
+    /// Returns the relations and page numbers of the links to the other pages of the feed.
+    fn page_links(&self) -> Vec<(&'static str, u64)> {
+        let Some(p) = self.pagination else {
+            return Vec::new();
+        };
+        let last = p.total.div_ceil(p.page_size).max(1) - 1;
+        let mut links = vec![("first", 0)];
+        if p.page > 0 {
+            links.push(("previous", p.page - 1));
+        }
+        if p.page < last {
+            links.push(("next", p.page + 1));
+        }
+        links.push(("last", last));
+        links
+    }
+}
+
+/// Escapes the characters that are special in XML text and attribute values.
+fn write_xml_escaped(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn write_atom_time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn write_atom_link(xml: &mut String, rel: &str, href: &str, media_type: &str) {
+    xml.push_str(&format!(
+        "  <link rel=\"{}\" href=\"{}\" type=\"{}\"/>\n",
+        write_xml_escaped(rel),
+        write_xml_escaped(href),
+        media_type
+    ));
+}
+
+/// Writes a feed as an OPDS 1.2 Atom document, with hrefs under the given base path.
+pub fn write_opds_atom(feed: &OpdsFeed, base: &str) -> String {
+    let feed_type = match feed.kind {
+        OpdsFeedKind::Navigation => ATOM_NAVIGATION_TYPE,
+        OpdsFeedKind::Acquisition => ATOM_ACQUISITION_TYPE,
+    };
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(concat!(
+        "<feed xmlns=\"http://www.w3.org/2005/Atom\"",
+        " xmlns:dc=\"http://purl.org/dc/terms/\"",
+        " xmlns:opds=\"http://opds-spec.org/2010/catalog\"",
+        " xmlns:opensearch=\"http://a9.com/-/spec/opensearch/1.1/\"",
+        " xmlns:thr=\"http://purl.org/syndication/thread/1.0\">\n"
+    ));
+    xml.push_str(&format!("  <id>{}</id>\n", write_xml_escaped(&feed.id)));
+    xml.push_str(&format!(
+        "  <title>{}</title>\n",
+        write_xml_escaped(&feed.title)
+    ));
+    xml.push_str(&format!(
+        "  <updated>{}</updated>\n",
+        write_atom_time(&feed.updated)
+    ));
+    xml.push_str("  <author><name>Harmony</name></author>\n");
+
+    // links to the feed itself, the root, the search and the other pages
+    let self_href = match feed.pagination {
+        Some(p) => feed.page_href(p.page),
+        None => feed.page_href(0),
+    };
+    write_atom_link(
+        &mut xml,
+        "self",
+        &format!("{}{}", base, self_href),
+        feed_type,
+    );
+    write_atom_link(&mut xml, "start", base, ATOM_NAVIGATION_TYPE);
+    write_atom_link(
+        &mut xml,
+        "search",
+        &format!("{}/opensearch.xml", base),
+        OPENSEARCH_TYPE,
+    );
+    for (rel, page) in feed.page_links() {
+        let href = format!("{}{}", base, feed.page_href(page));
+        write_atom_link(&mut xml, rel, &href, feed_type);
+    }
+    if let Some(p) = feed.pagination {
+        xml.push_str(&format!(
+            "  <opensearch:totalResults>{}</opensearch:totalResults>\n",
+            p.total
+        ));
+        xml.push_str(&format!(
+            "  <opensearch:itemsPerPage>{}</opensearch:itemsPerPage>\n",
+            p.page_size
+        ));
+        xml.push_str(&format!(
+            "  <opensearch:startIndex>{}</opensearch:startIndex>\n",
+            p.page.saturating_mul(p.page_size).saturating_add(1)
+        ));
+    }
+
+    for entry in &feed.navigation {
+        let entry_type = match entry.kind {
+            OpdsFeedKind::Navigation => ATOM_NAVIGATION_TYPE,
+            OpdsFeedKind::Acquisition => ATOM_ACQUISITION_TYPE,
+        };
+        xml.push_str("  <entry>\n");
+        xml.push_str(&format!(
+            "    <title>{}</title>\n",
+            write_xml_escaped(&entry.title)
+        ));
+        xml.push_str(&format!("    <id>{}</id>\n", write_xml_escaped(&entry.id)));
+        xml.push_str(&format!(
+            "    <updated>{}</updated>\n",
+            write_atom_time(&feed.updated)
+        ));
+        let href = write_xml_escaped(&format!("{}{}", base, entry.href));
+        match entry.count {
+            Some(count) => {
+                xml.push_str(&format!(
+                    "    <link rel=\"{}\" href=\"{}\" type=\"{}\" thr:count=\"{}\"/>\n",
+                    entry.rel, href, entry_type, count
+                ));
+                let books = if count == 1 { "book" } else { "books" };
+                xml.push_str(&format!(
+                    "    <content type=\"text\">{} {}</content>\n",
+                    count, books
+                ));
+            }
+            None => xml.push_str(&format!(
+                "    <link rel=\"{}\" href=\"{}\" type=\"{}\"/>\n",
+                entry.rel, href, entry_type
+            )),
+        }
+        xml.push_str("  </entry>\n");
+    }
+
+    for publication in &feed.publications {
+        let p = publication;
+        xml.push_str("  <entry>\n");
+        xml.push_str(&format!(
+            "    <title>{}</title>\n",
+            write_xml_escaped(&p.title)
+        ));
+        xml.push_str(&format!("    <id>{}</id>\n", write_xml_escaped(&p.id)));
+        xml.push_str(&format!(
+            "    <updated>{}</updated>\n",
+            write_atom_time(&p.updated)
+        ));
+        for author in &p.authors {
+            xml.push_str(&format!(
+                "    <author><name>{}</name><uri>{}</uri></author>\n",
+                write_xml_escaped(&author.name),
+                write_xml_escaped(&format!("{}{}", base, author.href))
+            ));
+        }
+        if let Some(language) = &p.language {
+            xml.push_str(&format!(
+                "    <dc:language>{}</dc:language>\n",
+                write_xml_escaped(language)
+            ));
+        }
+        if let Some(publisher) = &p.publisher {
+            xml.push_str(&format!(
+                "    <dc:publisher>{}</dc:publisher>\n",
+                write_xml_escaped(publisher)
+            ));
+        }
+        if let Some(published) = &p.published {
+            xml.push_str(&format!(
+                "    <dc:issued>{}</dc:issued>\n",
+                write_xml_escaped(published)
+            ));
+        }
+        for identifier in &p.identifiers {
+            xml.push_str(&format!(
+                "    <dc:identifier>{}</dc:identifier>\n",
+                write_xml_escaped(identifier)
+            ));
+        }
+        for subject in &p.subjects {
+            let subject = write_xml_escaped(subject);
+            xml.push_str(&format!(
+                "    <category term=\"{}\" label=\"{}\"/>\n",
+                subject, subject
+            ));
+        }
+
+        // descriptions of EPUB files are often HTML
+        if let Some(description) = &p.description {
+            xml.push_str(&format!(
+                "    <content type=\"html\">{}</content>\n",
+                write_xml_escaped(description)
+            ));
+        }
+        if let Some((href, media_type)) = &p.cover {
+            for rel in [
+                "http://opds-spec.org/image",
+                "http://opds-spec.org/image/thumbnail",
+            ] {
+                xml.push_str(&format!(
+                    "    <link rel=\"{}\" href=\"{}\" type=\"{}\"/>\n",
+                    rel,
+                    write_xml_escaped(href),
+                    media_type
+                ));
+            }
+        }
+        xml.push_str(&format!(
+            "    <link rel=\"http://opds-spec.org/acquisition\" href=\"{}\" type=\"{}\"/>\n",
+            write_xml_escaped(&p.acquisition_href),
+            EPUB_TYPE
+        ));
+        xml.push_str("  </entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+/// Writes the OpenSearch description of the search of an OPDS 1.2 catalog under the given base
+/// path.
+pub fn write_opensearch_description(base: &str) -> String {
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<OpenSearchDescription xmlns=\"http://a9.com/-/spec/opensearch/1.1/\">\n",
+            "  <ShortName>Harmony</ShortName>\n",
+            "  <Description>Search the books of the Harmony library</Description>\n",
+            "  <InputEncoding>UTF-8</InputEncoding>\n",
+            "  <OutputEncoding>UTF-8</OutputEncoding>\n",
+            "  <Url type=\"{}\" template=\"{}/search?q={{searchTerms}}\"/>\n",
+            "</OpenSearchDescription>\n"
+        ),
+        ATOM_ACQUISITION_TYPE,
+        write_xml_escaped(base)
+    )
+}
+
+#[derive(Serialize)]
+struct OpdsJsonLink {
+    href: String,
+    #[serde(rename = "type")]
+    media_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rel: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    templated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    properties: Option<OpdsJsonLinkProperties>,
+}
+
+impl OpdsJsonLink {
+    fn new(rel: Option<&'static str>, href: String, media_type: &'static str) -> OpdsJsonLink {
+        OpdsJsonLink {
+            href,
+            media_type,
+            rel,
+            title: None,
+            templated: false,
+            properties: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OpdsJsonLinkProperties {
+    number_of_items: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OpdsJsonFeedMetadata {
+    title: String,
+    modified: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    number_of_items: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    items_per_page: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    current_page: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct OpdsJsonContributor {
+    name: String,
+    links: Vec<OpdsJsonLink>,
+}
+
+#[derive(Serialize)]
+struct OpdsJsonSubject {
+    name: String,
+}
+
+#[derive(Serialize)]
+struct OpdsJsonSeries {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct OpdsJsonBelongsTo {
+    series: Vec<OpdsJsonSeries>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OpdsJsonPublicationMetadata {
+    #[serde(rename = "@type")]
+    schema_type: &'static str,
+    identifier: String,
+    title: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    author: Vec<OpdsJsonContributor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    publisher: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    published: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    subject: Vec<OpdsJsonSubject>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    belongs_to: Option<OpdsJsonBelongsTo>,
+    modified: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct OpdsJsonPublication {
+    metadata: OpdsJsonPublicationMetadata,
+    links: Vec<OpdsJsonLink>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    images: Vec<OpdsJsonLink>,
+}
+
+#[derive(Serialize)]
+struct OpdsJsonFeed {
+    metadata: OpdsJsonFeedMetadata,
+    links: Vec<OpdsJsonLink>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    navigation: Vec<OpdsJsonLink>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    publications: Vec<OpdsJsonPublication>,
+}
+
+/// Writes a feed as an OPDS 2.0 JSON document, with hrefs under the given base path.
+pub fn write_opds_json(feed: &OpdsFeed, base: &str) -> serde_json::Result<String> {
+    let self_href = match feed.pagination {
+        Some(p) => feed.page_href(p.page),
+        None => feed.page_href(0),
+    };
+    let mut links = vec![
+        OpdsJsonLink::new(
+            Some("self"),
+            format!("{}{}", base, self_href),
+            OPDS_JSON_TYPE,
+        ),
+        OpdsJsonLink::new(Some("start"), base.to_owned(), OPDS_JSON_TYPE),
+        OpdsJsonLink {
+            templated: true,
+            ..OpdsJsonLink::new(
+                Some("search"),
+                format!("{}/search{{?q}}", base),
+                OPDS_JSON_TYPE,
+            )
+        },
+    ];
+    for (rel, page) in feed.page_links() {
+        let href = format!("{}{}", base, feed.page_href(page));
+        links.push(OpdsJsonLink::new(Some(rel), href, OPDS_JSON_TYPE));
+    }
+
+    let navigation = feed
+        .navigation
+        .iter()
+        .map(|entry| OpdsJsonLink {
+            title: Some(entry.title.clone()),
+            properties: entry
+                .count
+                .map(|c| OpdsJsonLinkProperties { number_of_items: c }),
+            ..OpdsJsonLink::new(
+                Some(entry.rel),
+                format!("{}{}", base, entry.href),
+                OPDS_JSON_TYPE,
+            )
+        })
+        .collect();
+
+    let publications = feed
+        .publications
+        .iter()
+        .map(|p| OpdsJsonPublication {
+            metadata: OpdsJsonPublicationMetadata {
+                schema_type: "http://schema.org/Book",
+                identifier: p.id.clone(),
+                title: p.title.clone(),
+                author: p
+                    .authors
+                    .iter()
+                    .map(|a| OpdsJsonContributor {
+                        name: a.name.clone(),
+                        links: vec![OpdsJsonLink::new(
+                            None,
+                            format!("{}{}", base, a.href),
+                            OPDS_JSON_TYPE,
+                        )],
+                    })
+                    .collect(),
+                language: p.language.clone(),
+                publisher: p.publisher.clone(),
+                published: p.published.clone(),
+                description: p.description.clone(),
+                subject: p
+                    .subjects
+                    .iter()
+                    .map(|s| OpdsJsonSubject { name: s.clone() })
+                    .collect(),
+                belongs_to: p.series.as_ref().map(|s| OpdsJsonBelongsTo {
+                    series: vec![OpdsJsonSeries {
+                        name: s.clone(),
+                        position: p.series_index,
+                    }],
+                }),
+                modified: p.updated,
+            },
+            links: vec![OpdsJsonLink::new(
+                Some("http://opds-spec.org/acquisition"),
+                p.acquisition_href.clone(),
+                EPUB_TYPE,
+            )],
+            images: p
+                .cover
+                .iter()
+                .map(|(href, media_type)| OpdsJsonLink::new(None, href.clone(), media_type))
+                .collect(),
+        })
+        .collect();
+
+    let pagination = feed.pagination;
+    serde_json::to_string(&OpdsJsonFeed {
+        metadata: OpdsJsonFeedMetadata {
+            title: feed.title.clone(),
+            modified: feed.updated,
+            number_of_items: pagination.map(|p| p.total),
+            items_per_page: pagination.map(|p| p.page_size),
+            current_page: pagination.map(|p| p.page.saturating_add(1)),
+        },
+        links,
+        navigation,
+        publications,
+    })
+}
@@ -0,0 +1,96 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use super::flac::FlacDecoder;
+
+/// Returns the header of a PCM WAV file holding the given number of samples per channel. If the
+/// number of samples is unknown, the sizes are set to their maximum, which players treat as a
+/// stream of unknown length.
+fn wav_header(channels: u16, sample_rate: u32, bps: u16, samples: Option<u64>) -> Vec<u8> {
+    let block_align = channels * (bps / 8);
+    let data_size = samples
+        .map(|s| s * block_align as u64)
+        .filter(|&s| s <= (u32::MAX - 36) as u64)
+        .map_or(u32::MAX - 36, |s| s as u32);
+
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(data_size + 36).to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+
+    // format chunk describing integer PCM samples
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&bps.to_le_bytes());
+
+    // data chunk, whose samples follow the header
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_size.to_le_bytes());
+    header
+}
+
+/// Decodes the samples from `start` up to `end` (or the end of the stream) and writes them as
+/// a standalone WAV file. Samples are widened to a whole number of bytes if needed, so that a
+/// 20-bit stream is written as 24-bit WAV.
+pub fn write_wav_segment<W: Write>(
+    mut decoder: FlacDecoder,
+    start: u64,
+    end: Option<u64>,
+    writer: &mut W,
+) -> Result<()> {
+    let info = decoder.metadata.stream_info.clone();
+    let end = match end {
+        Some(end) => Some(end),
+        None if info.total_samples > 0 => Some(info.total_samples),
+        None => None,
+    };
+    let bps = (info.bps as u16).div_ceil(8) * 8;
+    let shift = bps - info.bps as u16;
+    let samples = end.map(|end| end.saturating_sub(start));
+    writer.write_all(&wav_header(
+        info.channels as u16,
+        info.sample_rate,
+        bps,
+        samples,
+    ))?;
+
+    let mut buffer = Vec::new();
+    while let Some((block_start, block)) = decoder.next_block()? {
+        let block_end = block_start + block.duration() as u64;
+        if block_end <= start {
+            continue;
+        }
+        if let Some(end) = end
+            && block_start >= end
+        {
+            break;
+        }
+
+        // interleave the part of the block that lies within the segment
+        let from = start.saturating_sub(block_start) as u32;
+        let to = end.map_or(block.duration(), |end| {
+            (end.min(block_end) - block_start) as u32
+        });
+        buffer.clear();
+        for i in from..to {
+            for ch in 0..block.channels() {
+                let sample = block.sample(ch, i) << shift;
+                match bps {
+                    8 => buffer.push((sample + 128) as u8),
+                    16 => buffer.extend_from_slice(&(sample as i16).to_le_bytes()),
+                    24 => buffer.extend_from_slice(&sample.to_le_bytes()[..3]),
+                    _ => buffer.extend_from_slice(&sample.to_le_bytes()),
+                }
+            }
+        }
+        writer.write_all(&buffer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
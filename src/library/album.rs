@@ -1,13 +1,23 @@
+use std::path::Path;
+
 use anyhow::{Result, anyhow};
 use rand::rng;
 use rand::seq::IteratorRandom;
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityLoaderTrait, Order, QueryOrder, prelude::*};
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, EntityLoaderTrait, Order, QueryOrder, prelude::*,
+    sea_query::Expr,
+};
 use uuid::Uuid;
 
-use crate::db::{
-    album::{self, Entity as Album, ModelEx},
-    artist::Entity as Artist,
-    track::Entity as Track,
+use crate::{
+    db::{
+        album::{self, Entity as Album},
+        artist::Entity as Artist,
+        file::{self, Entity as File},
+        track::{self, Entity as Track},
+    },
+    library::scanner::scan_read_sidecar_picture,
+    settings::{ArtworkConfig, ArtworkPrecedence},
 };
 
 /// Checks if an album is a match with the given metadata. Assumes the names are the same.
@@ -16,64 +26,49 @@ use crate::db::{
 ///         (i) If both the album_name and album_artists are the same
 ///     (2) If only artists are present, then:
 ///         (i) If the album_name is the same, and at least one artist is in album_artists
-pub async fn album_match(
-    artists: &Vec<String>,
+pub fn album_match(
+    artists: &[String],
     album_artists: &Option<Vec<String>>,
-    album: &ModelEx,
+    album_artist_names: &[String],
 ) -> bool {
     // if the album_artists are present, then it is a match only if they are the same
     if let Some(aa) = album_artists {
-        if aa.len() != album.artists.len() {
+        if aa.len() != album_artist_names.len() {
             return false;
         }
         return aa
             .iter()
-            .all(|name| album.artists.iter().any(|artist| artist.name == *name));
+            .all(|name| album_artist_names.iter().any(|artist| artist == name));
     }
 
     // otherwise, check if at least one artist is in album_artists on the database
-    return artists
+    artists
         .iter()
-        .any(|name| album.artists.iter().any(|artist| artist.name == *name));
+        .any(|name| album_artist_names.iter().any(|artist| artist == name))
 }
 
-/// Checks if an album already exists in the database by matching the given metadata.
-/// A match is found by first checking if the same musicbrainz_album_id exists on the
-/// database, if it was provided to the function. Otherwise, it attempts to match each
-/// album on the database with the same album_name using the function album_match().
-pub async fn album_find(
+/// Gets all albums in the database with the given name, along with their artists.
+pub async fn album_find_by_name<C: ConnectionTrait>(
     album_name: &str,
-    artists: &Vec<String>,
-    album_artists: Option<Vec<String>>,
-    musicbrainz_album_id: Option<String>,
-    db: &DatabaseConnection,
-) -> Option<Uuid> {
-    // check case where musicbrainz_album_id exists both in the file and on the database
-    if let Some(id) = musicbrainz_album_id {
-        if let Ok(Some(m)) = Album::find()
-            .filter(album::Column::MusicbrainzId.eq(id))
-            .one(db)
-            .await
-        {
-            return Some(m.id);
-        }
-    }
-
-    // otherwise, find all albums in the database with the same album name
-    if let Ok(m) = Album::load()
+    db: &C,
+) -> Result<Vec<album::ModelEx>> {
+    Ok(Album::load()
         .filter(album::Column::Name.eq(album_name))
         .with(Artist)
         .all(db)
-        .await
-    {
-        for album in m {
-            if album_match(&artists, &album_artists, &album).await {
-                return Some(album.id);
-            }
-        }
-    }
+        .await?)
+}
 
-    return None;
+/// Gets the id of the album in the database with the given musicbrainz_album_id.
+pub async fn album_find_by_musicbrainz_id<C: ConnectionTrait>(
+    musicbrainz_album_id: &str,
+    db: &C,
+) -> Result<Option<Uuid>> {
+    Ok(Album::find()
+        .filter(album::Column::MusicbrainzId.eq(musicbrainz_album_id))
+        .one(db)
+        .await?
+        .map(|m| m.id))
 }
 
 /// Gets a list of random albums from the database.
@@ -113,3 +108,89 @@ pub async fn album_get_by_id(id: Uuid, db: &DatabaseConnection) -> Result<album:
         return Err(anyhow!("[ERROR] Album not found in database"));
     }
 }
+
+/// Chooses the picture of an album from the pictures of its tracks and the cover files next to
+/// its audio files, unless the picture was uploaded. Embedded front covers and cover files are
+/// preferred over other embedded pictures, in the configured order, and tracks are considered
+/// in the order of their files so that the choice does not depend on the order of the scan.
+pub async fn album_update_picture<C: ConnectionTrait>(
+    id: Uuid,
+    artwork: &ArtworkConfig,
+    db: &C,
+) -> Result<()> {
+    let Some(album) = Album::find_by_id(id).one(db).await? else {
+        return Ok(());
+    };
+    if album.custom_picture {
+        return Ok(());
+    }
+
+    // order the tracks of the album by their files
+    let tracks = Track::find()
+        .filter(track::Column::AlbumId.eq(id))
+        .all(db)
+        .await?;
+    let files = File::find()
+        .filter(file::Column::TrackId.is_in(tracks.iter().map(|t| t.id)))
+        .all(db)
+        .await?;
+    let mut ordered: Vec<(&str, &track::Model)> = tracks
+        .iter()
+        .filter_map(|t| {
+            let file = files.iter().find(|f| f.track_id == Some(t.id))?;
+            Some((file.path.as_str(), t))
+        })
+        .collect();
+    ordered.sort_by_key(|(path, t)| (*path, t.start_sample));
+
+    // find the first embedded front cover, other embedded picture and cover file
+    let front = ordered
+        .iter()
+        .find(|(_, t)| t.front_cover && t.picture.is_some())
+        .and_then(|(_, t)| t.picture.clone());
+    let other = ordered.iter().find_map(|(_, t)| t.picture.clone());
+    let mut directories: Vec<&Path> = ordered
+        .iter()
+        .filter_map(|(path, _)| Path::new(path).parent())
+        .collect();
+    directories.dedup();
+    let mut cover = None;
+    for directory in directories {
+        cover = scan_read_sidecar_picture(directory, &artwork.cover_files).await;
+        if cover.is_some() {
+            break;
+        }
+    }
+
+    let picture = match artwork.precedence {
+        ArtworkPrecedence::Embedded => front.or(cover),
+        ArtworkPrecedence::Sidecar => cover.or(front),
+    }
+    .or(other);
+    if picture != album.picture {
+        Album::update_many()
+            .col_expr(album::Column::Picture, Expr::value(picture))
+            .filter(album::Column::Id.eq(id))
+            .exec(db)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Sets the picture of a specific album in the database. The picture is kept across scans.
+pub async fn album_set_picture<C: ConnectionTrait>(
+    id: Uuid,
+    db: &C,
+    album_picture: Vec<u8>,
+) -> Result<()> {
+    let result = Album::update_many()
+        .col_expr(album::Column::Picture, Expr::value(album_picture))
+        .col_expr(album::Column::CustomPicture, Expr::value(true))
+        .filter(album::Column::Id.eq(id))
+        .exec(db)
+        .await?;
+    if result.rows_affected == 0 {
+        return Err(anyhow!("[ERROR] Album not found in database"));
+    }
+    Ok(())
+}
@@ -0,0 +1,263 @@
+use std::{
+    collections::BTreeSet,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, prelude::Uuid, sea_query::Expr,
+};
+use serde::Serialize;
+
+use crate::{
+    db::{
+        album::{self, Entity as Album},
+        file::{self, Entity as File},
+        track::{self, Entity as Track},
+    },
+    format::flac::FlacDecoder,
+    library::{
+        job::JobFlag,
+        loudness::{Loudness, LoudnessMeter, REPLAY_GAIN_REFERENCE, integrated_loudness},
+        scan_cache::LibraryLock,
+    },
+};
+
+/// A track to analyze: its id, the path of its audio file and the samples it spans.
+struct AnalyzeTarget {
+    track_id: Uuid,
+    has_gain: bool,
+    path: String,
+    start_sample: u64,
+    end_sample: Option<u64>,
+}
+
+/// Decodes the samples of a track and measures their loudness.
+fn analyze_track(path: &Path, start: u64, end: Option<u64>) -> Result<Loudness> {
+    let mut decoder = FlacDecoder::open_at(path, start)?;
+    let info = decoder.metadata.stream_info.clone();
+    let scale = (1u64 << (info.bps - 1)) as f64;
+    let mut meter = LoudnessMeter::new(info.channels as u32, info.sample_rate);
+    let mut samples = vec![0.0; info.channels as usize];
+    while let Some((block_start, block)) = decoder.next_block()? {
+        let block_end = block_start + block.duration() as u64;
+        if block_end <= start {
+            continue;
+        }
+        if let Some(end) = end
+            && block_start >= end
+        {
+            break;
+        }
+
+        // measure the part of the block that lies within the track
+        let from = start.saturating_sub(block_start) as u32;
+        let to = end.map_or(block.duration(), |end| {
+            (end.min(block_end) - block_start) as u32
+        });
+        for i in from..to {
+            for (ch, sample) in samples.iter_mut().enumerate() {
+                *sample = block.sample(ch as u32, i) as f64 / scale;
+            }
+            meter.add(&samples);
+        }
+    }
+    Ok(meter.finish())
+}
+
+/// Progress of the current (or most recent) loudness analysis.
+#[derive(Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyzeStatus {
+    pub analyzing: bool,
+    pub total: u64,
+    pub count: u64,
+    pub analyzed: u64,
+    pub failed: u64,
+    pub current_album: Option<String>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Computes ReplayGain values for the tracks and albums of the library that have no loudness
+/// tags in the background, making sure that only one analysis runs at a time.
+#[derive(Default)]
+pub struct Analyzer {
+    running: JobFlag,
+    status: Mutex<AnalyzeStatus>,
+}
+
+impl Analyzer {
+    /// Returns a snapshot of the progress of the current (or most recent) analysis.
+    pub fn status(&self) -> AnalyzeStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Starts analyzing the library as a background task.
+    pub fn start(
+        self: &Arc<Self>,
+        library: &Arc<LibraryLock>,
+        db: &Arc<DatabaseConnection>,
+    ) -> Result<()> {
+        let analyzer = self.clone();
+        let library = library.clone();
+        let db = db.clone();
+        let finished = self.clone();
+        self.running.spawn(
+            "[ERROR] A loudness analysis is already in progress",
+            || {
+                *self.status.lock().unwrap() = AnalyzeStatus {
+                    analyzing: true,
+                    started_at: Some(Utc::now()),
+                    ..Default::default()
+                };
+                async move {
+                    if let Err(e) = analyzer.analyze(&library, &db).await {
+                        println!("[ERROR] Failed to analyze library loudness: {}", e);
+                    }
+                }
+            },
+            move || {
+                let mut status = finished.status.lock().unwrap();
+                status.analyzing = false;
+                status.current_album = None;
+                status.finished_at = Some(Utc::now());
+            },
+        )
+    }
+
+    /// Analyzes the albums that have tracks without a track gain, which have not been analyzed
+    /// before. Every track of such an album is decoded, as the album gain is measured over all
+    /// of its tracks together.
+    async fn analyze(&self, library: &LibraryLock, db: &DatabaseConnection) -> Result<()> {
+        let album_ids: BTreeSet<Uuid> = Track::find()
+            .filter(track::Column::TrackGain.is_null())
+            .filter(track::Column::AnalyzedAt.is_null())
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|t| t.album_id)
+            .collect();
+        self.status.lock().unwrap().total = album_ids.len() as u64;
+
+        for album_id in album_ids {
+            let Some(album) = Album::find_by_id(album_id).one(db).await? else {
+                continue;
+            };
+            self.status.lock().unwrap().current_album = Some(album.name.clone());
+            if let Err(e) = self.analyze_album(&album, library, db).await {
+                println!("[ERROR] Failed to analyze album {}: {}", album.name, e);
+            }
+            self.status.lock().unwrap().count += 1;
+        }
+        Ok(())
+    }
+
+    async fn analyze_album(
+        &self,
+        album: &album::Model,
+        library: &LibraryLock,
+        db: &DatabaseConnection,
+    ) -> Result<()> {
+        // find the audio of every track of the album
+        let tracks = Track::find()
+            .filter(track::Column::AlbumId.eq(album.id))
+            .all(db)
+            .await?;
+        let files = File::find()
+            .filter(file::Column::TrackId.is_in(tracks.iter().map(|t| t.id)))
+            .all(db)
+            .await?;
+        let targets: Vec<AnalyzeTarget> = tracks
+            .iter()
+            .filter_map(|t| {
+                let file = files.iter().find(|f| f.track_id == Some(t.id))?;
+                Some(AnalyzeTarget {
+                    track_id: t.id,
+                    has_gain: t.track_gain.is_some(),
+                    path: file.path.clone(),
+                    start_sample: t.start_sample.unwrap_or(0) as u64,
+                    end_sample: t.end_sample.map(|e| e as u64),
+                })
+            })
+            .collect();
+
+        // decoding is slow and CPU bound, so the tracks are decoded one at a time
+        let mut results = Vec::new();
+        for target in targets {
+            let measured = tokio::task::spawn_blocking(move || {
+                let loudness = analyze_track(
+                    Path::new(&target.path),
+                    target.start_sample,
+                    target.end_sample,
+                );
+                (target, loudness)
+            })
+            .await?;
+            match measured {
+                (target, Ok(loudness)) => results.push((target, loudness)),
+                (target, Err(e)) => {
+                    println!("[ERROR] Failed to analyze {}: {}", target.path, e);
+                    self.status.lock().unwrap().failed += 1;
+                }
+            }
+        }
+
+        // the album is measured by gating the blocks of all of its tracks together, unless its
+        // gain came from tags
+        let blocks: Vec<f64> = results
+            .iter()
+            .flat_map(|(_, l)| l.blocks.iter().copied())
+            .collect();
+        let measured_peak = results.iter().map(|(_, l)| l.peak).reduce(f64::max);
+        let (album_gain, album_peak) = match album.gain {
+            Some(gain) => (Some(gain), album.peak.or(measured_peak)),
+            None => (
+                integrated_loudness(&blocks).map(|l| REPLAY_GAIN_REFERENCE - l),
+                measured_peak,
+            ),
+        };
+
+        // only fill in values that did not come from tags, and mark silent tracks as analyzed
+        // so that they are not decoded again, as they have no loudness to store
+        let _lock = library.lock().await;
+        let analyzed_at = Utc::now();
+        for (target, loudness) in &results {
+            let mut update =
+                Track::update_many().col_expr(track::Column::AnalyzedAt, Expr::value(analyzed_at));
+            if let Some(lufs) = integrated_loudness(&loudness.blocks)
+                && !target.has_gain
+            {
+                update = update
+                    .col_expr(
+                        track::Column::TrackGain,
+                        Expr::value(REPLAY_GAIN_REFERENCE - lufs),
+                    )
+                    .col_expr(track::Column::TrackPeak, Expr::value(loudness.peak));
+                if let (Some(gain), Some(peak)) = (album_gain, album_peak) {
+                    update = update
+                        .col_expr(track::Column::AlbumGain, Expr::value(gain))
+                        .col_expr(track::Column::AlbumPeak, Expr::value(peak));
+                }
+                self.status.lock().unwrap().analyzed += 1;
+            }
+            update
+                .filter(track::Column::Id.eq(target.track_id))
+                .exec(db)
+                .await?;
+        }
+        if album.gain.is_none()
+            && let (Some(gain), Some(peak)) = (album_gain, album_peak)
+        {
+            Album::update_many()
+                .col_expr(album::Column::Gain, Expr::value(gain))
+                .col_expr(album::Column::Peak, Expr::value(peak))
+                .filter(album::Column::Id.eq(album.id))
+                .exec(db)
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,334 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{Result, anyhow};
+use chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait, Order, QueryFilter,
+    QueryOrder, QuerySelect, Set,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    db::annotation::{self, Entity as Annotation},
+    format::epub::{EpubTocEntry, parse_epub_toc},
+    library::{
+        book::{book_get_by_id, book_like_contains},
+        reading::{reading_check_book, reading_parse_cfi_spine_index},
+        shelf::get_user_id,
+    },
+};
+
+const ANNOTATION_DEFAULT_COLOR: &str = "yellow";
+
+/// The formats annotations are exported in.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnotationExportFormat {
+    #[default]
+    Markdown,
+    Json,
+}
+
+/// An annotation along with the label of the chapter it is in, if the table of contents has one.
+#[derive(Serialize)]
+struct AnnotationExportEntry {
+    chapter: Option<String>,
+    #[serde(flatten)]
+    annotation: annotation::Model,
+}
+
+/// The annotations of a user in a book, as exported to JSON.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AnnotationExport {
+    book_id: String,
+    title: String,
+    authors: Vec<String>,
+    annotations: Vec<AnnotationExportEntry>,
+}
+
+/// Checks that a color is a hex color such as "#ffcc00" or a name such as "yellow".
+fn annotation_check_color(color: &str) -> Result<()> {
+    let valid = match color.strip_prefix('#') {
+        Some(hex) => [3, 6].contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        None => {
+            !color.is_empty() && color.len() <= 20 && color.chars().all(|c| c.is_ascii_lowercase())
+        }
+    };
+    if !valid {
+        return Err(anyhow!("[ERROR] Invalid annotation color"));
+    }
+    Ok(())
+}
+
+/// Gets the steps of the path to the start of a CFI range, such as [6, 4, 4, 2, 1, 12] for
+/// "epubcfi(/6/4[chapter]!/4/2,/1:12,/1:20)". Comparing the steps puts annotations in the
+/// order they appear in the book.
+fn annotation_cfi_steps(cfi: &str) -> Vec<u32> {
+    let path = cfi
+        .trim_start_matches("epubcfi(")
+        .trim_end_matches(')')
+        .splitn(3, ',')
+        .take(2)
+        .collect::<String>();
+
+    // skip the id assertions in brackets, which aren't part of the position
+    let mut steps = Vec::new();
+    let mut number = String::new();
+    let mut in_assertion = false;
+    for c in path.chars().chain(std::iter::once('/')) {
+        match c {
+            '[' => in_assertion = true,
+            ']' => in_assertion = false,
+            _ if in_assertion => {}
+            '0'..='9' => number.push(c),
+            _ => {
+                if let Ok(n) = number.parse() {
+                    steps.push(n);
+                }
+                number.clear();
+            }
+        }
+    }
+    steps
+}
+
+/// Adds a highlight to a book for a user, with an optional note on it.
+pub async fn annotation_create(
+    username: &str,
+    book_id: Uuid,
+    cfi_range: String,
+    text: String,
+    color: Option<String>,
+    note: Option<String>,
+    db: &DatabaseConnection,
+) -> Result<annotation::Model> {
+    let user_id = get_user_id(username, db).await?;
+    reading_check_book(book_id, db).await?;
+    if !cfi_range.starts_with("epubcfi(") || !cfi_range.ends_with(')') {
+        return Err(anyhow!("[ERROR] Invalid CFI range"));
+    }
+    let color = color.unwrap_or(ANNOTATION_DEFAULT_COLOR.to_owned());
+    annotation_check_color(&color)?;
+    let now = Utc::now();
+    let annotation = annotation::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        user_id: Set(user_id),
+        book_id: Set(book_id),
+        cfi_range: Set(cfi_range),
+        text: Set(text),
+        color: Set(color),
+        note: Set(note.filter(|n| !n.is_empty())),
+        created_at: Set(now),
+        updated_at: Set(now),
+    };
+    Ok(annotation.insert(db).await?)
+}
+
+/// Changes the color or note of an annotation of a user. An empty note removes the note.
+pub async fn annotation_update(
+    username: &str,
+    id: Uuid,
+    color: Option<String>,
+    note: Option<String>,
+    db: &DatabaseConnection,
+) -> Result<annotation::Model> {
+    let user_id = get_user_id(username, db).await?;
+    let existing = Annotation::find_by_id(id)
+        .filter(annotation::Column::UserId.eq(user_id))
+        .one(db)
+        .await?
+        .ok_or(anyhow!("[ERROR] Annotation not found"))?;
+    let mut annotation: annotation::ActiveModel = existing.into();
+    if let Some(color) = color {
+        annotation_check_color(&color)?;
+        annotation.color = Set(color);
+    }
+    if let Some(note) = note {
+        annotation.note = Set(Some(note).filter(|n| !n.is_empty()));
+    }
+    annotation.updated_at = Set(Utc::now());
+    Ok(annotation.update(db).await?)
+}
+
+/// Deletes an annotation of a user.
+pub async fn annotation_delete(username: &str, id: Uuid, db: &DatabaseConnection) -> Result<()> {
+    let user_id = get_user_id(username, db).await?;
+    let result = Annotation::delete_many()
+        .filter(annotation::Column::Id.eq(id))
+        .filter(annotation::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+    if result.rows_affected == 0 {
+        return Err(anyhow!("[ERROR] Annotation not found"));
+    }
+    Ok(())
+}
+
+/// Gets the annotations of a user in a book, in the order they appear in the book.
+pub async fn annotation_get_list(
+    username: &str,
+    book_id: Uuid,
+    db: &DatabaseConnection,
+) -> Result<Vec<annotation::Model>> {
+    let user_id = get_user_id(username, db).await?;
+    let mut annotations = Annotation::find()
+        .filter(annotation::Column::UserId.eq(user_id))
+        .filter(annotation::Column::BookId.eq(book_id))
+        .order_by(annotation::Column::CreatedAt, Order::Asc)
+        .all(db)
+        .await?;
+    annotations.sort_by_cached_key(|a| annotation_cfi_steps(&a.cfi_range));
+    Ok(annotations)
+}
+
+/// Searches the notes and highlighted text of the annotations of a user, optionally in one
+/// book. Every word of the query must appear in either as written, ignoring case, and the most
+/// recently changed annotations come first. Wildcards in the query match themselves.
+pub async fn annotation_search(
+    username: &str,
+    query: &str,
+    book_id: Option<Uuid>,
+    len: u32,
+    db: &DatabaseConnection,
+) -> Result<Vec<annotation::Model>> {
+    let user_id = get_user_id(username, db).await?;
+    let mut condition = Condition::all().add(annotation::Column::UserId.eq(user_id));
+    if let Some(book_id) = book_id {
+        condition = condition.add(annotation::Column::BookId.eq(book_id));
+    }
+    let mut words = query.split_whitespace().peekable();
+    if words.peek().is_none() {
+        return Err(anyhow!("[ERROR] Search query is empty"));
+    }
+    for word in words {
+        condition = condition.add(
+            Condition::any()
+                .add(annotation::Column::Note.like(book_like_contains(word)))
+                .add(annotation::Column::Text.like(book_like_contains(word))),
+        );
+    }
+    Ok(Annotation::find()
+        .filter(condition)
+        .order_by(annotation::Column::UpdatedAt, Order::Desc)
+        .limit(len as u64)
+        .all(db)
+        .await?)
+}
+
+/// Maps the documents of a book to the labels of the table of contents entries pointing to
+/// them, keeping the first entry for each document.
+fn annotation_collect_labels(entries: &[EpubTocEntry], labels: &mut HashMap<String, String>) {
+    for entry in entries {
+        if let Some(path) = &entry.path {
+            labels
+                .entry(path.clone())
+                .or_insert_with(|| entry.label.clone());
+        }
+        annotation_collect_labels(&entry.children, labels);
+    }
+}
+
+/// Gets the chapter label of every document in the spine of a book. Documents without an entry
+/// in the table of contents belong to the chapter before them.
+async fn annotation_get_chapters(path: PathBuf) -> Vec<Option<String>> {
+    let Ok(Ok(toc)) = tokio::task::spawn_blocking(move || parse_epub_toc(&path)).await else {
+        return Vec::new();
+    };
+    let mut labels = HashMap::new();
+    annotation_collect_labels(&toc.entries, &mut labels);
+    let mut chapter = None;
+    toc.spine
+        .iter()
+        .map(|p| {
+            if let Some(label) = labels.get(p) {
+                chapter = Some(label.clone());
+            }
+            chapter.clone()
+        })
+        .collect()
+}
+
+/// Escapes the characters Markdown would read as formatting.
+fn annotation_escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_[]<>#|".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Exports the annotations of a user in a book as Markdown or JSON, grouped by the chapters of
+/// the book. Returns the document and its content type.
+pub async fn annotation_export(
+    username: &str,
+    book_id: Uuid,
+    format: AnnotationExportFormat,
+    db: &DatabaseConnection,
+) -> Result<(String, &'static str)> {
+    let book = book_get_by_id(book_id, db).await?;
+    let annotations = annotation_get_list(username, book_id, db).await?;
+    let chapters = match book.file.as_ref() {
+        Some(f) => annotation_get_chapters(PathBuf::from(&f.path)).await,
+        None => Vec::new(),
+    };
+    let authors: Vec<String> = book.artists.iter().map(|a| a.name.clone()).collect();
+    let entries: Vec<AnnotationExportEntry> = annotations
+        .into_iter()
+        .map(|annotation| AnnotationExportEntry {
+            chapter: reading_parse_cfi_spine_index(&annotation.cfi_range)
+                .and_then(|i| chapters.get(i as usize).cloned().flatten()),
+            annotation,
+        })
+        .collect();
+
+    match format {
+        AnnotationExportFormat::Json => {
+            let export = AnnotationExport {
+                book_id: book.id.to_string(),
+                title: book.title,
+                authors,
+                annotations: entries,
+            };
+            Ok((serde_json::to_string_pretty(&export)?, "application/json"))
+        }
+        AnnotationExportFormat::Markdown => {
+            let mut markdown = format!("# {}\n", annotation_escape_markdown(&book.title));
+            if !authors.is_empty() {
+                markdown.push_str(&format!(
+                    "\n*{}*\n",
+                    annotation_escape_markdown(&authors.join(", "))
+                ));
+            }
+
+            // start a section whenever the chapter changes, leaving out the heading for any
+            // annotations before the first chapter
+            let mut chapter = None;
+            for entry in &entries {
+                if entry.chapter != chapter {
+                    chapter = entry.chapter.clone();
+                    let heading = chapter.as_deref().unwrap_or("Untitled");
+                    markdown.push_str(&format!("\n## {}\n", annotation_escape_markdown(heading)));
+                }
+                let annotation = &entry.annotation;
+                markdown.push('\n');
+                for line in annotation.text.lines() {
+                    markdown.push_str(&format!("> {}\n", annotation_escape_markdown(line)));
+                }
+                if let Some(note) = &annotation.note {
+                    markdown.push_str(&format!("\n{}\n", note));
+                }
+                markdown.push_str(&format!(
+                    "\n*{}, {}*\n",
+                    annotation.color,
+                    annotation.created_at.format("%Y-%m-%d")
+                ));
+            }
+            Ok((markdown, "text/markdown; charset=utf-8"))
+        }
+    }
+}
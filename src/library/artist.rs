@@ -1,48 +1,70 @@
 use anyhow::{Result, anyhow};
 use sea_orm::entity::prelude::*;
 use sea_orm::{
-    ColumnTrait, DatabaseConnection, EntityLoaderTrait, EntityTrait, Order, QueryOrder,
-    QuerySelect, Set,
+    ColumnTrait, Condition, DatabaseConnection, EntityLoaderTrait, EntityTrait, Order, QueryOrder,
+    QuerySelect, Set, TransactionTrait, sea_query::Query,
 };
 use uuid::Uuid;
 
 use crate::db::{
     album::Entity as Album,
+    album_artists,
     artist::{self, Entity as Artist},
     track::Entity as Track,
+    track_artists,
 };
 
 /// Checks if an artist already exists in the database by matching the given metadata.
 /// A match is found if there is an artist with the same artist_name.
-async fn artist_find(artist_name: &str, db: &DatabaseConnection) -> Option<artist::ActiveModel> {
+pub async fn artist_find<C: ConnectionTrait>(artist_name: &str, db: &C) -> Option<artist::Model> {
     if let Ok(Some(m)) = Artist::find()
         .filter(artist::Column::Name.eq(artist_name))
         .one(db)
         .await
     {
-        return Some(m.into());
+        return Some(m);
     }
     return None;
 }
 
-/// Returns the active model of either the artist that is already existing in the database,
-/// or of a new artist if none matches within the database. The new artist is not yet inserted
-/// into the database.
-pub async fn artist_insert(name: &str, db: &DatabaseConnection) -> artist::ActiveModel {
+/// Returns either the artist that is already existing in the database, or a new artist that
+/// is inserted into the database if none matches.
+pub async fn artist_insert<C: ConnectionTrait>(name: &str, db: &C) -> Result<artist::Model> {
     if let Some(m) = artist_find(name.trim(), db).await {
-        return m;
+        Ok(m)
     } else {
-        return artist::ActiveModel {
+        let artist = artist::ActiveModel {
             id: Set(Uuid::new_v4()),
             name: Set(name.trim().to_owned()),
             picture: Set(None),
         };
+        Ok(artist.insert(db).await?)
     }
 }
 
-/// Returns a sorted list of all the artists in the database.
+/// Returns a sorted list of all the artists in the database that made music, leaving out those
+/// that only wrote books.
 pub async fn artist_get_list(len: u32, db: &DatabaseConnection) -> Vec<artist::Model> {
     if let Ok(m) = Artist::find()
+        .filter(
+            Condition::any()
+                .add(
+                    artist::Column::Id.in_subquery(
+                        Query::select()
+                            .column(album_artists::Column::ArtistId)
+                            .from(album_artists::Entity)
+                            .to_owned(),
+                    ),
+                )
+                .add(
+                    artist::Column::Id.in_subquery(
+                        Query::select()
+                            .column(track_artists::Column::ArtistId)
+                            .from(track_artists::Entity)
+                            .to_owned(),
+                    ),
+                ),
+        )
         .order_by(artist::Column::Name, Order::Asc)
         .limit(len as u64)
         .all(db)
@@ -70,9 +92,9 @@ pub async fn artist_get_by_id(id: Uuid, db: &DatabaseConnection) -> Result<artis
 }
 
 /// Sets the picture of a specific artist in the database.
-pub async fn artist_set_picture(
+pub async fn artist_set_picture<C: ConnectionTrait + TransactionTrait>(
     id: Uuid,
-    db: &DatabaseConnection,
+    db: &C,
     artist_picture: Vec<u8>,
 ) -> Result<()> {
     let mut artist = artist::ActiveModel::builder().set_id(id);
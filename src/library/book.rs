@@ -1,32 +1,213 @@
+use std::collections::{BTreeMap, HashMap};
+
 use anyhow::{Result, anyhow};
-use sea_orm::{DatabaseConnection, EntityLoaderTrait, Order, QueryOrder};
+use base64::{Engine, engine::general_purpose};
+use sea_orm::{
+    ColumnTrait, Condition, DatabaseConnection, EntityLoaderTrait, EntityTrait, Order, QueryFilter,
+    QueryOrder, QuerySelect,
+    sea_query::{Expr, ExprTrait, LikeExpr, NullOrdering, Query},
+};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::db::{
-    artist::Entity as Artist,
+    artist::{self, Entity as Artist},
     book::{self, Entity as Book},
+    book_artists,
+    book_subjects::{self, Entity as BookSubject},
     file::Entity as File,
+    starred_books,
 };
 
-/// Returns a sorted list of all the books in the database.
-pub async fn book_get_list(len: u32, db: &DatabaseConnection) -> Vec<book::ModelEx> {
-    if let Ok(m) = Book::load()
+/// The fields books can be sorted by.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum BookListSort {
+    #[default]
+    Title,
+    PublicationDate,
+    Publisher,
+    Language,
+    Series,
+    Added,
+}
+
+/// Filters for the list of books. Text filters match the whole value, ignoring case, and the
+/// identifier filter matches any kind of identifier. The query matches part of the title, series
+/// or name of an author.
+#[derive(Default)]
+pub struct BookListFilter {
+    pub language: Option<String>,
+    pub publisher: Option<String>,
+    pub subject: Option<String>,
+    pub series: Option<String>,
+    pub identifier: Option<String>,
+    pub author_id: Option<Uuid>,
+    pub starred_by: Option<Uuid>,
+    pub query: Option<String>,
+}
+
+/// Escapes the wildcards of LIKE in a value, so that they match themselves.
+fn book_like_escape(value: &str) -> String {
+    let mut pattern = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+/// Builds a LIKE pattern that matches the whole value ignoring case, as LIKE does in SQLite,
+/// with the wildcards in the value escaped so that they match themselves.
+fn book_like(value: &str) -> LikeExpr {
+    LikeExpr::new(book_like_escape(value)).escape('\\')
+}
+
+/// Builds a LIKE pattern that matches values containing the given value, ignoring case, with
+/// its wildcards escaped.
+pub fn book_like_contains(value: &str) -> LikeExpr {
+    LikeExpr::new(format!("%{}%", book_like_escape(value))).escape('\\')
+}
+
+/// Builds the condition books must meet to pass the filter.
+async fn book_list_condition(
+    filter: &BookListFilter,
+    db: &DatabaseConnection,
+) -> Result<Condition> {
+    let mut condition = Condition::all();
+    if let Some(language) = &filter.language {
+        condition = condition.add(book::Column::Language.like(book_like(language)));
+    }
+    if let Some(publisher) = &filter.publisher {
+        condition = condition.add(book::Column::Publisher.like(book_like(publisher)));
+    }
+    if let Some(series) = &filter.series {
+        condition = condition.add(book::Column::Series.like(book_like(series)));
+    }
+    if let Some(identifier) = &filter.identifier {
+        condition = condition.add(
+            Condition::any()
+                .add(book::Column::Isbn.like(book_like(identifier)))
+                .add(book::Column::Asin.like(book_like(identifier)))
+                .add(book::Column::Uuid.like(book_like(identifier))),
+        );
+    }
+    if let Some(subject) = &filter.subject {
+        let ids = BookSubject::find()
+            .filter(book_subjects::Column::Subject.like(book_like(subject)))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|s| s.book_id);
+        condition = condition.add(book::Column::Id.is_in(ids));
+    }
+    if let Some(author_id) = filter.author_id {
+        condition = condition.add(
+            book::Column::Id.in_subquery(
+                Query::select()
+                    .column(book_artists::Column::BookId)
+                    .from(book_artists::Entity)
+                    .and_where(book_artists::Column::ArtistId.eq(author_id))
+                    .to_owned(),
+            ),
+        );
+    }
+    if let Some(user_id) = filter.starred_by {
+        condition = condition.add(
+            book::Column::Id.in_subquery(
+                Query::select()
+                    .column(starred_books::Column::BookId)
+                    .from(starred_books::Entity)
+                    .and_where(starred_books::Column::UserId.eq(user_id))
+                    .to_owned(),
+            ),
+        );
+    }
+    if let Some(query) = &filter.query {
+        let authored = Query::select()
+            .column((book_artists::Entity, book_artists::Column::BookId))
+            .from(book_artists::Entity)
+            .inner_join(
+                artist::Entity,
+                Expr::col((artist::Entity, artist::Column::Id))
+                    .equals((book_artists::Entity, book_artists::Column::ArtistId)),
+            )
+            .and_where(artist::Column::Name.contains(query))
+            .to_owned();
+        condition = condition.add(
+            Condition::any()
+                .add(book::Column::Title.contains(query))
+                .add(book::Column::Series.contains(query))
+                .add(book::Column::Id.in_subquery(authored)),
+        );
+    }
+    Ok(condition)
+}
+
+/// Sorts a query for books by the given field, then by title.
+fn book_list_order<Q: QueryOrder>(query: Q, sort: BookListSort, order: Order) -> Q {
+    let column = match sort {
+        BookListSort::Title => book::Column::Title,
+        BookListSort::PublicationDate => book::Column::PublicationDate,
+        BookListSort::Publisher => book::Column::Publisher,
+        BookListSort::Language => book::Column::Language,
+        BookListSort::Series => book::Column::Series,
+        BookListSort::Added => book::Column::AddedAt,
+    };
+    let mut query = query.order_by_with_nulls(column, order.clone(), NullOrdering::Last);
+    if let BookListSort::Series = sort {
+        query = query.order_by_with_nulls(book::Column::SeriesIndex, order, NullOrdering::Last);
+    }
+    query.order_by(book::Column::Title, Order::Asc)
+}
+
+/// Returns a list of the books in the database matching the filter, sorted by the given field.
+/// Books without a value for the field come last either way.
+pub async fn book_get_list(
+    len: u32,
+    filter: &BookListFilter,
+    sort: BookListSort,
+    order: Order,
+    db: &DatabaseConnection,
+) -> Result<Vec<book::ModelEx>> {
+    let query = Book::load()
         .with(Artist)
-        .order_by(book::Column::Title, Order::Asc)
+        .with(BookSubject)
+        .filter(book_list_condition(filter, db).await?);
+    Ok(book_list_order(query, sort, order)
         .paginate(db, len as u64)
         .fetch()
-        .await
-    {
-        return m;
-    } else {
-        return Vec::new();
-    }
+        .await?)
+}
+
+/// Returns a page of the books in the database matching the filter, sorted like
+/// `book_get_list`, along with the number of books matching it. Pages are numbered from zero.
+pub async fn book_get_page(
+    page: u64,
+    page_size: u64,
+    filter: &BookListFilter,
+    sort: BookListSort,
+    order: Order,
+    db: &DatabaseConnection,
+) -> Result<(Vec<book::ModelEx>, u64)> {
+    let query = Book::load()
+        .with(Artist)
+        .with(BookSubject)
+        .filter(book_list_condition(filter, db).await?);
+    let paginator = book_list_order(query, sort, order).paginate(db, page_size);
+    Ok((
+        paginator.fetch_page(page).await?,
+        paginator.num_items().await?,
+    ))
 }
 
 /// Gets a specific book from the database.
 pub async fn book_get_by_id(id: Uuid, db: &DatabaseConnection) -> Result<book::ModelEx> {
     if let Ok(Some(a)) = Book::load()
         .with(Artist)
+        .with(BookSubject)
         .with(File)
         .filter_by_id(id)
         .one(db)
@@ -37,3 +218,136 @@ pub async fn book_get_by_id(id: Uuid, db: &DatabaseConnection) -> Result<book::M
         return Err(anyhow!("[ERROR] Book not found in database"));
     }
 }
+
+/// A series of books, with the cover of its first book that has one.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BookSeries {
+    pub name: String,
+    pub book_count: usize,
+    pub picture: Option<String>,
+}
+
+/// A series of books, with its books in the order of the series.
+#[derive(Serialize)]
+pub struct BookSeriesDetail {
+    pub name: String,
+    pub books: Vec<book::ModelEx>,
+}
+
+/// A language books are written in, with the number of books.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BookLanguage {
+    pub language: String,
+    pub book_count: usize,
+}
+
+/// An artist that wrote books, with the number of books.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BookAuthor {
+    #[serde(flatten)]
+    pub artist: artist::Model,
+    pub book_count: usize,
+}
+
+/// Returns a sorted list of the series of books. Series names are compared ignoring case, as
+/// books of a series may not spell its name the same way.
+pub async fn book_get_series_list(db: &DatabaseConnection) -> Result<Vec<BookSeries>> {
+    let books = Book::find()
+        .filter(book::Column::Series.is_not_null())
+        .order_by_with_nulls(book::Column::SeriesIndex, Order::Asc, NullOrdering::Last)
+        .order_by(book::Column::Title, Order::Asc)
+        .all(db)
+        .await?;
+    let mut series: BTreeMap<String, BookSeries> = BTreeMap::new();
+    for book in books {
+        let Some(name) = book.series else {
+            continue;
+        };
+        let entry = series.entry(name.to_lowercase()).or_insert(BookSeries {
+            name,
+            book_count: 0,
+            picture: None,
+        });
+        entry.book_count += 1;
+        if entry.picture.is_none() {
+            entry.picture = book.picture.map(|p| general_purpose::STANDARD.encode(p));
+        }
+    }
+    Ok(series.into_values().collect())
+}
+
+/// Gets a specific series of books, ordered by their index in the series.
+pub async fn book_get_series(name: &str, db: &DatabaseConnection) -> Result<BookSeriesDetail> {
+    let books = Book::load()
+        .with(Artist)
+        .with(BookSubject)
+        .filter(book::Column::Series.like(book_like(name.trim())))
+        .order_by_with_nulls(book::Column::SeriesIndex, Order::Asc, NullOrdering::Last)
+        .order_by(book::Column::Title, Order::Asc)
+        .all(db)
+        .await?;
+    let name = books
+        .first()
+        .and_then(|b| b.series.clone())
+        .ok_or(anyhow!("[ERROR] Series not found in database"))?;
+    Ok(BookSeriesDetail { name, books })
+}
+
+/// Returns a sorted list of the artists that wrote books, leaving out those that only made
+/// music.
+pub async fn book_get_authors(len: u32, db: &DatabaseConnection) -> Result<Vec<BookAuthor>> {
+    let authors = Artist::find()
+        .filter(
+            artist::Column::Id.in_subquery(
+                Query::select()
+                    .column(book_artists::Column::ArtistId)
+                    .from(book_artists::Entity)
+                    .to_owned(),
+            ),
+        )
+        .order_by(artist::Column::Name, Order::Asc)
+        .limit(len as u64)
+        .all(db)
+        .await?;
+    let mut counts: HashMap<Uuid, usize> = HashMap::new();
+    for link in book_artists::Entity::find()
+        .filter(book_artists::Column::ArtistId.is_in(authors.iter().map(|a| a.id)))
+        .all(db)
+        .await?
+    {
+        *counts.entry(link.artist_id).or_default() += 1;
+    }
+    Ok(authors
+        .into_iter()
+        .map(|artist| BookAuthor {
+            book_count: counts.get(&artist.id).copied().unwrap_or_default(),
+            artist,
+        })
+        .collect())
+}
+
+/// Returns a sorted list of the languages books are written in. Language tags are compared
+/// ignoring case.
+pub async fn book_get_languages(db: &DatabaseConnection) -> Result<Vec<BookLanguage>> {
+    let books = Book::find()
+        .filter(book::Column::Language.is_not_null())
+        .all(db)
+        .await?;
+    let mut languages: BTreeMap<String, BookLanguage> = BTreeMap::new();
+    for book in books {
+        let Some(language) = book.language else {
+            continue;
+        };
+        languages
+            .entry(language.to_lowercase())
+            .or_insert(BookLanguage {
+                language,
+                book_count: 0,
+            })
+            .book_count += 1;
+    }
+    Ok(languages.into_values().collect())
+}
@@ -0,0 +1,477 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Result, anyhow};
+use chrono::Utc;
+use regex::Regex;
+use sea_orm::{
+    ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder,
+    TransactionTrait, sea_query::Expr,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        album::{self, Entity as Album},
+        album_artists,
+        artist::{self, Entity as Artist},
+        file::{self, Entity as File},
+        metadata_edit::{self, Entity as MetadataEdit},
+        track::{self, Entity as Track},
+        track_artists,
+    },
+    format::flac::{FlacTagEdit, parse_flac_file},
+    library::{
+        scan_cache::{LibraryLock, ScanCache},
+        tags::{tags_valid_field, tags_write_file},
+    },
+    settings::ArtworkConfig,
+};
+
+/// The tracks to edit: those of the given albums, of the given artist, and matching the search
+/// query in their title, album or artist. Tracks have to match every given criterion.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkEditSelection {
+    pub album_ids: Option<Vec<Uuid>>,
+    pub artist_id: Option<Uuid>,
+    pub query: Option<String>,
+}
+
+/// An operation on the values of a tag. Operations are applied in order, so that later ones
+/// see the values left by earlier ones.
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub enum BulkEditOperation {
+    /// Replaces the values of the tag with a single value.
+    Set { field: String, value: String },
+    /// Replaces every match of a regular expression in the values of the tag, and removes the
+    /// values left empty.
+    Replace {
+        field: String,
+        pattern: String,
+        replacement: String,
+    },
+    /// Capitalizes the first letter of every word in the values of the tag.
+    TitleCase { field: String },
+    /// Removes the tag.
+    Clear { field: String },
+}
+
+impl BulkEditOperation {
+    fn field(&self) -> String {
+        match self {
+            BulkEditOperation::Set { field, .. }
+            | BulkEditOperation::Replace { field, .. }
+            | BulkEditOperation::TitleCase { field }
+            | BulkEditOperation::Clear { field } => field.trim().to_uppercase(),
+        }
+    }
+}
+
+/// The change of a single tag of a file.
+#[derive(Serialize)]
+pub struct BulkEditChange {
+    pub field: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+/// The changes made (or to be made) to the tags of a file. Files that could not be read or
+/// written come with the error that occurred.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkEditFile {
+    pub file_id: Uuid,
+    pub path: String,
+    pub changes: Vec<BulkEditChange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Capitalizes the first letter of every word and lowercases the others. Words start after
+/// whitespace, opening brackets, hyphens and slashes.
+fn bulk_edit_title_case(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut start = true;
+    for c in value.chars() {
+        if start {
+            out.extend(c.to_uppercase());
+        } else {
+            out.extend(c.to_lowercase());
+        }
+        start = c.is_whitespace() || "([{-/\"".contains(c);
+    }
+    out
+}
+
+/// Returns the values of a tag, regardless of the case of its name.
+fn bulk_edit_values(tags: &HashMap<String, Vec<String>>, field: &str) -> Vec<String> {
+    let mut keys: Vec<&String> = tags
+        .keys()
+        .filter(|k| k.eq_ignore_ascii_case(field))
+        .collect();
+    keys.sort();
+    keys.into_iter().flat_map(|k| tags[k].clone()).collect()
+}
+
+/// Applies the operations to the tags of a file, returning the tags that change.
+fn bulk_edit_changes(
+    tags: &HashMap<String, Vec<String>>,
+    operations: &[(BulkEditOperation, Option<Regex>)],
+) -> Vec<BulkEditChange> {
+    let mut values: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (operation, regex) in operations {
+        let field = operation.field();
+        let current = values
+            .entry(field.clone())
+            .or_insert_with(|| bulk_edit_values(tags, &field));
+        *current = match (operation, regex) {
+            (BulkEditOperation::Set { value, .. }, _) => vec![value.trim().to_owned()],
+            (BulkEditOperation::Replace { replacement, .. }, Some(regex)) => current
+                .iter()
+                .map(|v| regex.replace_all(v, replacement.as_str()).trim().to_owned())
+                .filter(|v| !v.is_empty())
+                .collect(),
+            (BulkEditOperation::TitleCase { .. }, _) => {
+                current.iter().map(|v| bulk_edit_title_case(v)).collect()
+            }
+            _ => Vec::new(),
+        };
+    }
+    values
+        .into_iter()
+        .filter_map(|(field, after)| {
+            let before = bulk_edit_values(tags, &field);
+            (before != after).then_some(BulkEditChange {
+                field,
+                before,
+                after,
+            })
+        })
+        .collect()
+}
+
+/// Gets the audio files of the selected tracks, ordered by path and with each path once.
+async fn bulk_edit_select(
+    selection: &BulkEditSelection,
+    db: &DatabaseConnection,
+) -> Result<Vec<file::Model>> {
+    let mut criteria: Vec<HashSet<Uuid>> = Vec::new();
+    if let Some(album_ids) = &selection.album_ids {
+        let tracks = Track::find()
+            .filter(track::Column::AlbumId.is_in(album_ids.iter().copied()))
+            .all(db)
+            .await?;
+        criteria.push(tracks.into_iter().map(|t| t.id).collect());
+    }
+    if let Some(artist_id) = selection.artist_id {
+        // the tracks of the artist, and the tracks of the albums of the artist
+        let album_ids = album_artists::Entity::find()
+            .filter(album_artists::Column::ArtistId.eq(artist_id))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|a| a.album_id);
+        let mut ids: HashSet<Uuid> = Track::find()
+            .filter(track::Column::AlbumId.is_in(album_ids))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|t| t.id)
+            .collect();
+        ids.extend(
+            track_artists::Entity::find()
+                .filter(track_artists::Column::ArtistId.eq(artist_id))
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|t| t.track_id),
+        );
+        criteria.push(ids);
+    }
+    if let Some(query) = selection.query.as_deref().map(str::trim)
+        && !query.is_empty()
+    {
+        let album_ids = Album::find()
+            .filter(album::Column::Name.contains(query))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|a| a.id);
+        let artist_ids = Artist::find()
+            .filter(artist::Column::Name.contains(query))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|a| a.id);
+        let mut ids: HashSet<Uuid> = Track::find()
+            .filter(
+                Condition::any()
+                    .add(track::Column::Title.contains(query))
+                    .add(track::Column::AlbumId.is_in(album_ids)),
+            )
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|t| t.id)
+            .collect();
+        ids.extend(
+            track_artists::Entity::find()
+                .filter(track_artists::Column::ArtistId.is_in(artist_ids))
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|t| t.track_id),
+        );
+        criteria.push(ids);
+    }
+
+    let track_ids = criteria
+        .into_iter()
+        .reduce(|a, b| a.intersection(&b).copied().collect())
+        .ok_or(anyhow!("[ERROR] No tracks were selected"))?;
+    Ok(File::find()
+        .filter(file::Column::TrackId.is_in(track_ids))
+        .order_by_asc(file::Column::Path)
+        .all(db)
+        .await?
+        .into_iter()
+        .fold(Vec::new(), |mut files: Vec<file::Model>, f| {
+            // the tracks of a single file rip have a file record each for the same path, and
+            // the file is only edited (and journaled) once
+            if files.last().is_none_or(|last| last.path != f.path) {
+                files.push(f);
+            }
+            files
+        }))
+}
+
+/// Reads the tags of the FLAC file at the given path, on the blocking thread pool.
+async fn bulk_edit_read_tags(path: &str) -> Result<HashMap<String, Vec<String>>> {
+    let path = PathBuf::from(path);
+    let metadata = tokio::task::spawn_blocking(move || parse_flac_file(&path, false)).await??;
+    Ok(metadata.tags)
+}
+
+/// Works out the changes the operations make to the tags of the selected files, without
+/// writing them. Only the files whose tags change are returned.
+pub async fn bulk_edit_preview(
+    selection: &BulkEditSelection,
+    operations: Vec<BulkEditOperation>,
+    db: &DatabaseConnection,
+) -> Result<Vec<BulkEditFile>> {
+    if operations.is_empty() {
+        return Err(anyhow!("[ERROR] No operations were given"));
+    }
+    let mut compiled = Vec::new();
+    for operation in operations {
+        let field = operation.field();
+        if field.is_empty() {
+            return Err(anyhow!("[ERROR] Operation is missing its field"));
+        }
+        if !tags_valid_field(&field) {
+            return Err(anyhow!("[ERROR] Invalid tag name: {}", field));
+        }
+        let regex = match &operation {
+            BulkEditOperation::Replace { pattern, .. } => Some(
+                Regex::new(pattern)
+                    .map_err(|e| anyhow!("[ERROR] Invalid pattern {}: {}", pattern, e))?,
+            ),
+            _ => None,
+        };
+        compiled.push((operation, regex));
+    }
+
+    let mut files = Vec::new();
+    for f in bulk_edit_select(selection, db).await? {
+        let (changes, error) = match bulk_edit_read_tags(&f.path).await {
+            Ok(tags) => (bulk_edit_changes(&tags, &compiled), None),
+            Err(e) => (Vec::new(), Some(e.to_string())),
+        };
+        if !changes.is_empty() || error.is_some() {
+            files.push(BulkEditFile {
+                file_id: f.id,
+                path: f.path,
+                changes,
+                error,
+            });
+        }
+    }
+    Ok(files)
+}
+
+/// Writes the changes of a file and scans it again, holding the library lock meanwhile.
+async fn bulk_edit_write(
+    file: &BulkEditFile,
+    cache: &mut ScanCache,
+    library: &LibraryLock,
+    db: &Arc<DatabaseConnection>,
+) -> Result<()> {
+    let edit = FlacTagEdit {
+        tags: file
+            .changes
+            .iter()
+            .map(|c| (c.field.clone(), c.after.clone()))
+            .collect(),
+        front_cover: None,
+    };
+    let _lock = cache.lock(library).await;
+    tags_write_file(Path::new(&file.path), &edit, cache, db).await
+}
+
+/// Journals the changes of a file under the given batch, in a single transaction so that either
+/// all or none of its changes are journaled. Returns the ids of the journal rows.
+async fn bulk_edit_journal(
+    file: &BulkEditFile,
+    batch_id: Uuid,
+    db: &DatabaseConnection,
+) -> Result<Vec<Uuid>> {
+    let txn = db.begin().await?;
+    let mut ids = Vec::new();
+    for change in &file.changes {
+        let id = Uuid::new_v4();
+        metadata_edit::ActiveModel::builder()
+            .set_id(id)
+            .set_batch_id(batch_id)
+            .set_file_id(file.file_id)
+            .set_path(file.path.clone())
+            .set_field(change.field.clone())
+            .set_before(serde_json::to_string(&change.before)?)
+            .set_after(serde_json::to_string(&change.after)?)
+            .set_edited_at(Utc::now())
+            .insert(&txn)
+            .await?;
+        ids.push(id);
+    }
+    txn.commit().await?;
+    Ok(ids)
+}
+
+/// Applies the operations to the tags of the selected files, and journals the changes under a
+/// new batch so that they can be reverted. The changes of a file are journaled before it is
+/// written, so that a file is never changed without a journal to revert it by, and the journal
+/// is removed again if writing fails. Returns the id of the batch and the changed files.
+pub async fn bulk_edit_apply(
+    selection: &BulkEditSelection,
+    operations: Vec<BulkEditOperation>,
+    artwork: &Arc<ArtworkConfig>,
+    library: &LibraryLock,
+    db: &Arc<DatabaseConnection>,
+) -> Result<(Uuid, Vec<BulkEditFile>)> {
+    let batch_id = Uuid::new_v4();
+    let mut files = bulk_edit_preview(selection, operations, db).await?;
+    let mut cache = ScanCache::new(artwork.clone());
+    for file in files.iter_mut().filter(|f| f.error.is_none()) {
+        let ids = match bulk_edit_journal(file, batch_id, db).await {
+            Ok(ids) => ids,
+            Err(e) => {
+                file.error = Some(e.to_string());
+                continue;
+            }
+        };
+        if let Err(e) = bulk_edit_write(file, &mut cache, library, db).await {
+            file.error = Some(e.to_string());
+            if let Err(e) = MetadataEdit::delete_many()
+                .filter(metadata_edit::Column::Id.is_in(ids))
+                .exec(db.as_ref())
+                .await
+            {
+                println!(
+                    "[ERROR] Failed to remove the journal of {}: {}",
+                    file.path, e
+                );
+            }
+        }
+    }
+    Ok((batch_id, files))
+}
+
+/// Reverts the changes of a batch, restoring the values the tags had before it. Files whose
+/// tags were changed again since are left alone and reported as conflicts.
+pub async fn bulk_edit_revert(
+    batch_id: Uuid,
+    artwork: &Arc<ArtworkConfig>,
+    library: &LibraryLock,
+    db: &Arc<DatabaseConnection>,
+) -> Result<Vec<BulkEditFile>> {
+    let edits = MetadataEdit::find()
+        .filter(metadata_edit::Column::BatchId.eq(batch_id))
+        .filter(metadata_edit::Column::RevertedAt.is_null())
+        .order_by_asc(metadata_edit::Column::Path)
+        .all(db.as_ref())
+        .await?;
+    if edits.is_empty() {
+        return Err(anyhow!(
+            "[ERROR] Edit not found in database or already reverted"
+        ));
+    }
+
+    // group the changes by file, which may have been moved since
+    let mut by_file: Vec<(Uuid, Vec<metadata_edit::Model>)> = Vec::new();
+    for edit in edits {
+        match by_file.iter_mut().find(|(id, _)| *id == edit.file_id) {
+            Some((_, file_edits)) => file_edits.push(edit),
+            None => by_file.push((edit.file_id, vec![edit])),
+        }
+    }
+
+    let mut files = Vec::new();
+    let mut cache = ScanCache::new(artwork.clone());
+    for (file_id, file_edits) in by_file {
+        let current = File::find_by_id(file_id).one(db.as_ref()).await?;
+        let mut file = BulkEditFile {
+            file_id,
+            path: current
+                .as_ref()
+                .map_or(file_edits[0].path.clone(), |f| f.path.clone()),
+            changes: Vec::new(),
+            error: None,
+        };
+        let tags = match current {
+            Some(_) => bulk_edit_read_tags(&file.path).await,
+            None => Err(anyhow!("[ERROR] File is no longer in the library")),
+        };
+        match tags {
+            Ok(tags) => {
+                for edit in &file_edits {
+                    let after: Vec<String> = serde_json::from_str(&edit.after)?;
+                    if bulk_edit_values(&tags, &edit.field) != after {
+                        file.error = Some(format!(
+                            "[ERROR] {} was changed again since the edit",
+                            edit.field
+                        ));
+                        break;
+                    }
+                    file.changes.push(BulkEditChange {
+                        field: edit.field.clone(),
+                        before: after,
+                        after: serde_json::from_str(&edit.before)?,
+                    });
+                }
+            }
+            Err(e) => file.error = Some(e.to_string()),
+        }
+        if file.error.is_none() {
+            match bulk_edit_write(&file, &mut cache, library, db).await {
+                Ok(()) => {
+                    MetadataEdit::update_many()
+                        .col_expr(metadata_edit::Column::RevertedAt, Expr::value(Utc::now()))
+                        .filter(metadata_edit::Column::Id.is_in(file_edits.iter().map(|e| e.id)))
+                        .exec(db.as_ref())
+                        .await?;
+                }
+                Err(e) => file.error = Some(e.to_string()),
+            }
+        }
+        if file.error.is_some() {
+            file.changes.clear();
+        }
+        files.push(file);
+    }
+    Ok(files)
+}
@@ -0,0 +1,58 @@
+use std::{
+    future::Future,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use anyhow::{Result, anyhow};
+
+/// Makes sure that only one background job of a kind, such as a library scan, runs at a time.
+#[derive(Default)]
+pub struct JobFlag(Arc<AtomicBool>);
+
+/// Runs the end of a job when dropped, so that the flag is cleared even if the job panics.
+struct JobGuard<F: FnOnce()> {
+    running: Arc<AtomicBool>,
+    finish: Option<F>,
+}
+
+impl<F: FnOnce()> Drop for JobGuard<F> {
+    fn drop(&mut self) {
+        if let Some(finish) = self.finish.take() {
+            finish();
+        }
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+impl JobFlag {
+    /// Spawns the job returned by `start` as a background task, or returns `busy` as an error
+    /// if a job is already running. `finish` runs once the job has ended or panicked, before
+    /// another job can start.
+    pub fn spawn<S, J, F>(&self, busy: &str, start: S, finish: F) -> Result<()>
+    where
+        S: FnOnce() -> J,
+        J: Future<Output = ()> + Send + 'static,
+        F: FnOnce() + Send + 'static,
+    {
+        if self
+            .0
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return Err(anyhow!(busy.to_owned()));
+        }
+        let guard = JobGuard {
+            running: self.0.clone(),
+            finish: Some(finish),
+        };
+        let job = start();
+        tokio::spawn(async move {
+            let _guard = guard;
+            job.await;
+        });
+        Ok(())
+    }
+}
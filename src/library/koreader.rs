@@ -0,0 +1,99 @@
+use anyhow::{Result, anyhow};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    db::book::{self, Entity as Book},
+    library::reading::{
+        ReadingPosition, reading_get_progress, reading_parse_cfi_spine_index, reading_save_progress,
+    },
+};
+
+/// A position in a document as the KOReader sync protocol exchanges it. The document is the
+/// partial MD5 digest of the file, the progress is an XPointer, and the percentage goes from 0
+/// to 1.
+#[derive(Serialize, Deserialize)]
+pub struct KoreaderProgress {
+    pub document: String,
+    pub progress: String,
+    pub percentage: f64,
+    pub device: String,
+    pub device_id: String,
+    /// The Unix time the position was saved at, which KOReader leaves out when saving.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+}
+
+/// Finds the book whose file has the given partial MD5 digest.
+async fn koreader_find_book(document: &str, db: &DatabaseConnection) -> Result<Uuid> {
+    let book = Book::find()
+        .filter(book::Column::PartialMd5.eq(document.to_lowercase()))
+        .one(db)
+        .await?
+        .ok_or(anyhow!("[ERROR] Document not found in library"))?;
+    Ok(book.id)
+}
+
+/// Gets the index of the spine document an XPointer points into, as KOReader numbers the
+/// documents of an EPUB from one in "/body/DocFragment[n]".
+fn koreader_parse_spine_index(xpointer: &str) -> Option<u32> {
+    let (_, rest) = xpointer.split_once("DocFragment[")?;
+    let (number, _) = rest.split_once(']')?;
+    number.parse::<u32>().ok()?.checked_sub(1)
+}
+
+/// Saves the position a KOReader device reports, returning the Unix time it is saved at.
+pub async fn koreader_save_progress(
+    username: &str,
+    progress: KoreaderProgress,
+    db: &DatabaseConnection,
+) -> Result<i64> {
+    let book_id = koreader_find_book(&progress.document, db).await?;
+    if !(0.0..=1.0).contains(&progress.percentage) {
+        return Err(anyhow!("[ERROR] Percentage must be between 0 and 1"));
+    }
+    let position = ReadingPosition {
+        cfi: None,
+        spine_index: koreader_parse_spine_index(&progress.progress),
+        xpointer: Some(progress.progress),
+        percentage: progress.percentage * 100.0,
+        device: Some(progress.device),
+        device_id: Some(progress.device_id),
+        finished: false,
+        timestamp: None,
+    };
+    let saved = reading_save_progress(username, book_id, position, db).await?;
+    Ok(saved.updated_at.timestamp())
+}
+
+/// Gets the position of a user in a document for a KOReader device. Positions saved by other
+/// readers have no XPointer, so one pointing to the start of their spine document is made up.
+pub async fn koreader_get_progress(
+    username: &str,
+    document: &str,
+    db: &DatabaseConnection,
+) -> Result<Option<KoreaderProgress>> {
+    let book_id = koreader_find_book(document, db).await?;
+    let Some(saved) = reading_get_progress(username, book_id, db).await? else {
+        return Ok(None);
+    };
+    let progress = match saved.xpointer {
+        Some(x) => x,
+        None => {
+            let spine_index = saved
+                .spine_index
+                .or_else(|| saved.cfi.as_deref().and_then(reading_parse_cfi_spine_index))
+                .unwrap_or(0);
+            format!("/body/DocFragment[{}]", spine_index + 1)
+        }
+    };
+    Ok(Some(KoreaderProgress {
+        document: document.to_owned(),
+        progress,
+        percentage: saved.percentage / 100.0,
+        device: saved.device.unwrap_or_default(),
+        device_id: saved.device_id.unwrap_or_default(),
+        timestamp: Some(saved.updated_at.timestamp()),
+    }))
+}
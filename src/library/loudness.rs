@@ -0,0 +1,213 @@
+use std::{collections::VecDeque, f64::consts::PI};
+
+/// Loudness that ReplayGain gains are relative to, in LUFS.
+pub const REPLAY_GAIN_REFERENCE: f64 = -18.0;
+
+/// Number of taps of each phase of the true peak interpolation filter.
+const TRUE_PEAK_TAPS: usize = 12;
+
+/// A second order IIR filter, in transposed direct form II.
+#[derive(Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// Returns the two stages of the K-weighting filter of ITU-R BS.1770 for the given sample rate:
+/// a high shelf modelling the head, and a high pass. The coefficients given in the standard are
+/// for 48 kHz, so they are derived from the analog prototypes instead.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    // high shelf
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    // high pass
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+    [shelf, high_pass]
+}
+
+/// Returns the weight of a channel in the loudness sum, given its position in the FLAC channel
+/// order. Surround channels are weighted higher, and the LFE channel is not counted.
+fn channel_weight(channels: u32, channel: u32) -> f64 {
+    match (channels, channel) {
+        (4, 2..=3) | (5, 3..=4) => 1.41,
+        (6..=8, 3) => 0.0,
+        (6..=8, 4..) => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// Measures the true peak of a signal by oversampling it with a windowed sinc interpolation
+/// filter, as described in Annex 2 of ITU-R BS.1770.
+struct TruePeak {
+    phases: Vec<[f64; TRUE_PEAK_TAPS]>,
+    history: Vec<VecDeque<f64>>,
+    peak: f64,
+}
+
+impl TruePeak {
+    fn new(channels: u32, sample_rate: u32) -> Self {
+        // oversample to at least 192 kHz
+        let factor = match sample_rate {
+            0..96000 => 4,
+            96000..192000 => 2,
+            _ => 1,
+        };
+
+        // split a windowed sinc low pass filter into one filter per phase
+        let length = factor * TRUE_PEAK_TAPS;
+        let center = (length - 1) as f64 / 2.0;
+        let mut phases = vec![[0.0; TRUE_PEAK_TAPS]; factor];
+        for n in 0..length {
+            let x = (n as f64 - center) / factor as f64;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            };
+            let window = 0.5 - 0.5 * (2.0 * PI * (n as f64 + 0.5) / length as f64).cos();
+            phases[n % factor][n / factor] = sinc * window;
+        }
+        TruePeak {
+            phases,
+            history: vec![VecDeque::from(vec![0.0; TRUE_PEAK_TAPS]); channels as usize],
+            peak: 0.0,
+        }
+    }
+
+    fn process(&mut self, channel: usize, x: f64) {
+        let history = &mut self.history[channel];
+        history.pop_back();
+        history.push_front(x);
+        self.peak = self.peak.max(x.abs());
+        if self.phases.len() > 1 {
+            for phase in &self.phases {
+                let y: f64 = phase.iter().zip(history.iter()).map(|(c, h)| c * h).sum();
+                self.peak = self.peak.max(y.abs());
+            }
+        }
+    }
+}
+
+/// Result of measuring a track: the mean square of the K-weighted signal for every 400 ms
+/// block, which are kept so that the tracks of an album can be gated together, and the true
+/// peak as a linear value.
+pub struct Loudness {
+    pub blocks: Vec<f64>,
+    pub peak: f64,
+}
+
+/// Measures loudness per ITU-R BS.1770-4. Blocks are 400 ms long and overlap by 75%, so a new
+/// block is completed every 100 ms.
+pub struct LoudnessMeter {
+    weights: Vec<f64>,
+    filters: Vec<[Biquad; 2]>,
+    true_peak: TruePeak,
+    step: usize,
+    position: usize,
+    energy: f64,
+    steps: VecDeque<f64>,
+    blocks: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: u32, sample_rate: u32) -> Self {
+        LoudnessMeter {
+            weights: (0..channels).map(|c| channel_weight(channels, c)).collect(),
+            filters: (0..channels).map(|_| k_weighting(sample_rate)).collect(),
+            true_peak: TruePeak::new(channels, sample_rate),
+            step: (sample_rate as usize / 10).max(1),
+            position: 0,
+            energy: 0.0,
+            steps: VecDeque::with_capacity(4),
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Adds one sample of every channel, as values between -1.0 and 1.0.
+    pub fn add(&mut self, samples: &[f64]) {
+        for (c, &x) in samples.iter().enumerate() {
+            let [shelf, high_pass] = &mut self.filters[c];
+            let y = high_pass.process(shelf.process(x));
+            self.energy += self.weights[c] * y * y;
+            self.true_peak.process(c, x);
+        }
+        self.position += 1;
+
+        // every 100 ms, complete the block made of the last four steps
+        if self.position == self.step {
+            if self.steps.len() == 4 {
+                self.steps.pop_front();
+            }
+            self.steps.push_back(self.energy / self.step as f64);
+            if self.steps.len() == 4 {
+                self.blocks.push(self.steps.iter().sum::<f64>() / 4.0);
+            }
+            self.position = 0;
+            self.energy = 0.0;
+        }
+    }
+
+    pub fn finish(self) -> Loudness {
+        Loudness {
+            blocks: self.blocks,
+            peak: self.true_peak.peak,
+        }
+    }
+}
+
+fn block_loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+/// Returns the integrated loudness of the given blocks in LUFS, gating out blocks quieter than
+/// -70 LUFS and then blocks more than 10 LU quieter than the rest. Returns None if every block
+/// is gated out, e.g. for silence or audio shorter than a block.
+pub fn integrated_loudness(blocks: &[f64]) -> Option<f64> {
+    let mean = |blocks: &[f64]| blocks.iter().sum::<f64>() / blocks.len() as f64;
+    let audible: Vec<f64> = blocks
+        .iter()
+        .copied()
+        .filter(|&z| block_loudness(z) > -70.0)
+        .collect();
+    if audible.is_empty() {
+        return None;
+    }
+    let relative_gate = block_loudness(mean(&audible)) - 10.0;
+    let gated: Vec<f64> = audible
+        .into_iter()
+        .filter(|&z| block_loudness(z) > relative_gate)
+        .collect();
+    Some(block_loudness(mean(&gated)))
+}
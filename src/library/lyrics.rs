@@ -0,0 +1,111 @@
+use anyhow::Result;
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, TransactionTrait};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use uuid::Uuid;
+
+use crate::{
+    db::lyrics,
+    format::lrc::{LyricLine, Lyrics, parse_lrc},
+};
+
+/// Language of lyrics whose language is unknown, as commonly written by taggers.
+const LYRICS_UNKNOWN_LANGUAGE: &str = "xxx";
+
+/// Lyrics of a track found in its tags or in a sidecar file, in LRC format or plain text,
+/// with the language given by the name of the sidecar file if there is one.
+pub struct TrackLyrics {
+    pub lang: Option<String>,
+    pub text: String,
+}
+
+impl Serialize for LyricLine {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("Line", 2)?;
+        if let Some(start) = self.start {
+            state.serialize_field("start", &start)?;
+        } else {
+            state.skip_field("start")?;
+        }
+        state.serialize_field("value", &self.value)?;
+        state.end()
+    }
+}
+
+impl Serialize for Lyrics {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("StructuredLyrics", 6)?;
+        state.serialize_field(
+            "lang",
+            self.lang.as_deref().unwrap_or(LYRICS_UNKNOWN_LANGUAGE),
+        )?;
+        state.serialize_field("synced", &self.synced)?;
+        state.serialize_field("offset", &self.offset)?;
+        state.serialize_field("displayArtist", &self.display_artist)?;
+        state.serialize_field("displayTitle", &self.display_title)?;
+        state.serialize_field("line", &self.lines)?;
+        state.end()
+    }
+}
+
+/// Replaces the lyrics of a track with the given ones. Lyrics without any lines are skipped.
+pub async fn lyrics_set<C: ConnectionTrait + TransactionTrait>(
+    track_id: Uuid,
+    sources: &[TrackLyrics],
+    db: &C,
+) -> Result<()> {
+    lyrics::Entity::delete_many()
+        .filter(lyrics::Column::TrackId.eq(track_id))
+        .exec(db)
+        .await?;
+    for l in sources {
+        // the language in the lyrics themselves takes precedence over the file name
+        let parsed = parse_lrc(&l.text);
+        if parsed.lines.is_empty() {
+            continue;
+        }
+        let lang = parsed.lang.or(l.lang.clone());
+        lyrics::ActiveModel::builder()
+            .set_id(Uuid::new_v4())
+            .set_track_id(track_id)
+            .set_lang(lang.unwrap_or(LYRICS_UNKNOWN_LANGUAGE.to_owned()))
+            .set_content(l.text.clone())
+            .insert(db)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Gets the lyrics of a track, synced lyrics first.
+pub async fn lyrics_get_by_track<C: ConnectionTrait>(
+    track_id: Uuid,
+    db: &C,
+) -> Result<Vec<Lyrics>> {
+    let mut found: Vec<Lyrics> = lyrics::Entity::find()
+        .filter(lyrics::Column::TrackId.eq(track_id))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|l| Lyrics {
+            lang: Some(l.lang),
+            ..parse_lrc(&l.content)
+        })
+        .collect();
+    found.sort_by_key(|l| !l.synced);
+    Ok(found)
+}
+
+/// Returns the lyrics as plain text, preferring lyrics that were not synced to begin with.
+pub fn lyrics_plain(lyrics: &[Lyrics]) -> Option<String> {
+    let l = lyrics
+        .iter()
+        .find(|l| !l.synced)
+        .or_else(|| lyrics.first())?;
+    let lines: Vec<&str> = l.lines.iter().map(|line| line.value.as_str()).collect();
+    Some(lines.join("\n"))
+}
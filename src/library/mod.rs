@@ -1,7 +1,21 @@
 pub mod album;
+pub mod analyzer;
+pub mod annotation;
 pub mod artist;
 pub mod book;
+pub mod bulk_edit;
+pub mod job;
+pub mod koreader;
+pub mod loudness;
+pub mod lyrics;
 pub mod playlist;
+pub mod reading;
+pub mod scan_cache;
+pub mod scan_error;
 pub mod scanner;
 pub mod shelf;
+pub mod tags;
 pub mod track;
+pub mod verifier;
+pub mod watcher;
+pub mod waveform;
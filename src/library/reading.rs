@@ -0,0 +1,208 @@
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, Order, QueryFilter, QueryOrder,
+    QuerySelect, Set,
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        book::{self, Entity as Book},
+        bookmark::{self, Entity as Bookmark},
+        reading_progress::{self, Entity as ReadingProgress},
+    },
+    library::shelf::get_user_id,
+};
+
+/// A position in a book reported by a device.
+pub struct ReadingPosition {
+    pub cfi: Option<String>,
+    pub spine_index: Option<u32>,
+    pub xpointer: Option<String>,
+    pub percentage: f64,
+    pub device: Option<String>,
+    pub device_id: Option<String>,
+    pub finished: bool,
+    /// When the position was reached, which is now if the device doesn't say or gives a time in
+    /// the future.
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// A book that a user started and has not finished, with where they are in it.
+#[derive(Serialize)]
+pub struct ContinueReading {
+    pub book: book::Model,
+    pub progress: reading_progress::Model,
+}
+
+/// Checks that a book exists.
+pub async fn reading_check_book(book_id: Uuid, db: &DatabaseConnection) -> Result<()> {
+    Book::find_by_id(book_id)
+        .one(db)
+        .await?
+        .ok_or(anyhow!("[ERROR] Book not found"))?;
+    Ok(())
+}
+
+/// Gets the index of the spine document an EPUB CFI points into, from the step after the one
+/// pointing to the spine, such as the 4 in "epubcfi(/6/4[chapter]!/4/2)".
+pub fn reading_parse_cfi_spine_index(cfi: &str) -> Option<u32> {
+    let path = cfi.trim_start_matches("epubcfi(");
+    let step = path.split('/').nth(2)?;
+    let digits: String = step.chars().take_while(|c| c.is_ascii_digit()).collect();
+    (digits.parse::<u32>().ok()? / 2).checked_sub(1)
+}
+
+/// Saves the position of a user in a book. Devices may sync late, so a position older than the
+/// saved one is ignored, and the saved position is returned either way.
+pub async fn reading_save_progress(
+    username: &str,
+    book_id: Uuid,
+    position: ReadingPosition,
+    db: &DatabaseConnection,
+) -> Result<reading_progress::Model> {
+    let user_id = get_user_id(username, db).await?;
+    reading_check_book(book_id, db).await?;
+    if position.cfi.is_none() && position.xpointer.is_none() && position.spine_index.is_none() {
+        return Err(anyhow!(
+            "[ERROR] Either a CFI, an XPointer or a spine index must be given"
+        ));
+    }
+    if !(0.0..=100.0).contains(&position.percentage) {
+        return Err(anyhow!("[ERROR] Percentage must be between 0 and 100"));
+    }
+
+    // the newest position wins, where a time in the future (such as from a device with a wrong
+    // clock) counts as now so that it can't hold off every later position
+    let now = Utc::now();
+    let timestamp = position.timestamp.map_or(now, |t| t.min(now));
+    let existing = ReadingProgress::find_by_id((user_id, book_id))
+        .one(db)
+        .await?;
+    if let Some(existing) = &existing
+        && existing.updated_at > timestamp
+    {
+        return Ok(existing.clone());
+    }
+    let progress = reading_progress::ActiveModel {
+        user_id: Set(user_id),
+        book_id: Set(book_id),
+        cfi: Set(position.cfi),
+        spine_index: Set(position.spine_index),
+        xpointer: Set(position.xpointer),
+        percentage: Set(position.percentage),
+        device: Set(position.device),
+        device_id: Set(position.device_id),
+        finished: Set(position.finished),
+        updated_at: Set(timestamp),
+    };
+    Ok(match existing {
+        Some(_) => progress.update(db).await?,
+        None => progress.insert(db).await?,
+    })
+}
+
+/// Gets the position of a user in a book, if they started it.
+pub async fn reading_get_progress(
+    username: &str,
+    book_id: Uuid,
+    db: &DatabaseConnection,
+) -> Result<Option<reading_progress::Model>> {
+    let user_id = get_user_id(username, db).await?;
+    Ok(ReadingProgress::find_by_id((user_id, book_id))
+        .one(db)
+        .await?)
+}
+
+/// Returns the books a user started and has not finished, most recently read first.
+pub async fn reading_get_continue_list(
+    username: &str,
+    len: u32,
+    db: &DatabaseConnection,
+) -> Result<Vec<ContinueReading>> {
+    let user_id = get_user_id(username, db).await?;
+    let progress = ReadingProgress::find()
+        .filter(reading_progress::Column::UserId.eq(user_id))
+        .filter(reading_progress::Column::Finished.eq(false))
+        .order_by(reading_progress::Column::UpdatedAt, Order::Desc)
+        .limit(len as u64)
+        .all(db)
+        .await?;
+    let mut books = Book::find()
+        .filter(book::Column::Id.is_in(progress.iter().map(|p| p.book_id)))
+        .all(db)
+        .await?;
+    Ok(progress
+        .into_iter()
+        .filter_map(|progress| {
+            let index = books.iter().position(|b| b.id == progress.book_id)?;
+            Some(ContinueReading {
+                book: books.swap_remove(index),
+                progress,
+            })
+        })
+        .collect())
+}
+
+/// Adds a bookmark to a book for a user.
+pub async fn reading_add_bookmark(
+    username: &str,
+    book_id: Uuid,
+    cfi: Option<String>,
+    spine_index: Option<u32>,
+    label: Option<String>,
+    db: &DatabaseConnection,
+) -> Result<bookmark::Model> {
+    let user_id = get_user_id(username, db).await?;
+    reading_check_book(book_id, db).await?;
+    if cfi.is_none() && spine_index.is_none() {
+        return Err(anyhow!(
+            "[ERROR] Either a CFI or a spine index must be given"
+        ));
+    }
+    let bookmark = bookmark::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        user_id: Set(user_id),
+        book_id: Set(book_id),
+        cfi: Set(cfi),
+        spine_index: Set(spine_index),
+        label: Set(label),
+        created_at: Set(Utc::now()),
+    };
+    Ok(bookmark.insert(db).await?)
+}
+
+/// Gets the bookmarks of a user in a book, in the order they were added.
+pub async fn reading_get_bookmarks(
+    username: &str,
+    book_id: Uuid,
+    db: &DatabaseConnection,
+) -> Result<Vec<bookmark::Model>> {
+    let user_id = get_user_id(username, db).await?;
+    Ok(Bookmark::find()
+        .filter(bookmark::Column::UserId.eq(user_id))
+        .filter(bookmark::Column::BookId.eq(book_id))
+        .order_by(bookmark::Column::CreatedAt, Order::Asc)
+        .all(db)
+        .await?)
+}
+
+/// Deletes a bookmark of a user.
+pub async fn reading_delete_bookmark(
+    username: &str,
+    id: Uuid,
+    db: &DatabaseConnection,
+) -> Result<()> {
+    let user_id = get_user_id(username, db).await?;
+    let result = Bookmark::delete_many()
+        .filter(bookmark::Column::Id.eq(id))
+        .filter(bookmark::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+    if result.rows_affected == 0 {
+        return Err(anyhow!("[ERROR] Bookmark not found"));
+    }
+    Ok(())
+}
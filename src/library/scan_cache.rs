@@ -0,0 +1,213 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use sea_orm::{ConnectionTrait, TransactionTrait};
+use tokio::sync::{Mutex, MutexGuard};
+use uuid::Uuid;
+
+use crate::{db::artist, settings::ArtworkConfig};
+
+use super::{
+    album::{album_find_by_musicbrainz_id, album_find_by_name, album_match, album_update_picture},
+    artist::{artist_insert, artist_set_picture},
+    scanner::scan_read_sidecar_picture,
+};
+
+/// Serializes the writers of the library: scans, the library watcher and tag edits. The lock
+/// counts the writes made under it, so that a scan cache can tell when another writer changed
+/// the library since it last held the lock.
+#[derive(Default)]
+pub struct LibraryLock(Mutex<u64>);
+
+impl LibraryLock {
+    /// Waits for the current writer to finish, then keeps other writers out until the guard is
+    /// dropped.
+    pub async fn lock(&self) -> MutexGuard<'_, u64> {
+        let mut writes = self.0.lock().await;
+        *writes += 1;
+        writes
+    }
+}
+
+/// An album as remembered by the scan cache, with the names of its album artists.
+struct CachedAlbum {
+    id: Uuid,
+    artists: Vec<String>,
+}
+
+/// Caches the artists and albums looked up or created during a scan, so that the files of the
+/// same artist or album do not have to query the database again, along with the picture files
+/// read for them. The cache must be cleared whenever a write is rolled back, as it may then
+/// refer to rows that no longer exist, and it is only used while holding the library lock.
+pub struct ScanCache {
+    artwork: Arc<ArtworkConfig>,
+    artists: HashMap<String, artist::Model>,
+    albums: HashMap<String, Vec<CachedAlbum>>,
+    albums_by_musicbrainz_id: HashMap<String, Uuid>,
+    albums_changed: HashSet<Uuid>,
+    artist_pictures: HashMap<PathBuf, Option<Vec<u8>>>,
+    writes: Option<u64>,
+}
+
+impl ScanCache {
+    pub fn new(artwork: Arc<ArtworkConfig>) -> Self {
+        ScanCache {
+            artwork,
+            artists: HashMap::new(),
+            albums: HashMap::new(),
+            albums_by_musicbrainz_id: HashMap::new(),
+            albums_changed: HashSet::new(),
+            artist_pictures: HashMap::new(),
+            writes: None,
+        }
+    }
+
+    /// Takes the library lock, first forgetting everything that was cached if another writer
+    /// had the lock since this cache last did, as it may have removed the cached rows.
+    pub async fn lock<'a>(&mut self, library: &'a LibraryLock) -> MutexGuard<'a, u64> {
+        let writes = library.lock().await;
+        if self.writes != Some(*writes - 1) {
+            self.clear();
+        }
+        self.writes = Some(*writes);
+        writes
+    }
+
+    /// Forgets everything that was cached.
+    pub fn clear(&mut self) {
+        self.artists.clear();
+        self.albums.clear();
+        self.albums_by_musicbrainz_id.clear();
+    }
+
+    /// Remembers that the tracks of an album changed, so that its picture is chosen again.
+    pub fn album_changed(&mut self, album_id: Uuid) {
+        self.albums_changed.insert(album_id);
+    }
+
+    /// Chooses the pictures of the albums whose tracks changed since the last call. This is
+    /// done once all of their tracks are written, so that every track is taken into account.
+    pub async fn album_pictures_update<C: ConnectionTrait>(&mut self, db: &C) -> Result<()> {
+        for album_id in std::mem::take(&mut self.albums_changed) {
+            album_update_picture(album_id, &self.artwork, db).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the artwork configuration the scan was started with.
+    pub fn artwork(&self) -> &Arc<ArtworkConfig> {
+        &self.artwork
+    }
+
+    /// Gives the artist with the given name the artist picture in the directory above the
+    /// audio file at the given path, if the artist does not have a picture yet.
+    pub async fn artist_picture<C: ConnectionTrait + TransactionTrait>(
+        &mut self,
+        name: &str,
+        path: &Path,
+        db: &C,
+    ) -> Result<()> {
+        let name = name.trim();
+        self.artist(name, db).await?;
+        if self.artists[name].picture.is_some() {
+            return Ok(());
+        }
+        let Some(directory) = path.parent().and_then(|d| d.parent()) else {
+            return Ok(());
+        };
+        if !self.artist_pictures.contains_key(directory) {
+            let picture = scan_read_sidecar_picture(directory, &self.artwork.artist_files).await;
+            self.artist_pictures
+                .insert(directory.to_path_buf(), picture);
+        }
+        if let Some(picture) = &self.artist_pictures[directory] {
+            let artist = self.artists.get_mut(name).unwrap();
+            artist_set_picture(artist.id, db, picture.clone()).await?;
+            artist.picture = Some(picture.clone());
+        }
+        Ok(())
+    }
+
+    /// Returns the active model of the artist with the given name, inserting the artist into
+    /// the database if it does not exist yet.
+    pub async fn artist<C: ConnectionTrait>(
+        &mut self,
+        name: &str,
+        db: &C,
+    ) -> Result<artist::ActiveModel> {
+        let name = name.trim();
+        if let Some(m) = self.artists.get(name) {
+            return Ok(m.clone().into());
+        }
+        let m = artist_insert(name, db).await?;
+        self.artists.insert(name.to_owned(), m.clone());
+        Ok(m.into())
+    }
+
+    /// Finds an existing album matching the given metadata, following the same rules as
+    /// album_match(). Albums are loaded from the database once per album name.
+    pub async fn album<C: ConnectionTrait>(
+        &mut self,
+        album_name: &str,
+        artists: &[String],
+        album_artists: &Option<Vec<String>>,
+        musicbrainz_album_id: &Option<String>,
+        db: &C,
+    ) -> Result<Option<Uuid>> {
+        // check case where musicbrainz_album_id exists both in the file and on the database
+        if let Some(mbid) = musicbrainz_album_id {
+            if let Some(id) = self.albums_by_musicbrainz_id.get(mbid) {
+                return Ok(Some(*id));
+            }
+            if let Some(id) = album_find_by_musicbrainz_id(mbid, db).await? {
+                self.albums_by_musicbrainz_id.insert(mbid.clone(), id);
+                return Ok(Some(id));
+            }
+        }
+
+        // otherwise, match against the albums with the same album name
+        if !self.albums.contains_key(album_name) {
+            let albums = album_find_by_name(album_name, db)
+                .await?
+                .into_iter()
+                .map(|a| CachedAlbum {
+                    id: a.id,
+                    artists: a.artists.iter().map(|artist| artist.name.clone()).collect(),
+                })
+                .collect();
+            self.albums.insert(album_name.to_owned(), albums);
+        }
+        Ok(self.albums[album_name]
+            .iter()
+            .find(|a| album_match(artists, album_artists, &a.artists))
+            .map(|a| a.id))
+    }
+
+    /// Remembers an album that was just inserted into the database.
+    pub fn add_album(
+        &mut self,
+        album_name: &str,
+        id: Uuid,
+        album_artists: &Option<Vec<String>>,
+        musicbrainz_album_id: &Option<String>,
+    ) {
+        if let Some(mbid) = musicbrainz_album_id {
+            self.albums_by_musicbrainz_id.insert(mbid.clone(), id);
+        }
+        self.albums
+            .entry(album_name.to_owned())
+            .or_default()
+            .push(CachedAlbum {
+                id,
+                artists: album_artists
+                    .iter()
+                    .flatten()
+                    .map(|a| a.trim().to_owned())
+                    .collect(),
+            });
+    }
+}
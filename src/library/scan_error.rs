@@ -0,0 +1,63 @@
+use anyhow::Result;
+use chrono::Utc;
+use sea_orm::entity::prelude::*;
+use sea_orm::{DatabaseConnection, Order, QueryOrder, QuerySelect, Set};
+use uuid::Uuid;
+
+use crate::db::scan_error::{self, Entity as ScanError};
+
+/// Records a failure to scan the file at the given path. If the file has failed before, the
+/// error message and last seen time are updated while the first seen time is kept.
+pub async fn scan_error_record(
+    path: &str,
+    format: &str,
+    error: &str,
+    db: &DatabaseConnection,
+) -> Result<()> {
+    let now = Utc::now();
+    if let Some(e) = ScanError::find()
+        .filter(scan_error::Column::Path.eq(path))
+        .one(db)
+        .await?
+    {
+        let mut e: scan_error::ActiveModel = e.into();
+        e.format = Set(format.to_owned());
+        e.error = Set(error.to_owned());
+        e.last_seen = Set(now);
+        e.update(db).await?;
+    } else {
+        let e = scan_error::ActiveModel::builder()
+            .set_id(Uuid::new_v4())
+            .set_path(path)
+            .set_format(format)
+            .set_error(error)
+            .set_first_seen(now)
+            .set_last_seen(now);
+        e.insert(db).await?;
+    }
+    Ok(())
+}
+
+/// Removes any recorded failures for the files at the given paths, e.g. after they were fixed.
+pub async fn scan_error_clear(paths: Vec<String>, db: &DatabaseConnection) -> Result<()> {
+    ScanError::delete_many()
+        .filter(scan_error::Column::Path.is_in(paths))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Returns a list of the recorded scan failures, sorted by path.
+pub async fn scan_error_get_list(
+    len: u32,
+    offset: u32,
+    db: &DatabaseConnection,
+) -> Vec<scan_error::Model> {
+    ScanError::find()
+        .order_by(scan_error::Column::Path, Order::Asc)
+        .offset(offset as u64)
+        .limit(len as u64)
+        .all(db)
+        .await
+        .unwrap_or_default()
+}
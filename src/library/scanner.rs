@@ -1,204 +1,635 @@
-use std::{fs, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use chrono::{DateTime, Utc};
+use cron::Schedule;
 use sea_orm::entity::prelude::*;
-use sea_orm::{ColumnTrait, DatabaseConnection, ModelTrait, Set};
+use sea_orm::{
+    ColumnTrait, Condition, DatabaseConnection, ModelTrait, QuerySelect, Set, TransactionTrait,
+    sea_query::Expr,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::Semaphore;
 use walkdir::WalkDir;
 
-use crate::db::{album, artist, book, track};
-use crate::format::epub::parse_epub_file;
-use crate::format::flac::FlacPictureType;
-use crate::library::album::album_find;
-use crate::library::artist::artist_insert;
+use crate::db::{album, book, book_subjects, scan_error, track};
+use crate::format::cue::{CueSheet, parse_cue_file};
+use crate::format::epub::{EpubMetadata, parse_epub_file};
+use crate::format::flac::{FlacMetadata, FlacPictureType};
+use crate::format::lrc::read_lrc_file;
+use crate::library::job::JobFlag;
+use crate::library::lyrics::{TrackLyrics, lyrics_set};
+use crate::library::scan_cache::{LibraryLock, ScanCache};
+use crate::library::scan_error::{scan_error_clear, scan_error_record};
+use crate::settings::ArtworkConfig;
 use crate::{
     db::file::{self, Entity as File},
     format::flac::parse_flac_file,
 };
 
-use super::track::TrackMetadata;
+use super::track::{ReplayGain, TrackMetadata};
 
-async fn scan_epub(path: &Path, db: &DatabaseConnection) -> Result<()> {
-    // check if file exists in database
-    let file: Option<file::Model> = File::find()
-        .filter(file::Column::Path.eq(path.display().to_string()))
-        .one(db)
-        .await?;
+/// Number of directories whose files are parsed ahead of the database writes.
+const SCAN_PREFETCH: usize = 4;
+
+/// Metadata parsed from a supported library file. FLAC files that are rips of a whole album
+/// come with the cue sheet that splits them into tracks, and other FLAC files with the lyrics
+/// files stored next to them.
+enum ScanMetadata {
+    Flac(Box<FlacMetadata>, Option<CueSheet>, Vec<TrackLyrics>),
+    Epub(Box<EpubMetadata>),
+}
+
+/// A library file that has changed since the last scan and needs to be written. A file holds
+/// several file records if it is split into tracks by a cue sheet.
+struct ScanTarget {
+    files: Vec<file::Model>,
+    modified: DateTime<Utc>,
+    size: u64,
+}
+
+/// A track to be written for a FLAC file, either the whole file or a part of it.
+struct FlacTrack {
+    title: String,
+    album_name: String,
+    artists: Vec<String>,
+    album_artists: Option<Vec<String>>,
+    musicbrainz_album_id: Option<String>,
+    picture: Option<Vec<u8>>,
+    front_cover: bool,
+    runtime: i64,
+    start_sample: Option<i64>,
+    end_sample: Option<i64>,
+    replay_gain: ReplayGain,
+    lyrics: Vec<TrackLyrics>,
+}
+
+/// A library file after it went through the parsing stage of a scan. The result is None if
+/// the file has not changed since the last scan.
+struct ScanItem {
+    path: PathBuf,
+    format: &'static str,
+    parsed: Result<Option<(ScanTarget, ScanMetadata)>>,
+}
+
+/// Returns the format of the file at the given path if it is supported by the scanner.
+fn scan_format(path: &Path) -> Option<&'static str> {
+    match path.extension().and_then(|s| s.to_str()) {
+        Some("flac") | Some("oga") => Some("flac"),
+        Some("epub") => Some("epub"),
+        _ => None,
+    }
+}
 
-    // if file exists, only continue if last modified is more recent
-    let modified: DateTime<Utc> = fs::metadata(path)?.modified()?.into();
-    if let Some(f) = &file {
-        if modified <= f.last_modified {
-            return Ok(());
+/// Returns the cue sheet stored next to the audio file at the given path, named either
+/// "album.cue" or "album.flac.cue".
+fn scan_sidecar_cue(path: &Path) -> Option<PathBuf> {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".cue");
+    [path.with_extension("cue"), PathBuf::from(name)]
+        .into_iter()
+        .find(|p| p.is_file())
+}
+
+/// Returns the audio file that the cue sheet at the given path is stored next to.
+fn scan_cue_audio(path: &Path) -> Option<PathBuf> {
+    [
+        path.with_extension("flac"),
+        path.with_extension("oga"),
+        path.with_extension(""),
+    ]
+    .into_iter()
+    .find(|p| p.is_file() && scan_format(p).is_some())
+}
+
+/// Returns the first of the given picture files that exists in the directory, ignoring the
+/// case of file names.
+pub fn scan_sidecar_picture(directory: &Path, names: &[String]) -> Option<PathBuf> {
+    let files: HashMap<String, PathBuf> = fs::read_dir(directory)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .filter_map(|p| Some((p.file_name()?.to_str()?.to_lowercase(), p.clone())))
+        .collect();
+    names
+        .iter()
+        .find_map(|n| files.get(&n.to_lowercase()).cloned())
+}
+
+/// Reads the first of the given picture files that exists in the directory.
+pub async fn scan_read_sidecar_picture(directory: &Path, names: &[String]) -> Option<Vec<u8>> {
+    let path = scan_sidecar_picture(directory, names)?;
+    match tokio::fs::read(&path).await {
+        Ok(data) => Some(data),
+        Err(e) => {
+            println!("[ERROR] Failed to read picture {}: {}", path.display(), e);
+            None
         }
     }
+}
 
-    // extract useful metadata from file
-    let metadata = parse_epub_file(path)?;
+/// Returns the lyrics files stored next to the audio file at the given path, named either
+/// "track.lrc" or "track.txt", or "track.eng.lrc" for lyrics in a specific language, along
+/// with that language.
+fn scan_sidecar_lyrics(path: &Path) -> Vec<(PathBuf, Option<String>)> {
+    let (Some(parent), Some(stem)) = (path.parent(), path.file_stem().and_then(|s| s.to_str()))
+    else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(parent) else {
+        return Vec::new();
+    };
+    let mut sidecars: Vec<(PathBuf, Option<String>)> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "lrc" || e == "txt") && p.is_file())
+        .filter_map(|p| {
+            let base = p.file_stem()?.to_str()?;
+            if base == stem {
+                return Some((p.clone(), None));
+            }
+            let lang = base.strip_prefix(stem)?.strip_prefix('.')?;
+            if (2..=3).contains(&lang.len()) && lang.chars().all(|c| c.is_ascii_alphabetic()) {
+                return Some((p.clone(), Some(lang.to_lowercase())));
+            }
+            None
+        })
+        .collect();
+    sidecars.sort();
+    sidecars
+}
+
+/// Returns the audio file that the cue sheet or lyrics file at the given path belongs to.
+fn scan_sidecar_audio(path: &Path) -> Option<PathBuf> {
+    if path.extension().is_some_and(|e| e == "cue") {
+        return scan_cue_audio(path);
+    }
+
+    // lyrics may be named after the audio file with a language in between
+    let base = path.with_extension("");
+    let mut candidates = vec![base.clone()];
+    if base
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| (2..=3).contains(&e.len()))
+    {
+        candidates.push(base.with_extension(""));
+    }
+    candidates
+        .into_iter()
+        .flat_map(|c| {
+            ["flac", "oga"].map(|ext| {
+                let mut name = c.as_os_str().to_owned();
+                name.push(".");
+                name.push(ext);
+                PathBuf::from(name)
+            })
+        })
+        .find(|p| p.is_file())
+}
+
+/// Combines the format specific fingerprint of a file with its size, so that files can be
+/// recognized after being moved or renamed.
+fn scan_fingerprint(format_fingerprint: &str, size: u64) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format_fingerprint.as_bytes());
+    hasher.update(size.to_be_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Finds the file records with the given fingerprint whose path no longer exists, i.e. a file
+/// that was moved or renamed since the last scan.
+async fn scan_find_moved<C: ConnectionTrait>(
+    fingerprint: &str,
+    db: &C,
+) -> Result<Vec<file::Model>> {
+    let files = File::find()
+        .filter(file::Column::Fingerprint.eq(fingerprint))
+        .all(db)
+        .await?;
+    let Some(moved) = files.iter().find(|f| !Path::new(&f.path).exists()) else {
+        return Ok(Vec::new());
+    };
+    let moved = moved.path.clone();
+    Ok(files.into_iter().filter(|f| f.path == moved).collect())
+}
+
+async fn scan_epub<C: ConnectionTrait + TransactionTrait>(
+    path: &Path,
+    target: ScanTarget,
+    metadata: EpubMetadata,
+    cache: &mut ScanCache,
+    db: &C,
+) -> Result<ScanOutcome> {
+    let ScanTarget {
+        files,
+        modified,
+        size,
+    } = target;
+    let mut file = files.into_iter().next();
+    let fingerprint = metadata
+        .identifier
+        .as_ref()
+        .map(|id| scan_fingerprint(&format!("epub:{}", id.trim()), size));
+
+    // a new path may belong to a book that was moved, so keep its identity if possible
+    if file.is_none()
+        && let Some(fp) = &fingerprint
+    {
+        file = scan_find_moved(fp, db).await?.into_iter().next();
+    }
+    // the authors are the artists of the book, or every creator if none has that role
+    let has_authors = metadata
+        .creators
+        .iter()
+        .any(|c| c.role.as_deref().is_none_or(|r| r == "aut"));
     let mut artists: Vec<String> = Vec::new();
-    if let Some(a) = metadata.creator {
-        artists.push(a);
+    for creator in metadata.creators {
+        if (!has_authors || creator.role.as_deref().is_none_or(|r| r == "aut"))
+            && !artists.contains(&creator.name)
+        {
+            artists.push(creator.name);
+        }
     }
 
     // turn list of artists into active models
-    let mut artist_models: Vec<artist::ActiveModel> = Vec::new();
+    let mut artist_models = Vec::new();
     for artist in &artists {
-        artist_models.push(artist_insert(artist, db).await);
+        artist_models.push(cache.artist(artist, db).await?);
     }
 
     // check if file has an existing book (update case) or needs new book (insert case)
-    let existing_book_id = file.as_ref().and_then(|f| f.track_id);
-    let book_id = if let Some(book_id) = existing_book_id {
-        // update existing book
-        let mut book = book::ActiveModel::builder()
-            .set_id(book_id)
-            .set_title(metadata.title.unwrap_or("".to_owned()))
-            .set_picture(metadata.cover);
-        for artist in artist_models {
-            book = book.add_artist(artist);
-        }
+    let existing_book_id = file.as_ref().and_then(|f| f.book_id);
+    let book_id = existing_book_id.unwrap_or_else(Uuid::new_v4);
+    let mut book = book::ActiveModel::builder()
+        .set_id(book_id)
+        .set_title(metadata.title.unwrap_or("".to_owned()))
+        .set_picture(metadata.cover)
+        .set_description(metadata.description)
+        .set_publisher(metadata.publisher)
+        .set_publication_date(metadata.date)
+        .set_language(metadata.language)
+        .set_isbn(metadata.isbn)
+        .set_asin(metadata.asin)
+        .set_uuid(metadata.uuid)
+        .set_series(metadata.series)
+        .set_series_index(metadata.series_index)
+        .set_partial_md5(metadata.partial_md5);
+    for artist in artist_models {
+        book = book.add_artist(artist);
+    }
+    if existing_book_id.is_some() {
         let _ = book.save(db).await?;
-        book_id
     } else {
-        // insert new book
-        let book_id = Uuid::new_v4();
-        let mut book = book::ActiveModel::builder()
-            .set_id(book_id)
-            .set_title(metadata.title.unwrap_or("".to_owned()))
-            .set_picture(metadata.cover);
-        for artist in artist_models {
-            book = book.add_artist(artist);
-        }
-        let _ = book.insert(db).await?;
-        book_id
-    };
+        let _ = book.set_added_at(Some(Utc::now())).insert(db).await?;
+    }
+
+    // the subjects are replaced as a whole
+    book_subjects::Entity::delete_many()
+        .filter(book_subjects::Column::BookId.eq(book_id))
+        .exec(db)
+        .await?;
+    for subject in metadata.subjects {
+        book_subjects::ActiveModel::builder()
+            .set_book_id(book_id)
+            .set_subject(subject)
+            .insert(db)
+            .await?;
+    }
 
     // update or create file in the database (must do this last)
     if let Some(f) = file {
         let mut f: file::ActiveModel = f.into();
+        f.path = Set(path.display().to_string());
         f.last_modified = Set(modified);
+        f.size = Set(Some(size as i64));
+        f.fingerprint = Set(fingerprint);
         let _ = f.update(db).await?;
+        Ok(ScanOutcome::Updated)
     } else {
         let f = file::ActiveModel::builder()
             .set_id(Uuid::new_v4())
             .set_path(path.display().to_string())
             .set_last_modified(modified)
+            .set_size(Some(size as i64))
+            .set_fingerprint(fingerprint)
             .set_book_id(book_id);
         let _ = f.insert(db).await?;
+        Ok(ScanOutcome::New)
     }
-
-    return Ok(());
 }
 
-async fn scan_flac(path: &Path, db: &DatabaseConnection) -> Result<()> {
-    // check if file exists in database
-    let file: Option<file::Model> = File::find()
-        .filter(file::Column::Path.eq(path.display().to_string()))
-        .one(db)
-        .await?;
+/// Returns the tracks of a FLAC file: the whole file, or one track per cue sheet track. Cue
+/// sheet fields take precedence over the tags of the file.
+fn scan_flac_tracks(
+    metadata: &FlacMetadata,
+    cue_sheet: Option<CueSheet>,
+    sidecar_lyrics: Vec<TrackLyrics>,
+) -> Result<Vec<FlacTrack>> {
+    let picture = metadata.get_picture_data(FlacPictureType::FrontCover);
+    let front_cover = metadata
+        .pictures
+        .iter()
+        .any(|p| p.picture_type == FlacPictureType::FrontCover);
+    let Some(sheet) = cue_sheet else {
+        let mut lyrics: Vec<TrackLyrics> = metadata
+            .get_lyrics()
+            .into_iter()
+            .map(|text| TrackLyrics { lang: None, text })
+            .collect();
+        lyrics.extend(sidecar_lyrics);
+        return Ok(vec![FlacTrack {
+            title: metadata.get_track_name()?,
+            album_name: metadata.get_album_name()?,
+            artists: metadata.get_artists()?,
+            album_artists: metadata.get_album_artists(),
+            musicbrainz_album_id: metadata.get_musicbrainz_album_id(),
+            picture,
+            front_cover,
+            runtime: metadata.get_runtime() as i64,
+            start_sample: None,
+            end_sample: None,
+            replay_gain: metadata.get_replay_gain(),
+            lyrics,
+        }]);
+    };
 
-    // if file exists, only continue if last modified is more recent
-    let modified: DateTime<Utc> = fs::metadata(path)?.modified()?.into();
-    if let Some(f) = &file {
-        if modified <= f.last_modified {
-            return Ok(());
-        }
+    let album_name = match &sheet.title {
+        Some(title) => title.clone(),
+        None => metadata.get_album_name()?,
+    };
+    let album_artists = match &sheet.performer {
+        Some(performer) => Some(vec![performer.clone()]),
+        None => metadata.get_album_artists(),
+    };
+    let artists = match &album_artists {
+        Some(aa) => aa.clone(),
+        None => metadata.get_artists()?,
+    };
+    // the loudness tags of a single file rip describe the whole album
+    let file_gain = metadata.get_replay_gain();
+    let replay_gain = ReplayGain {
+        album_gain: file_gain.album_gain.or(file_gain.track_gain),
+        album_peak: file_gain.album_peak.or(file_gain.track_peak),
+        ..Default::default()
+    };
+    let info = &metadata.stream_info;
+    let mut tracks = Vec::new();
+    for (i, t) in sheet.tracks.iter().enumerate() {
+        let end = sheet.end_sample(i);
+        let runtime = end
+            .unwrap_or(info.total_samples)
+            .saturating_sub(t.start_sample)
+            / info.sample_rate as u64;
+        tracks.push(FlacTrack {
+            title: t.title.clone().unwrap_or(format!("Track {:02}", t.number)),
+            album_name: album_name.clone(),
+            artists: t.performer.clone().map_or(artists.clone(), |p| vec![p]),
+            album_artists: album_artists.clone(),
+            musicbrainz_album_id: metadata.get_musicbrainz_album_id(),
+            picture: picture.clone(),
+            front_cover,
+            runtime: runtime as i64,
+            start_sample: Some(t.start_sample as i64),
+            end_sample: end.map(|e| e as i64),
+            replay_gain: replay_gain.clone(),
+            lyrics: Vec::new(),
+        });
     }
+    Ok(tracks)
+}
 
-    // extract useful metadata from file
-    let metadata = parse_flac_file(path)?;
-    let album_name: String = metadata.get_album_name()?;
-    let track_name: String = metadata.get_track_name()?;
-    let artists: Vec<String> = metadata.get_artists()?;
-    let runtime: i64 = metadata.get_runtime() as i64;
-    let album_artists: Option<Vec<String>> = metadata.get_album_artists();
-    let musicbrainz_album_id: Option<String> = metadata.get_musicbrainz_album_id();
-    let picture: Option<Vec<u8>> = metadata.get_picture_data(FlacPictureType::FrontCover);
+/// Writes a track and its album to the database, updating the existing track with the given
+/// id if there is one. Returns the id of the track.
+async fn scan_flac_track<C: ConnectionTrait + TransactionTrait>(
+    path: &Path,
+    t: FlacTrack,
+    existing_track_id: Option<Uuid>,
+    modified: DateTime<Utc>,
+    cache: &mut ScanCache,
+    db: &C,
+) -> Result<Uuid> {
+    // loudness values are only written if the file is tagged with them, so that the values
+    // computed by loudness analysis are kept across scans
+    let rg = &t.replay_gain;
 
     // check if album exists in database already
-    let album_id = match album_find(
-        &album_name,
-        &artists,
-        album_artists.clone(),
-        musicbrainz_album_id.clone(),
-        db,
-    )
-    .await
+    let album_name = t.album_name.trim();
+    let album_id = match cache
+        .album(
+            album_name,
+            &t.artists,
+            &t.album_artists,
+            &t.musicbrainz_album_id,
+            db,
+        )
+        .await?
     {
-        Some(id) => id,
+        Some(id) => {
+            if let Some(gain) = rg.album_gain {
+                album::Entity::update_many()
+                    .col_expr(album::Column::Gain, Expr::value(gain))
+                    .col_expr(album::Column::Peak, Expr::value(rg.album_peak))
+                    .filter(album::Column::Id.eq(id))
+                    .exec(db)
+                    .await?;
+            }
+            id
+        }
         None => {
             // insert new album into the database
             let album_id = Uuid::new_v4();
             let mut album = album::ActiveModel::builder()
                 .set_id(album_id)
-                .set_name(album_name.trim())
-                .set_musicbrainz_id(musicbrainz_album_id)
+                .set_name(album_name)
+                .set_musicbrainz_id(t.musicbrainz_album_id.clone())
                 .set_last_modified(modified);
-            if let Some(aa) = album_artists {
-                for artist in &aa {
-                    album = album.add_artist(artist_insert(artist, db).await);
+            if let Some(aa) = &t.album_artists {
+                for artist in aa {
+                    album = album.add_artist(cache.artist(artist, db).await?);
                 }
             }
+            if let Some(gain) = rg.album_gain {
+                album = album.set_gain(Some(gain)).set_peak(rg.album_peak);
+            }
             let _ = album.insert(db).await?;
+            cache.add_album(
+                album_name,
+                album_id,
+                &t.album_artists,
+                &t.musicbrainz_album_id,
+            );
             album_id
         }
     };
+    cache.album_changed(album_id);
+
+    // the directory above the album holds the picture of its (first) artist
+    if let Some(name) = t
+        .album_artists
+        .as_ref()
+        .and_then(|aa| aa.first())
+        .or(t.artists.first())
+    {
+        cache.artist_picture(name, path, db).await?;
+    }
 
     // turn list of artists into active models
-    let mut artist_models: Vec<artist::ActiveModel> = Vec::new();
-    for artist in &artists {
-        artist_models.push(artist_insert(artist, db).await);
-    }
-
-    // check if file has an existing track (update case) or needs new track (insert case)
-    let existing_track_id = file.as_ref().and_then(|f| f.track_id);
-    let track_id = if let Some(track_id) = existing_track_id {
-        // update existing track
-        let mut track = track::ActiveModel::builder()
-            .set_id(track_id)
-            .set_title(track_name.trim())
-            .set_picture(picture)
-            .set_runtime(runtime)
-            .set_album_id(album_id);
-        for artist in artist_models {
-            track = track.add_artist(artist);
-        }
+    let mut artist_models = Vec::new();
+    for artist in &t.artists {
+        artist_models.push(cache.artist(artist, db).await?);
+    }
+
+    // update the existing track (update case) or insert a new track (insert case)
+    let track_id = existing_track_id.unwrap_or_else(Uuid::new_v4);
+    let mut track = track::ActiveModel::builder()
+        .set_id(track_id)
+        .set_title(t.title.trim())
+        .set_picture(t.picture)
+        .set_front_cover(t.front_cover)
+        .set_runtime(t.runtime)
+        .set_start_sample(t.start_sample)
+        .set_end_sample(t.end_sample)
+        .set_album_id(album_id);
+    if let Some(gain) = rg.track_gain {
+        track = track
+            .set_track_gain(Some(gain))
+            .set_track_peak(rg.track_peak);
+    }
+    if let Some(gain) = rg.album_gain {
+        track = track
+            .set_album_gain(Some(gain))
+            .set_album_peak(rg.album_peak);
+    }
+    for artist in artist_models {
+        track = track.add_artist(artist);
+    }
+    if existing_track_id.is_some() {
         let _ = track.save(db).await?;
-        track_id
     } else {
-        // insert new track
-        let track_id = Uuid::new_v4();
-        let mut track = track::ActiveModel::builder()
-            .set_id(track_id)
-            .set_title(track_name.trim())
-            .set_picture(picture)
-            .set_runtime(runtime)
-            .set_album_id(album_id);
-        for artist in artist_models {
-            track = track.add_artist(artist);
-        }
         let _ = track.insert(db).await?;
-        track_id
-    };
+    }
+    lyrics_set(track_id, &t.lyrics, db).await?;
+    Ok(track_id)
+}
 
-    // update or create file in the database (must do this last)
-    if let Some(f) = file {
-        let mut f: file::ActiveModel = f.into();
-        f.last_modified = Set(modified);
-        let _ = f.update(db).await?;
+async fn scan_flac<C: ConnectionTrait + TransactionTrait>(
+    path: &Path,
+    target: ScanTarget,
+    metadata: FlacMetadata,
+    cue_sheet: Option<CueSheet>,
+    lyrics: Vec<TrackLyrics>,
+    cache: &mut ScanCache,
+    db: &C,
+) -> Result<ScanOutcome> {
+    let ScanTarget {
+        mut files,
+        modified,
+        size,
+    } = target;
+
+    // extract useful metadata from file
+    let tracks = scan_flac_tracks(&metadata, cue_sheet, lyrics)?;
+    let fingerprint = metadata
+        .get_fingerprint()
+        .map(|fp| scan_fingerprint(&fp, size));
+
+    // a new path may belong to a file that was moved, so keep its tracks if possible
+    if files.is_empty()
+        && let Some(fp) = &fingerprint
+    {
+        files = scan_find_moved(fp, db).await?;
+    }
+    let outcome = if files.is_empty() {
+        ScanOutcome::New
     } else {
-        let f = file::ActiveModel::builder()
-            .set_id(Uuid::new_v4())
-            .set_path(path.display().to_string())
-            .set_last_modified(modified)
-            .set_track_id(track_id);
-        let _ = f.insert(db).await?;
+        ScanOutcome::Updated
+    };
+
+    // reuse the existing file records in the order in which their tracks start
+    let track_ids = files.iter().filter_map(|f| f.track_id);
+    let starts: HashMap<Uuid, Option<i64>> = track::Entity::find()
+        .filter(track::Column::Id.is_in(track_ids))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|t| (t.id, t.start_sample))
+        .collect();
+    files.sort_by_key(|f| f.track_id.and_then(|id| starts.get(&id).copied().flatten()));
+    let mut files = files.into_iter();
+
+    for t in tracks {
+        let file = files.next();
+        let existing_track_id = file.as_ref().and_then(|f| f.track_id);
+        let track_id = scan_flac_track(path, t, existing_track_id, modified, cache, db).await?;
+
+        // update or create file in the database (must do this last)
+        if let Some(f) = file {
+            let mut f: file::ActiveModel = f.into();
+            f.path = Set(path.display().to_string());
+            f.last_modified = Set(modified);
+            f.size = Set(Some(size as i64));
+            f.fingerprint = Set(fingerprint.clone());
+            f.track_id = Set(Some(track_id));
+            let _ = f.update(db).await?;
+        } else {
+            let f = file::ActiveModel::builder()
+                .set_id(Uuid::new_v4())
+                .set_path(path.display().to_string())
+                .set_last_modified(modified)
+                .set_size(Some(size as i64))
+                .set_fingerprint(fingerprint.clone())
+                .set_track_id(track_id);
+            let _ = f.insert(db).await?;
+        }
     }
 
-    return Ok(());
+    // delete the records of tracks that are no longer in the cue sheet
+    for f in files {
+        if let Some(album_id) = scan_delete_file(f, db).await? {
+            cache.album_changed(album_id);
+        }
+    }
+    Ok(outcome)
 }
 
-async fn scan_cleanup(path: &str, db: &DatabaseConnection) -> Result<()> {
+/// Deletes a file record along with the track or book it holds. Albums left without any
+/// tracks are deleted as well. Returns the album of the deleted track if it still exists.
+async fn scan_delete_file<C: ConnectionTrait>(f: file::Model, db: &C) -> Result<Option<Uuid>> {
+    let track_id = f.track_id;
+    let book_id = f.book_id;
+    let mut changed = None;
+    f.delete(db).await?;
+    if let Some(track_id) = track_id
+        && let Some(t) = track::Entity::find_by_id(track_id).one(db).await?
+    {
+        let album_id = t.album_id;
+        t.delete(db).await?;
+        let track_count = track::Entity::find()
+            .filter(track::Column::AlbumId.eq(album_id))
+            .count(db)
+            .await?;
+        if track_count == 0 {
+            album::Entity::delete_by_id(album_id).exec(db).await?;
+        } else {
+            changed = Some(album_id);
+        }
+    }
+    if let Some(book_id) = book_id {
+        book::Entity::delete_by_id(book_id).exec(db).await?;
+    }
+    Ok(changed)
+}
+
+async fn scan_cleanup(path: &str, cache: &mut ScanCache, db: &DatabaseConnection) -> Result<u64> {
     // find all the files in the library that are in the database
     let files = File::find()
         .filter(file::Column::Path.starts_with(path))
@@ -206,18 +637,27 @@ async fn scan_cleanup(path: &str, db: &DatabaseConnection) -> Result<()> {
         .await?;
 
     // delete file records if the file no longer exists
+    let mut removed = 0;
     for f in files {
         if !Path::new(&f.path).exists() {
-            let track_id = f.track_id;
-            f.delete(db).await?;
-            if let Some(track_id) = track_id {
-                if let Some(t) = track::Entity::find_by_id(track_id).one(db).await? {
-                    t.delete(db).await?;
-                }
+            removed += 1;
+            if let Some(album_id) = scan_delete_file(f, db).await? {
+                cache.album_changed(album_id);
             }
         }
     }
 
+    // forget recorded failures of files that no longer exist
+    let errors = scan_error::Entity::find()
+        .filter(scan_error::Column::Path.starts_with(path))
+        .all(db)
+        .await?;
+    for e in errors {
+        if !Path::new(&e.path).exists() {
+            e.delete(db).await?;
+        }
+    }
+
     // delete orphaned albums (albums with no tracks)
     let albums = album::Entity::find().all(db).await?;
     for a in albums {
@@ -230,22 +670,502 @@ async fn scan_cleanup(path: &str, db: &DatabaseConnection) -> Result<()> {
         }
     }
 
-    Ok(())
+    Ok(removed)
 }
 
-pub async fn scan(path: &str, db: &DatabaseConnection) -> Result<()> {
-    scan_cleanup(&path, db).await?;
-    for entry in WalkDir::new(path) {
-        let entry = entry?;
-        let path = entry.path();
-        if !entry.file_type().is_file() {
-            continue;
+/// Returns a condition matching the given path, or any path inside it if it is a directory.
+fn scan_path_condition(column: impl ColumnTrait, path: &str) -> Condition {
+    Condition::any()
+        .add(column.eq(path))
+        .add(column.starts_with(format!("{}/", path.trim_end_matches('/'))))
+}
+
+/// Removes the file (or all files in the directory) at the given path from the library. This
+/// is the single path counterpart of the cleanup done at the start of a full scan.
+pub async fn scan_remove(
+    path: &Path,
+    cache: &mut ScanCache,
+    db: &DatabaseConnection,
+) -> Result<u64> {
+    let path = path.display().to_string();
+    let files = File::find()
+        .filter(scan_path_condition(file::Column::Path, &path))
+        .all(db)
+        .await?;
+    let removed = files.len() as u64;
+    for f in files {
+        if let Some(album_id) = scan_delete_file(f, db).await? {
+            cache.album_changed(album_id);
+        }
+    }
+    scan_error::Entity::delete_many()
+        .filter(scan_path_condition(scan_error::Column::Path, &path))
+        .exec(db)
+        .await?;
+    Ok(removed)
+}
+
+/// Moves the file records of the file (or all files in the directory) at the given path to
+/// their new path, so that the tracks and books they hold keep their identity.
+pub async fn scan_rename(from: &Path, to: &Path, db: &DatabaseConnection) -> Result<u64> {
+    let from = from.display().to_string();
+    let to = to.display().to_string();
+    let files = File::find()
+        .filter(scan_path_condition(file::Column::Path, &from))
+        .all(db)
+        .await?;
+    let renamed = files.len() as u64;
+    for f in files {
+        let path = format!("{}{}", to, &f.path[from.len()..]);
+        let mut f: file::ActiveModel = f.into();
+        f.path = Set(path);
+        f.update(db).await?;
+    }
+    scan_error::Entity::delete_many()
+        .filter(scan_path_condition(scan_error::Column::Path, &from))
+        .exec(db)
+        .await?;
+    Ok(renamed)
+}
+
+/// Checks whether the file at the given path changed since the last scan (or always, if a
+/// full scan), and parses its metadata if so. This runs on the blocking thread pool.
+fn scan_parse(
+    path: &Path,
+    format: &str,
+    files: Vec<file::Model>,
+    full: bool,
+    artwork: &ArtworkConfig,
+) -> Result<Option<(ScanTarget, ScanMetadata)>> {
+    // a file is also modified when the cue sheet, lyrics or pictures next to it are
+    let fs_metadata = fs::metadata(path)?;
+    let mut modified: DateTime<Utc> = fs_metadata.modified()?.into();
+    let cue_path = scan_sidecar_cue(path).filter(|_| format == "flac");
+    let lyrics_paths = match format {
+        "flac" => scan_sidecar_lyrics(path),
+        _ => Vec::new(),
+    };
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let picture_paths = match format {
+        "flac" => [
+            scan_sidecar_picture(directory, &artwork.cover_files),
+            directory
+                .parent()
+                .and_then(|d| scan_sidecar_picture(d, &artwork.artist_files)),
+        ],
+        _ => [None, None],
+    };
+    for sidecar in cue_path
+        .iter()
+        .chain(lyrics_paths.iter().map(|(p, _)| p))
+        .chain(picture_paths.iter().flatten())
+    {
+        modified = modified.max(fs::metadata(sidecar)?.modified()?.into());
+    }
+
+    // if file exists, only continue if last modified is more recent (unless a full scan), or
+    // if it was scanned before fingerprints were recorded
+    if let Some(f) = files.first()
+        && !full
+        && modified <= f.last_modified
+        && f.fingerprint.is_some()
+    {
+        return Ok(None);
+    }
+
+    let metadata = match format {
+        "flac" => {
+            let metadata = parse_flac_file(path, true)?;
+            let cue_sheet = match &cue_path {
+                Some(cue_path) => {
+                    let mut sheet = parse_cue_file(cue_path, metadata.stream_info.sample_rate)?;
+                    if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                        sheet.retain_file(name);
+                    }
+                    Some(sheet).filter(|s| s.tracks.len() > 1)
+                }
+                None => metadata.get_cue_sheet(),
+            };
+            let mut lyrics = Vec::new();
+            for (lyrics_path, lang) in lyrics_paths {
+                let text = read_lrc_file(&lyrics_path)?;
+                lyrics.push(TrackLyrics { lang, text });
+            }
+            ScanMetadata::Flac(Box::new(metadata), cue_sheet, lyrics)
         }
-        match path.extension().and_then(|s| s.to_str()) {
-            Some("flac") => scan_flac(path, db).await?,
-            Some("epub") => scan_epub(path, db).await?,
-            _ => continue,
+        _ => ScanMetadata::Epub(Box::new(parse_epub_file(path)?)),
+    };
+    let target = ScanTarget {
+        files,
+        modified,
+        size: fs_metadata.len(),
+    };
+    Ok(Some((target, metadata)))
+}
+
+/// Parses the given files (usually the files of a single directory) concurrently, with at
+/// most as many files being parsed at once as there are permits in the semaphore.
+async fn scan_prepare(
+    paths: Vec<PathBuf>,
+    full: bool,
+    artwork: Arc<ArtworkConfig>,
+    db: Arc<DatabaseConnection>,
+    parsers: Arc<Semaphore>,
+) -> Result<Vec<ScanItem>> {
+    // look up the existing file records of all the files at once
+    let mut files: HashMap<String, Vec<file::Model>> = HashMap::new();
+    for f in File::find()
+        .filter(file::Column::Path.is_in(paths.iter().map(|p| p.display().to_string())))
+        .all(db.as_ref())
+        .await?
+    {
+        files.entry(f.path.clone()).or_default().push(f);
+    }
+
+    // books scanned before partial MD5 digests were recorded are parsed again to get one
+    let book_ids: Vec<Uuid> = files.values().flatten().filter_map(|f| f.book_id).collect();
+    let missing_md5: HashSet<Uuid> = book::Entity::find()
+        .select_only()
+        .column(book::Column::Id)
+        .filter(book::Column::Id.is_in(book_ids))
+        .filter(book::Column::PartialMd5.is_null())
+        .into_tuple()
+        .all(db.as_ref())
+        .await?
+        .into_iter()
+        .collect();
+
+    // parse the files on the blocking thread pool
+    let mut handles = Vec::new();
+    for path in paths {
+        let Some(format) = scan_format(&path) else {
+            continue;
+        };
+        let files = files
+            .remove(&path.display().to_string())
+            .unwrap_or_default();
+        let rescan = full
+            || files
+                .iter()
+                .any(|f| f.book_id.is_some_and(|id| missing_md5.contains(&id)));
+        let permit = parsers.clone().acquire_owned().await?;
+        let parse_path = path.clone();
+        let artwork = artwork.clone();
+        let handle = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            scan_parse(&parse_path, format, files, rescan, &artwork)
+        });
+        handles.push((path, format, handle));
+    }
+
+    let mut items = Vec::new();
+    for (path, format, handle) in handles {
+        let parsed = match handle.await {
+            Ok(parsed) => parsed,
+            Err(e) => Err(anyhow!("[ERROR] Failed to parse file: {}", e)),
+        };
+        items.push(ScanItem {
+            path,
+            format,
+            parsed,
+        });
+    }
+    Ok(items)
+}
+
+/// Writes the parsed files to the database in a single transaction. Each file is written in
+/// its own savepoint, so that a file that fails to be written does not leave anything behind
+/// and does not affect the other files.
+async fn scan_write(
+    items: Vec<ScanItem>,
+    cache: &mut ScanCache,
+    db: &DatabaseConnection,
+) -> Result<Vec<(ScanItem, Result<ScanOutcome>)>> {
+    let txn = db.begin().await?;
+    let mut results = Vec::new();
+    for mut item in items {
+        let parsed = std::mem::replace(&mut item.parsed, Ok(None));
+        let result = match parsed {
+            Ok(Some((target, metadata))) => {
+                let savepoint = txn.begin().await?;
+                let result = match metadata {
+                    ScanMetadata::Flac(m, cue_sheet, lyrics) => {
+                        scan_flac(&item.path, target, *m, cue_sheet, lyrics, cache, &savepoint)
+                            .await
+                    }
+                    ScanMetadata::Epub(m) => {
+                        scan_epub(&item.path, target, *m, cache, &savepoint).await
+                    }
+                };
+                if result.is_ok() {
+                    savepoint.commit().await?;
+                } else {
+                    savepoint.rollback().await?;
+                    cache.clear();
+                }
+                result
+            }
+            Ok(None) => Ok(ScanOutcome::Unchanged),
+            Err(e) => Err(e),
         };
+        results.push((item, result));
+    }
+    txn.commit().await?;
+    Ok(results)
+}
+
+/// Records the files that failed to scan in the database so that they can be reported and
+/// fixed, and clears the previous failures of files that were scanned successfully.
+async fn scan_record(
+    results: Vec<(ScanItem, Result<ScanOutcome>)>,
+    db: &DatabaseConnection,
+) -> Result<Vec<ScanOutcome>> {
+    let mut outcomes = Vec::new();
+    let mut scanned = Vec::new();
+    for (item, result) in results {
+        let path = item.path.display().to_string();
+        match result {
+            Ok(outcome) => {
+                if !matches!(outcome, ScanOutcome::Unchanged) {
+                    scanned.push(path);
+                }
+                outcomes.push(outcome);
+            }
+            Err(e) => {
+                println!("[ERROR] Failed to scan {}: {}", path, e);
+                scan_error_record(&path, item.format, &e.to_string(), db).await?;
+                outcomes.push(ScanOutcome::Failed);
+            }
+        }
+    }
+    if !scanned.is_empty() {
+        scan_error_clear(scanned, db).await?;
+    }
+    Ok(outcomes)
+}
+
+/// Scans a single file if it has a supported format. A file that fails to scan is recorded in
+/// the database so that it can be reported and fixed, and a previous failure is cleared once
+/// the file scans successfully. A cue sheet or lyrics file is scanned as the audio file it is
+/// stored next to.
+/// Returns None if the file format is not supported.
+pub async fn scan_file(
+    path: &Path,
+    full: bool,
+    cache: &mut ScanCache,
+    db: &Arc<DatabaseConnection>,
+) -> Result<Option<ScanOutcome>> {
+    let path = &match path.extension().and_then(|s| s.to_str()) {
+        Some("cue") | Some("lrc") | Some("txt") => match scan_sidecar_audio(path) {
+            Some(audio) => audio,
+            None => return Ok(None),
+        },
+        _ => path.to_path_buf(),
+    };
+    if scan_format(path).is_none() {
+        return Ok(None);
+    }
+    let parsers = Arc::new(Semaphore::new(1));
+    let items = scan_prepare(
+        vec![path.to_path_buf()],
+        full,
+        cache.artwork().clone(),
+        db.clone(),
+        parsers,
+    )
+    .await?;
+    let results = scan_write(items, cache, db).await?;
+    cache.album_pictures_update(db.as_ref()).await?;
+    Ok(scan_record(results, db).await?.pop())
+}
+
+/// Result of scanning a single file.
+pub enum ScanOutcome {
+    New,
+    Updated,
+    Unchanged,
+    Failed,
+}
+
+/// Progress of the current (or most recent) library scan.
+#[derive(Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanStatus {
+    pub scanning: bool,
+    pub full: bool,
+    pub count: u64,
+    pub new: u64,
+    pub updated: u64,
+    pub removed: u64,
+    pub failed: u64,
+    pub current_path: Option<String>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Runs library scans in the background, making sure that only one scan runs at a time and
+/// keeping track of its progress.
+pub struct Scanner {
+    running: JobFlag,
+    status: Mutex<ScanStatus>,
+    artwork: Arc<ArtworkConfig>,
+    library: Arc<LibraryLock>,
+}
+
+impl Scanner {
+    pub fn new(artwork: Arc<ArtworkConfig>) -> Self {
+        Scanner {
+            running: JobFlag::default(),
+            status: Mutex::new(ScanStatus::default()),
+            artwork,
+            library: Arc::new(LibraryLock::default()),
+        }
+    }
+
+    /// Returns the lock that the other writers of the library share with scans.
+    pub fn library(&self) -> &Arc<LibraryLock> {
+        &self.library
+    }
+
+    /// Returns a snapshot of the progress of the current (or most recent) scan.
+    pub fn status(&self) -> ScanStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Starts a scan of the library at the given path as a background task. A full scan
+    /// rescans every file, while an incremental scan skips files that have not been modified.
+    pub fn start(
+        self: &Arc<Self>,
+        path: &str,
+        full: bool,
+        db: &Arc<DatabaseConnection>,
+    ) -> Result<()> {
+        let scanner = self.clone();
+        let path = path.to_owned();
+        let db = db.clone();
+        let finished = self.clone();
+        self.running.spawn(
+            "[ERROR] A library scan is already in progress",
+            || {
+                *self.status.lock().unwrap() = ScanStatus {
+                    scanning: true,
+                    full,
+                    started_at: Some(Utc::now()),
+                    ..Default::default()
+                };
+                async move {
+                    if let Err(e) = scanner.scan(&path, full, &db).await {
+                        println!("[ERROR] Failed to scan library: {}", e);
+                    }
+                }
+            },
+            move || {
+                let mut status = finished.status.lock().unwrap();
+                status.scanning = false;
+                status.current_path = None;
+                status.finished_at = Some(Utc::now());
+            },
+        )
+    }
+
+    /// Starts an incremental scan of the library whenever the given cron schedule fires.
+    pub fn schedule(
+        self: &Arc<Self>,
+        schedule: &str,
+        path: &str,
+        db: &Arc<DatabaseConnection>,
+    ) -> Result<()> {
+        let schedule = Schedule::from_str(schedule)
+            .map_err(|e| anyhow!("[ERROR] Invalid scan schedule: {}", e))?;
+        let scanner = self.clone();
+        let path = path.to_owned();
+        let db = db.clone();
+        tokio::spawn(async move {
+            while let Some(next) = schedule.upcoming(Utc).next() {
+                let wait = (next - Utc::now()).to_std().unwrap_or_default();
+                tokio::time::sleep(wait).await;
+                if let Err(e) = scanner.start(&path, false, &db) {
+                    println!("{}", e);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Scans the library at the given path. Files are parsed concurrently a few directories
+    /// ahead, while the files of each directory are written to the database in a single
+    /// transaction. A file that fails to scan does not abort the scan; instead the failure is
+    /// recorded by scan_record().
+    async fn scan(&self, path: &str, full: bool, db: &Arc<DatabaseConnection>) -> Result<()> {
+        // group the supported files in the library by directory
+        let mut directories: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+        for entry in WalkDir::new(path) {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => {
+                    println!("[ERROR] Failed to read library entry: {}", e);
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() || scan_format(entry.path()).is_none() {
+                continue;
+            }
+            let directory = entry.path().parent().unwrap_or(Path::new("")).to_path_buf();
+            directories
+                .entry(directory)
+                .or_default()
+                .push(entry.into_path());
+        }
+
+        // parse the directories ahead of writing them, bounded by the number of cpus
+        let parallelism = std::thread::available_parallelism().map_or(4, |n| n.get());
+        let parsers = Arc::new(Semaphore::new(parallelism));
+        let mut cache = ScanCache::new(self.artwork.clone());
+        let mut directories = directories.into_iter();
+        let mut pending = VecDeque::new();
+        loop {
+            while pending.len() < SCAN_PREFETCH {
+                let Some((directory, paths)) = directories.next() else {
+                    break;
+                };
+                let prepare = scan_prepare(
+                    paths,
+                    full,
+                    self.artwork.clone(),
+                    db.clone(),
+                    parsers.clone(),
+                );
+                pending.push_back((directory, tokio::spawn(prepare)));
+            }
+            let Some((directory, handle)) = pending.pop_front() else {
+                break;
+            };
+            let items = handle.await??;
+            self.status.lock().unwrap().current_path = Some(directory.display().to_string());
+            let lock = cache.lock(&self.library).await;
+            let results = scan_write(items, &mut cache, db).await?;
+            let outcomes = scan_record(results, db).await?;
+            drop(lock);
+
+            // update the progress of the scan
+            let mut status = self.status.lock().unwrap();
+            for outcome in outcomes {
+                match outcome {
+                    ScanOutcome::New => status.new += 1,
+                    ScanOutcome::Updated => status.updated += 1,
+                    ScanOutcome::Unchanged => {}
+                    ScanOutcome::Failed => status.failed += 1,
+                }
+                status.count += 1;
+            }
+        }
+
+        // clean up after the walk, so that moved files are relinked rather than deleted
+        let lock = cache.lock(&self.library).await;
+        let removed = scan_cleanup(path, &mut cache, db).await?;
+        cache.album_pictures_update(db.as_ref()).await?;
+        drop(lock);
+        self.status.lock().unwrap().removed = removed;
+        Ok(())
     }
-    Ok(())
 }
@@ -13,7 +13,7 @@ use crate::db::{
 };
 
 /// Gets a user's ID from their username.
-async fn get_user_id(username: &str, db: &DatabaseConnection) -> Result<Uuid> {
+pub async fn get_user_id(username: &str, db: &DatabaseConnection) -> Result<Uuid> {
     if let Some(user) = User::find()
         .filter(user::Column::Username.eq(username))
         .one(db)
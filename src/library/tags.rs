@@ -0,0 +1,122 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Result, anyhow};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        album::Entity as Album,
+        file::{self, Entity as File},
+        track::{self, Entity as Track},
+    },
+    format::flac::{FlacTagEdit, write_flac_tags},
+    library::{
+        scan_cache::{LibraryLock, ScanCache},
+        scanner::{ScanOutcome, scan_file},
+    },
+    settings::ArtworkConfig,
+};
+
+/// Checks the name of a Vorbis comment field: printable ascii, except for '='.
+pub fn tags_valid_field(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| (0x20..=0x7d).contains(&b) && b != b'=')
+}
+
+/// Writes the changes to the tags of a FLAC file and scans it again.
+pub async fn tags_write_file(
+    path: &Path,
+    edit: &FlacTagEdit,
+    cache: &mut ScanCache,
+    db: &Arc<DatabaseConnection>,
+) -> Result<()> {
+    let write_path = path.to_path_buf();
+    let write_edit = edit.clone();
+    tokio::task::spawn_blocking(move || write_flac_tags(&write_path, &write_edit)).await??;
+    if let Some(ScanOutcome::Failed) = scan_file(path, true, cache, db).await? {
+        return Err(anyhow!(
+            "[ERROR] Failed to scan {} after editing its tags",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Writes the changes to the tags of the given FLAC files and scans them again, one file at a
+/// time so that a failure leaves the other files as they were.
+async fn tags_write(
+    paths: Vec<PathBuf>,
+    edit: FlacTagEdit,
+    artwork: &Arc<ArtworkConfig>,
+    library: &LibraryLock,
+    db: &Arc<DatabaseConnection>,
+) -> Result<()> {
+    let mut cache = ScanCache::new(artwork.clone());
+    let _lock = cache.lock(library).await;
+    for path in paths {
+        tags_write_file(&path, &edit, &mut cache, db).await?;
+    }
+    Ok(())
+}
+
+/// Edits the tags of the audio file of a track. Tracks that are part of a single file rip of a
+/// whole album cannot be edited on their own, as their file holds the other tracks as well.
+pub async fn tags_update_track(
+    id: Uuid,
+    edit: FlacTagEdit,
+    artwork: &Arc<ArtworkConfig>,
+    library: &LibraryLock,
+    db: &Arc<DatabaseConnection>,
+) -> Result<()> {
+    let track = Track::find_by_id(id)
+        .one(db.as_ref())
+        .await?
+        .ok_or(anyhow!("[ERROR] Track not found in database"))?;
+    if track.start_sample.is_some() {
+        return Err(anyhow!(
+            "[ERROR] Track is part of a single file rip, edit the tags of its album instead"
+        ));
+    }
+    let file = File::find()
+        .filter(file::Column::TrackId.eq(id))
+        .one(db.as_ref())
+        .await?
+        .ok_or(anyhow!("[ERROR] Track does not have an audio file"))?;
+    tags_write(vec![PathBuf::from(file.path)], edit, artwork, library, db).await
+}
+
+/// Edits the tags of the audio files of every track of an album.
+pub async fn tags_update_album(
+    id: Uuid,
+    edit: FlacTagEdit,
+    artwork: &Arc<ArtworkConfig>,
+    library: &LibraryLock,
+    db: &Arc<DatabaseConnection>,
+) -> Result<()> {
+    Album::find_by_id(id)
+        .one(db.as_ref())
+        .await?
+        .ok_or(anyhow!("[ERROR] Album not found in database"))?;
+    let tracks = Track::find()
+        .filter(track::Column::AlbumId.eq(id))
+        .all(db.as_ref())
+        .await?;
+    let mut paths: Vec<PathBuf> = File::find()
+        .filter(file::Column::TrackId.is_in(tracks.iter().map(|t| t.id)))
+        .all(db.as_ref())
+        .await?
+        .into_iter()
+        .map(|f| PathBuf::from(f.path))
+        .collect();
+
+    // the tracks of a single file rip share their file
+    paths.sort();
+    paths.dedup();
+    tags_write(paths, edit, artwork, library, db).await
+}
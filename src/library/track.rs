@@ -11,6 +11,16 @@ use crate::{
     format::flac::FlacPictureType,
 };
 
+/// Loudness normalization values of a track, as ReplayGain gains in dB relative to -18 LUFS
+/// and linear peaks.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayGain {
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+}
+
 pub trait TrackMetadata {
     // required metadata fields
     fn get_album_name(&self) -> Result<String>;
@@ -22,6 +32,9 @@ pub trait TrackMetadata {
     fn get_album_artists(&self) -> Option<Vec<String>>;
     fn get_musicbrainz_album_id(&self) -> Option<String>;
     fn get_picture_data(&self, priority: FlacPictureType) -> Option<Vec<u8>>;
+    fn get_fingerprint(&self) -> Option<String>;
+    fn get_replay_gain(&self) -> ReplayGain;
+    fn get_lyrics(&self) -> Vec<String>;
 }
 
 /// Gets a specific track from the database.
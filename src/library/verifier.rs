@@ -0,0 +1,169 @@
+use std::{
+    collections::BTreeSet,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, EntityTrait, Order, QueryFilter, QueryOrder, QuerySelect,
+    sea_query::Expr,
+};
+use serde::Serialize;
+use tokio::task::JoinSet;
+
+use crate::{
+    db::file::{self, Entity as File},
+    format::flac::{FlacVerification, verify_flac_file},
+    library::job::JobFlag,
+};
+
+/// Values of file.verify_status.
+pub const VERIFY_OK: &str = "ok";
+pub const VERIFY_CORRUPT: &str = "corrupt";
+pub const VERIFY_UNVERIFIABLE: &str = "unverifiable";
+
+/// Stores the result of verifying the file at the given path on all of its file records.
+async fn verify_record(
+    path: &str,
+    verification: &FlacVerification,
+    db: &DatabaseConnection,
+) -> Result<()> {
+    let (status, error) = match verification {
+        FlacVerification::Ok => (VERIFY_OK, None),
+        FlacVerification::Corrupt(e) => (VERIFY_CORRUPT, Some(e.clone())),
+        FlacVerification::Unverifiable(e) => (VERIFY_UNVERIFIABLE, Some(e.clone())),
+    };
+    File::update_many()
+        .col_expr(file::Column::VerifyStatus, Expr::value(status))
+        .col_expr(file::Column::VerifyError, Expr::value(error))
+        .col_expr(file::Column::VerifiedAt, Expr::value(Utc::now()))
+        .filter(file::Column::Path.eq(path))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Gets the files that were found to be corrupted or could not be verified.
+pub async fn verify_get_report(len: u32, offset: u32, db: &DatabaseConnection) -> Vec<file::Model> {
+    File::find()
+        .filter(file::Column::VerifyStatus.is_in([VERIFY_CORRUPT, VERIFY_UNVERIFIABLE]))
+        .order_by(file::Column::Path, Order::Asc)
+        .offset(offset as u64)
+        .limit(len as u64)
+        .all(db)
+        .await
+        .unwrap_or_default()
+}
+
+/// Progress of the current (or most recent) library verification.
+#[derive(Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyStatus {
+    pub verifying: bool,
+    pub full: bool,
+    pub total: u64,
+    pub count: u64,
+    pub ok: u64,
+    pub corrupt: u64,
+    pub unverifiable: u64,
+    pub current_path: Option<String>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Verifies the audio of the FLAC files in the library against their checksums in the
+/// background, making sure that only one verification runs at a time.
+#[derive(Default)]
+pub struct Verifier {
+    running: JobFlag,
+    status: Mutex<VerifyStatus>,
+}
+
+impl Verifier {
+    /// Returns a snapshot of the progress of the current (or most recent) verification.
+    pub fn status(&self) -> VerifyStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Starts verifying the library as a background task. A full verification checks every
+    /// file, while an incremental one only checks files modified since they were last checked.
+    pub fn start(self: &Arc<Self>, full: bool, db: &Arc<DatabaseConnection>) -> Result<()> {
+        let verifier = self.clone();
+        let db = db.clone();
+        let finished = self.clone();
+        self.running.spawn(
+            "[ERROR] A library verification is already in progress",
+            || {
+                *self.status.lock().unwrap() = VerifyStatus {
+                    verifying: true,
+                    full,
+                    started_at: Some(Utc::now()),
+                    ..Default::default()
+                };
+                async move {
+                    if let Err(e) = verifier.verify(full, &db).await {
+                        println!("[ERROR] Failed to verify library: {}", e);
+                    }
+                }
+            },
+            move || {
+                let mut status = finished.status.lock().unwrap();
+                status.verifying = false;
+                status.current_path = None;
+                status.finished_at = Some(Utc::now());
+            },
+        )
+    }
+
+    /// Verifies the audio files of the library. Decoding is slow and CPU bound, so only a few
+    /// files are decoded at once, leaving room for streaming and scanning.
+    async fn verify(&self, full: bool, db: &DatabaseConnection) -> Result<()> {
+        // find the audio files that were not verified since they were last modified, once per
+        // path as the tracks of a cue sheet share a file
+        let paths: BTreeSet<String> = File::find()
+            .filter(file::Column::TrackId.is_not_null())
+            .all(db)
+            .await?
+            .into_iter()
+            .filter(|f| full || f.verified_at.is_none_or(|v| v < f.last_modified))
+            .map(|f| f.path)
+            .collect();
+        self.status.lock().unwrap().total = paths.len() as u64;
+
+        let parallelism = std::thread::available_parallelism().map_or(2, |n| n.get() / 2);
+        let mut pending = JoinSet::new();
+        let mut paths = paths.into_iter();
+        loop {
+            while pending.len() < parallelism.max(1) {
+                let Some(path) = paths.next() else {
+                    break;
+                };
+                pending.spawn_blocking(move || {
+                    let verification = verify_flac_file(Path::new(&path));
+                    (path, verification)
+                });
+            }
+            let Some(result) = pending.join_next().await else {
+                break;
+            };
+            let (path, verification) = result?;
+            if let FlacVerification::Corrupt(e) = &verification {
+                println!("[ERROR] Corrupted audio in {}: {}", path, e);
+            }
+            verify_record(&path, &verification, db).await?;
+
+            // update the progress of the verification
+            let mut status = self.status.lock().unwrap();
+            match verification {
+                FlacVerification::Ok => status.ok += 1,
+                FlacVerification::Corrupt(_) => status.corrupt += 1,
+                FlacVerification::Unverifiable(_) => status.unverifiable += 1,
+            }
+            status.count += 1;
+            status.current_path = Some(path);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use anyhow::{Result, anyhow};
+use notify_debouncer_full::{
+    DebounceEventResult, DebouncedEvent, new_debouncer,
+    notify::{
+        EventKind, RecursiveMode,
+        event::{ModifyKind, RenameMode},
+    },
+};
+use sea_orm::DatabaseConnection;
+use tokio::sync::mpsc;
+use walkdir::WalkDir;
+
+use super::{
+    scan_cache::{LibraryLock, ScanCache},
+    scanner::{scan_file, scan_remove, scan_rename},
+};
+use crate::settings::ArtworkConfig;
+
+/// How long the filesystem has to be quiet before the collected events are handled.
+const WATCH_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Handles a single debounced filesystem event by updating the library incrementally.
+async fn watch_handle(
+    event: &DebouncedEvent,
+    artwork: &Arc<ArtworkConfig>,
+    db: &Arc<DatabaseConnection>,
+) -> Result<()> {
+    match event.kind {
+        // renamed or moved within the library, possibly a whole directory
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+            let (from, to) = (&event.paths[0], &event.paths[1]);
+            scan_rename(from, to, db).await?;
+            watch_scan(to, false, artwork, db).await?;
+
+            // a renamed picture may no longer apply, or start to apply, to its directory
+            if (watch_is_picture(from, artwork) || watch_is_picture(to, artwork))
+                && let Some(directory) = to.parent()
+            {
+                watch_scan(directory, true, artwork, db).await?;
+            }
+        }
+        // moved out of the library
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) | EventKind::Remove(_) => {
+            let mut cache = ScanCache::new(artwork.clone());
+            for path in &event.paths {
+                scan_remove(path, &mut cache, db).await?;
+
+                // the audio file of a removed cue sheet is no longer split into tracks, and
+                // that of a removed lyrics file no longer has those lyrics
+                if path
+                    .extension()
+                    .is_some_and(|e| e == "cue" || e == "lrc" || e == "txt")
+                {
+                    scan_file(path, true, &mut cache, db).await?;
+                }
+
+                // the albums and artists in and below the directory of a removed picture
+                // no longer have it
+                if watch_is_picture(path, artwork)
+                    && let Some(directory) = path.parent()
+                {
+                    watch_scan(directory, true, artwork, db).await?;
+                }
+            }
+            cache.album_pictures_update(db.as_ref()).await?;
+        }
+        // created, written to or moved into the library
+        EventKind::Create(_) | EventKind::Modify(_) => {
+            for path in &event.paths {
+                // a new picture applies to all the audio files in and below its directory
+                match path.parent() {
+                    Some(directory) if watch_is_picture(path, artwork) => {
+                        watch_scan(directory, false, artwork, db).await?
+                    }
+                    _ => watch_scan(path, false, artwork, db).await?,
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Scans the file (or all files in the directory) at the given path.
+async fn watch_scan(
+    path: &Path,
+    full: bool,
+    artwork: &Arc<ArtworkConfig>,
+    db: &Arc<DatabaseConnection>,
+) -> Result<()> {
+    // the files of a directory share a cache, so that their artists and albums are only
+    // looked up once
+    let mut cache = ScanCache::new(artwork.clone());
+    if path.is_dir() {
+        for entry in WalkDir::new(path).into_iter().flatten() {
+            if entry.file_type().is_file() {
+                scan_file(entry.path(), full, &mut cache, db).await?;
+            }
+        }
+    } else if path.is_file() {
+        scan_file(path, full, &mut cache, db).await?;
+    }
+    Ok(())
+}
+
+/// Returns whether the file at the given path is one of the configured cover or artist
+/// pictures.
+fn watch_is_picture(path: &Path, artwork: &ArtworkConfig) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    artwork
+        .cover_files
+        .iter()
+        .chain(&artwork.artist_files)
+        .any(|n| n.eq_ignore_ascii_case(name))
+}
+
+/// Watches the library at the given path for changes, and keeps the database up to date with
+/// them without requiring a full scan. Changes are written while holding the library lock, so
+/// that they wait for a running scan or tag edit.
+pub fn watch(
+    path: &str,
+    artwork: &Arc<ArtworkConfig>,
+    library: &Arc<LibraryLock>,
+    db: &Arc<DatabaseConnection>,
+) -> Result<()> {
+    // forward debounced events from the watcher thread to the async runtime
+    let (tx, mut rx) = mpsc::unbounded_channel::<DebounceEventResult>();
+    let mut debouncer = new_debouncer(WATCH_DEBOUNCE, None, move |result| {
+        let _ = tx.send(result);
+    })
+    .map_err(|e| anyhow!("[ERROR] Failed to create library watcher: {}", e))?;
+    debouncer
+        .watch(Path::new(path), RecursiveMode::Recursive)
+        .map_err(|e| anyhow!("[ERROR] Failed to watch library at {}: {}", path, e))?;
+
+    let artwork = artwork.clone();
+    let library = library.clone();
+    let db = db.clone();
+    tokio::spawn(async move {
+        // the watcher stops when the debouncer is dropped, so keep it alive in this task
+        let _debouncer = debouncer;
+        while let Some(result) = rx.recv().await {
+            match result {
+                Ok(events) => {
+                    let _lock = library.lock().await;
+                    for event in &events {
+                        if let Err(e) = watch_handle(event, &artwork, &db).await {
+                            println!("[ERROR] Failed to handle library change: {}", e);
+                        }
+                    }
+                }
+                Err(errors) => {
+                    for e in errors {
+                        println!("[ERROR] Library watcher error: {}", e);
+                    }
+                }
+            }
+        }
+    });
+    Ok(())
+}
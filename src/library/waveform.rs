@@ -0,0 +1,266 @@
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set, sea_query::OnConflict,
+};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::{
+    db::{
+        track,
+        waveform::{self, Entity as Waveform},
+    },
+    format::flac::FlacDecoder,
+};
+
+/// Numbers of peaks that waveforms are generated with. Requests are rounded up to one of them,
+/// so that only a few waveforms of each track are cached.
+const WAVEFORM_RESOLUTIONS: [u32; 7] = [100, 250, 500, 1000, 2000, 5000, 10000];
+
+/// Rounds a requested number of peaks up to the nearest resolution. There is none for zero
+/// peaks, or more than the largest resolution.
+pub fn waveform_resolution(samples: u32) -> Option<u32> {
+    WAVEFORM_RESOLUTIONS
+        .into_iter()
+        .find(|r| *r >= samples)
+        .filter(|_| samples > 0)
+}
+
+/// Minimum and maximum sample of every channel within evenly sized buckets of a track, as
+/// 16-bit values. Peaks are stored interleaved: bucket by bucket, channel by channel, with the
+/// minimum before the maximum.
+pub struct WaveformPeaks {
+    pub channels: u16,
+    pub samples: u32,
+    pub peaks: Vec<i16>,
+}
+
+impl WaveformPeaks {
+    /// Encodes the peaks in the compact binary format: the number of channels (u16) and of
+    /// buckets (u32), followed by the interleaved peaks (i16), all little endian.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(6 + self.peaks.len() * 2);
+        bytes.extend_from_slice(&self.channels.to_le_bytes());
+        bytes.extend_from_slice(&self.samples.to_le_bytes());
+        for peak in &self.peaks {
+            bytes.extend_from_slice(&peak.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Merges the buckets down to the given number, which must not be more than there are,
+    /// keeping the minimum of the minimums and the maximum of the maximums.
+    fn downsample(self, samples: u32) -> WaveformPeaks {
+        if samples >= self.samples {
+            return self;
+        }
+        let channels = self.channels as usize;
+        let mut peaks = Vec::with_capacity(samples as usize * channels * 2);
+        for bucket in 0..samples as u64 {
+            let from = (bucket * self.samples as u64 / samples as u64) as usize;
+            let to = ((bucket + 1) * self.samples as u64 / samples as u64) as usize;
+            let merged = &self.peaks[from * channels * 2..to * channels * 2];
+            for c in 0..channels {
+                let bucket_peaks = merged.chunks_exact(channels * 2);
+                peaks.push(bucket_peaks.clone().map(|b| b[c * 2]).min().unwrap_or(0));
+                peaks.push(bucket_peaks.map(|b| b[c * 2 + 1]).max().unwrap_or(0));
+            }
+        }
+        WaveformPeaks {
+            channels: self.channels,
+            samples,
+            peaks,
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<WaveformPeaks> {
+        if bytes.len() < 6 || !bytes.len().is_multiple_of(2) {
+            return Err(anyhow!("[ERROR] Invalid cached waveform"));
+        }
+        Ok(WaveformPeaks {
+            channels: u16::from_le_bytes([bytes[0], bytes[1]]),
+            samples: u32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]),
+            peaks: bytes[6..]
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                .collect(),
+        })
+    }
+}
+
+/// Peaks of a single channel, as values between -1.0 and 1.0.
+struct ChannelPeaks {
+    min: Vec<f64>,
+    max: Vec<f64>,
+}
+
+impl Serialize for ChannelPeaks {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("ChannelPeaks", 2)?;
+        state.serialize_field("min", &self.min)?;
+        state.serialize_field("max", &self.max)?;
+        state.end()
+    }
+}
+
+impl Serialize for WaveformPeaks {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        // split the interleaved peaks by channel, scaled to four decimals
+        let scale = |p: i16| (p as f64 / 32768.0 * 10000.0).round() / 10000.0;
+        let channels = self.channels as usize;
+        let peaks: Vec<ChannelPeaks> = (0..channels)
+            .map(|c| ChannelPeaks {
+                min: self
+                    .peaks
+                    .chunks_exact(channels * 2)
+                    .map(|b| scale(b[c * 2]))
+                    .collect(),
+                max: self
+                    .peaks
+                    .chunks_exact(channels * 2)
+                    .map(|b| scale(b[c * 2 + 1]))
+                    .collect(),
+            })
+            .collect();
+
+        let mut state = serializer.serialize_struct("Waveform", 3)?;
+        state.serialize_field("channels", &self.channels)?;
+        state.serialize_field("samples", &self.samples)?;
+        state.serialize_field("peaks", &peaks)?;
+        state.end()
+    }
+}
+
+/// Decodes the samples of a track from `start` up to `end` (or the end of the stream) and
+/// computes the peaks of the given number of buckets. The length of the track is taken from
+/// its runtime if the stream does not state its total number of samples.
+fn waveform_generate(
+    path: &Path,
+    start: u64,
+    end: Option<u64>,
+    runtime: i64,
+    samples: u32,
+) -> Result<WaveformPeaks> {
+    let mut decoder = FlacDecoder::open_at(path, start)?;
+    let info = decoder.metadata.stream_info.clone();
+    let end = match end {
+        Some(end) => end,
+        None if info.total_samples > 0 => info.total_samples,
+        None => start + runtime.max(1) as u64 * info.sample_rate as u64,
+    };
+    let length = end.saturating_sub(start).max(1);
+    let channels = info.channels as usize;
+
+    let mut peaks = vec![0i16; samples as usize * channels * 2];
+    let mut seen = vec![false; samples as usize];
+    while let Some((block_start, block)) = decoder.next_block()? {
+        let block_end = block_start + block.duration() as u64;
+        if block_end <= start {
+            continue;
+        }
+        if block_start >= end {
+            break;
+        }
+
+        // fold the part of the block that lies within the track into its buckets
+        let from = start.saturating_sub(block_start) as u32;
+        let to = (end.min(block_end) - block_start) as u32;
+        for i in from..to {
+            let position = block_start + i as u64 - start;
+            let bucket = ((position * samples as u64 / length) as usize).min(samples as usize - 1);
+            for c in 0..channels {
+                let sample = block.sample(c as u32, i);
+                let sample = if info.bps > 16 {
+                    sample >> (info.bps - 16)
+                } else {
+                    sample << (16 - info.bps)
+                } as i16;
+                let index = (bucket * channels + c) * 2;
+                if seen[bucket] {
+                    peaks[index] = peaks[index].min(sample);
+                    peaks[index + 1] = peaks[index + 1].max(sample);
+                } else {
+                    peaks[index] = sample;
+                    peaks[index + 1] = sample;
+                }
+            }
+            seen[bucket] = true;
+        }
+    }
+    Ok(WaveformPeaks {
+        channels: channels as u16,
+        samples,
+        peaks,
+    })
+}
+
+/// Gets the waveform of a track with the given number of buckets. Waveforms are generated at
+/// the nearest resolution, and cached in the database until the audio file is modified.
+pub async fn waveform_get(
+    track: &track::ModelEx,
+    samples: u32,
+    db: &DatabaseConnection,
+) -> Result<WaveformPeaks> {
+    let resolution = waveform_resolution(samples)
+        .ok_or(anyhow!("[ERROR] Invalid number of peaks: {}", samples))?;
+    Ok(waveform_get_resolution(track, resolution, db)
+        .await?
+        .downsample(samples))
+}
+
+/// Gets the waveform of a track at one of the resolutions, from the cache if it is up to date.
+async fn waveform_get_resolution(
+    track: &track::ModelEx,
+    samples: u32,
+    db: &DatabaseConnection,
+) -> Result<WaveformPeaks> {
+    let file = track
+        .file
+        .as_ref()
+        .ok_or(anyhow!("[ERROR] Track does not have an audio file"))?;
+    if let Some(cached) = Waveform::find_by_id((track.id, samples)).one(db).await?
+        && cached.last_modified == file.last_modified
+    {
+        return WaveformPeaks::from_bytes(&cached.peaks);
+    }
+
+    // decode the track on the blocking thread pool
+    let path = file.path.clone();
+    let start = track.start_sample.unwrap_or(0) as u64;
+    let end = track.end_sample.map(|e| e as u64);
+    let runtime = track.runtime;
+    let peaks = tokio::task::spawn_blocking(move || {
+        waveform_generate(Path::new(&path), start, end, runtime, samples)
+    })
+    .await??;
+
+    // remove the outdated waveforms of the track, and replace this one if it was generated
+    // meanwhile by another request
+    Waveform::delete_many()
+        .filter(waveform::Column::TrackId.eq(track.id))
+        .filter(waveform::Column::LastModified.ne(file.last_modified))
+        .exec(db)
+        .await?;
+    let waveform = waveform::ActiveModel {
+        track_id: Set(track.id),
+        samples: Set(samples),
+        last_modified: Set(file.last_modified),
+        peaks: Set(peaks.to_bytes()),
+    };
+    Waveform::insert(waveform)
+        .on_conflict(
+            OnConflict::columns([waveform::Column::TrackId, waveform::Column::Samples])
+                .update_columns([waveform::Column::LastModified, waveform::Column::Peaks])
+                .to_owned(),
+        )
+        .exec_without_returning(db)
+        .await?;
+    Ok(peaks)
+}
@@ -8,25 +8,47 @@ mod settings;
 use std::sync::Arc;
 
 use api::{
+    analysis::{api_get_analysis_status, api_start_analysis},
+    annotations::{
+        api_create_annotation, api_delete_annotation, api_export_annotations, api_get_annotations,
+        api_search_annotations, api_update_annotation,
+    },
     browse::{
-        api_get_album, api_get_album_list, api_get_artist, api_get_artist_list, api_get_book,
-        api_get_books, api_get_track,
+        api_get_album, api_get_album_list, api_get_artist, api_get_artist_list, api_get_authors,
+        api_get_book, api_get_book_series, api_get_book_series_detail, api_get_books,
+        api_get_lyrics_by_song_id, api_get_track,
+    },
+    koreader::{
+        api_koreader_auth, api_koreader_create_user, api_koreader_get_progress,
+        api_koreader_update_progress,
+    },
+    opds::{OpdsVersion, opds_router},
+    reading::{
+        api_create_bookmark, api_delete_bookmark, api_get_bookmarks, api_get_continue_reading,
+        api_get_reading_progress, api_save_reading_progress,
     },
-    retrieve::{api_fetch_book, api_stream_track},
+    retrieve::{
+        api_fetch_book, api_get_book_resource, api_get_book_toc, api_get_waveform, api_stream_track,
+    },
+    scanning::{api_get_scan_errors, api_get_scan_status, api_start_scan},
     shelf::{
         api_create_playlist, api_delete_playlist, api_get_playlist, api_get_playlists,
         api_get_starred, api_star, api_unstar, api_update_playlist,
     },
     system::{api_get_license, api_ping},
-    upload::api_upload_artist_picture,
+    tags::{
+        api_bulk_edit_tags, api_revert_bulk_edit, api_update_album_tags, api_update_track_tags,
+    },
+    upload::{api_upload_album_picture, api_upload_artist_picture},
     users::api_create_user,
+    verification::{api_get_verify_report, api_get_verify_status, api_start_verify},
 };
-use auth::middleware::auth_middleware;
+use auth::middleware::{auth_basic_middleware, auth_middleware};
 use axum::{
     Router, middleware,
-    routing::{get, post},
+    routing::{get, post, put},
 };
-use library::scanner::scan;
+use library::{analyzer::Analyzer, scanner::Scanner, verifier::Verifier, watcher::watch};
 use sea_orm::{Database, DatabaseConnection};
 use settings::Settings;
 use tower_http::cors::CorsLayer;
@@ -35,9 +57,24 @@ use tower_http::cors::CorsLayer;
 struct AppState {
     settings: Arc<Settings>,
     db: Arc<DatabaseConnection>,
+    scanner: Arc<Scanner>,
+    verifier: Arc<Verifier>,
+    analyzer: Arc<Analyzer>,
 }
 
-const ADMIN_PATHS: [&str; 1] = ["/rest/uploadArtistPicture"];
+const ADMIN_PATHS: [&str; 11] = [
+    "/rest/uploadArtistPicture",
+    "/rest/uploadAlbumPicture",
+    "/rest/updateTrackTags",
+    "/rest/updateAlbumTags",
+    "/rest/bulkEditTags",
+    "/rest/revertBulkEdit",
+    "/rest/getScanErrors",
+    "/rest/startScan",
+    "/rest/startVerify",
+    "/rest/getVerifyReport",
+    "/rest/startAnalysis",
+];
 
 #[tokio::main]
 async fn main() {
@@ -54,28 +91,69 @@ async fn main() {
         .await
         .expect("[FATAL] Failed to get schema registry");
 
-    let _ = scan(&settings.library.path, &db).await.unwrap();
+    // scan the library in the background, then keep it up to date as configured
+    let artwork = Arc::new(settings.library.artwork.clone());
+    let scanner = Arc::new(Scanner::new(artwork.clone()));
+    if let Err(e) = scanner.start(&settings.library.path, false, &db) {
+        println!("{}", e);
+    }
+    if let Some(schedule) = &settings.library.schedule {
+        scanner
+            .schedule(schedule, &settings.library.path, &db)
+            .expect("[FATAL] Failed to schedule library scans");
+    }
+    if settings.library.watch {
+        watch(&settings.library.path, &artwork, scanner.library(), &db)
+            .expect("[FATAL] Failed to watch library");
+    }
 
     // create shared application state
-    let state = AppState { settings, db };
+    let state = AppState {
+        settings,
+        db,
+        scanner,
+        verifier: Arc::new(Verifier::default()),
+        analyzer: Arc::new(Analyzer::default()),
+    };
 
     // set up API routing and serve
     let router = Router::new()
         // SYSTEM
         .route("/rest/ping", get(api_ping))
         .route("/rest/getLicense", get(api_get_license))
+        // LIBRARY SCANNING
+        .route("/rest/startScan", get(api_start_scan))
+        .route("/rest/getScanStatus", get(api_get_scan_status))
+        .route("/rest/getScanErrors", get(api_get_scan_errors))
+        .route("/rest/startVerify", get(api_start_verify))
+        .route("/rest/getVerifyStatus", get(api_get_verify_status))
+        .route("/rest/getVerifyReport", get(api_get_verify_report))
+        .route("/rest/startAnalysis", get(api_start_analysis))
+        .route("/rest/getAnalysisStatus", get(api_get_analysis_status))
         // MUSIC LIBRARY
         .route("/rest/getAlbumList", get(api_get_album_list))
         .route("/rest/getArtistList", get(api_get_artist_list))
         .route("/rest/getArtist", get(api_get_artist))
         .route("/rest/uploadArtistPicture", post(api_upload_artist_picture))
+        .route("/rest/uploadAlbumPicture", post(api_upload_album_picture))
+        .route("/rest/updateTrackTags", post(api_update_track_tags))
+        .route("/rest/updateAlbumTags", post(api_update_album_tags))
+        .route("/rest/bulkEditTags", post(api_bulk_edit_tags))
+        .route("/rest/revertBulkEdit", get(api_revert_bulk_edit))
         .route("/rest/getAlbum", get(api_get_album))
         .route("/rest/getTrack", get(api_get_track))
+        .route("/rest/getLyricsBySongId", get(api_get_lyrics_by_song_id))
         .route("/rest/streamTrack", get(api_stream_track))
+        .route("/rest/getWaveform", get(api_get_waveform))
         // BOOK LIBRARY
         .route("/rest/getBooks", get(api_get_books))
         .route("/rest/getBook", get(api_get_book))
+        .route("/rest/getBookSeries", get(api_get_book_series))
+        .route("/rest/getBookSeriesDetail", get(api_get_book_series_detail))
+        .route("/rest/getAuthors", get(api_get_authors))
         .route("/rest/fetchBook", get(api_fetch_book))
+        .route("/rest/getBookToc", get(api_get_book_toc))
+        .route("/rest/getBookResource", get(api_get_book_resource))
         // SHELF
         .route("/rest/getPlaylists", get(api_get_playlists))
         .route("/rest/getPlaylist", get(api_get_playlist))
@@ -85,11 +163,43 @@ async fn main() {
         .route("/rest/star", get(api_star))
         .route("/rest/unstar", get(api_unstar))
         .route("/rest/getStarred", get(api_get_starred))
+        .route("/rest/saveReadingProgress", get(api_save_reading_progress))
+        .route("/rest/getReadingProgress", get(api_get_reading_progress))
+        .route("/rest/getContinueReading", get(api_get_continue_reading))
+        .route("/rest/createBookmark", get(api_create_bookmark))
+        .route("/rest/getBookmarks", get(api_get_bookmarks))
+        .route("/rest/deleteBookmark", get(api_delete_bookmark))
+        .route("/rest/createAnnotation", get(api_create_annotation))
+        .route("/rest/updateAnnotation", get(api_update_annotation))
+        .route("/rest/deleteAnnotation", get(api_delete_annotation))
+        .route("/rest/getAnnotations", get(api_get_annotations))
+        .route("/rest/searchAnnotations", get(api_search_annotations))
+        .route("/rest/exportAnnotations", get(api_export_annotations))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
         ))
         .route("/rest/createUser", get(api_create_user))
+        // KOREADER SYNC
+        .route("/users/create", post(api_koreader_create_user))
+        .route("/users/auth", get(api_koreader_auth))
+        .route("/syncs/progress", put(api_koreader_update_progress))
+        .route("/syncs/progress/{document}", get(api_koreader_get_progress))
+        // OPDS CATALOG
+        .nest(
+            "/opds",
+            opds_router(OpdsVersion::Atom).route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                auth_basic_middleware,
+            )),
+        )
+        .nest(
+            "/opds/v2",
+            opds_router(OpdsVersion::Json).route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                auth_basic_middleware,
+            )),
+        )
         .layer(CorsLayer::permissive())
         .with_state(state);
     let listener = tokio::net::TcpListener::bind(&host_address)
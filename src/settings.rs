@@ -25,6 +25,48 @@ pub struct DatabaseConfig {
 #[derive(Debug, Deserialize)]
 pub struct LibraryConfig {
     pub path: String,
+    pub schedule: Option<String>,
+    #[serde(default)]
+    pub watch: bool,
+    #[serde(default)]
+    pub artwork: ArtworkConfig,
+}
+
+/// Whether pictures embedded in audio files or picture files next to them are used first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtworkPrecedence {
+    #[default]
+    Embedded,
+    Sidecar,
+}
+
+/// Picture files that are looked for next to audio files, by priority. Cover files are looked
+/// for in the directory of the album and artist files in the directory above it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ArtworkConfig {
+    pub cover_files: Vec<String>,
+    pub artist_files: Vec<String>,
+    pub precedence: ArtworkPrecedence,
+}
+
+impl Default for ArtworkConfig {
+    fn default() -> Self {
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        ArtworkConfig {
+            cover_files: names(&[
+                "cover.jpg",
+                "cover.png",
+                "folder.jpg",
+                "folder.png",
+                "front.jpg",
+                "front.png",
+            ]),
+            artist_files: names(&["artist.jpg", "artist.png"]),
+            precedence: ArtworkPrecedence::Embedded,
+        }
+    }
 }
 
 impl Settings {